@@ -0,0 +1,71 @@
+// Compares the plain interpreter with the decode cache on the test ROMs, run headless
+// as fast as possible. cargo bench --bench interpreter
+// With --features jit the JIT is timed as well.
+use chip8_rs::chip8::Chip8;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const ROMS: [&str; 4] = ["opcode-checks", "flag-checks", "quirk-checks", "ibm-logo"];
+const FRAMES: u32 = 2_000;
+const CYCLES_PER_FRAME: u32 = 1_000;
+
+// A counting loop that never draws or waits, so every cycle is fetch/decode/execute
+const BUSY_LOOP: [u8; 12] = [
+    0x60, 0x00, // V0 = 0
+    0x70, 0x01, // V0 += 1
+    0x81, 0x04, // V1 += V0
+    0x40, 0xFF, // skip unless V0 == 0xFF
+    0x12, 0x02, // loop
+    0x12, 0x00, // start over
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Plain,
+    Cached,
+    #[cfg(feature = "jit")]
+    Jit
+}
+
+fn time(rom: &[u8], mode: Mode) -> Duration {
+    let mut machine_state = Chip8::new();
+    machine_state.set_decode_cache(mode == Mode::Cached);
+    #[cfg(feature = "jit")]
+    assert!(machine_state.set_jit(mode == Mode::Jit));
+    machine_state.set_cycles_per_frame(CYCLES_PER_FRAME);
+    machine_state.load_fonts();
+    machine_state.load_rom(rom);
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        machine_state.emulate_frame();
+    }
+    start.elapsed()
+}
+
+fn report(name: &str, rom: &[u8]) {
+    let cycles = (FRAMES * CYCLES_PER_FRAME) as f64;
+    let plain = time(rom, Mode::Plain);
+    let cached = time(rom, Mode::Cached);
+    print!("{:<13} plain {:>7.1} Mcycles/s   cached {:>7.1} Mcycles/s   {:.2}x",
+        name,
+        cycles / plain.as_secs_f64() / 1e6,
+        cycles / cached.as_secs_f64() / 1e6,
+        plain.as_secs_f64() / cached.as_secs_f64());
+    #[cfg(feature = "jit")]
+    {
+        let jit = time(rom, Mode::Jit);
+        print!("   jit {:>7.1} Mcycles/s   {:.2}x",
+            cycles / jit.as_secs_f64() / 1e6,
+            plain.as_secs_f64() / jit.as_secs_f64());
+    }
+    println!();
+}
+
+fn main() {
+    let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("roms");
+    report("busy loop", &BUSY_LOOP);
+    for name in ROMS {
+        report(name, &std::fs::read(roms.join(format!("{}.ch8", name))).unwrap());
+    }
+}
@@ -0,0 +1,15 @@
+// Save states come from disk, so load_state must reject anything it can't run
+#![no_main]
+use chip8_rs::chip8::Chip8;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut machine_state = Chip8::new();
+    if machine_state.load_state(data).is_ok() {
+        for k in 0..16 {
+            machine_state.set_key(k, true);
+            machine_state.set_key(k, false);
+        }
+        machine_state.emulate_frame();
+    }
+});
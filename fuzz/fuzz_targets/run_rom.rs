@@ -0,0 +1,55 @@
+// Runs an arbitrary ROM under arbitrary quirks with a key event per frame, then
+// round-trips a save state. Any panic is a bug.
+#![no_main]
+use arbitrary::Arbitrary;
+use chip8_rs::chip8::{Chip8, Quirks, STACK_SIZE, STATE_SIZE};
+use libfuzzer_sys::fuzz_target;
+
+const FRAMES: usize = 64;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    flags: u16, // one bit per boolean quirk
+    stack_depth: u8,
+    cycles_per_frame: u8,
+    keys: Vec<(u8, bool)>,
+    rom: Vec<u8>,
+}
+
+fn quirks(input: &Input) -> Quirks {
+    let flag = |bit: u16| input.flags & 1 << bit != 0;
+    Quirks {
+        vf_reset: flag(0),
+        load_store_increments_i: flag(1),
+        shift_uses_vy: flag(2),
+        jump_uses_vx: flag(3),
+        display_wait: flag(4),
+        clip_sprites: flag(5),
+        key_wait_release: flag(6),
+        stack_depth: input.stack_depth as usize % (STACK_SIZE + 1),
+        stack_in_memory: flag(7),
+        halt_on_stack_fault: flag(8)
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let mut machine_state = Chip8::new();
+    machine_state.set_quirks(quirks(&input));
+    machine_state.set_cycles_per_frame(input.cycles_per_frame as u32 + 1);
+    machine_state.load_fonts();
+    machine_state.load_rom(&input.rom);
+    let mut events = input.keys.iter();
+    for _ in 0..FRAMES {
+        if let Some(&(key, pressed)) = events.next() {
+            machine_state.set_key((key & 0xF) as usize, pressed);
+        }
+        machine_state.emulate_frame();
+    }
+
+    let mut state = vec![0; STATE_SIZE];
+    machine_state.save_state(&mut state).unwrap();
+    let mut restored = Chip8::new();
+    restored.set_quirks(quirks(&input));
+    restored.load_state(&state).unwrap();
+    restored.emulate_frame();
+});
@@ -0,0 +1,299 @@
+// Static analysis of a ROM: which bytes are reachable code, the control-flow graph between
+// its basic blocks, and what the remaining bytes are probably for. Control flow follows
+// execute_opcode: a skip may or may not be taken, a call comes back to the instruction
+// after it, 0NNN is ignored, and BNNN goes somewhere that depends on a register, so it
+// is reported but not followed. I is tracked through ANNN to find sprites, data and
+// stores into code; once it is computed (FX1E, FX29, joins of different values) the
+// bytes it points at can't be told apart from unreachable ones
+use crate::chip8::{Quirks, MAX_ROM_SIZE};
+use crate::instruction::Instruction;
+use crate::symbols::Symbols;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+const START: u16 = 0x200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    Next, // falls through, including past a skip that isn't taken
+    Skip, // over the next instruction, when a skip is taken
+    Jump,
+    Call,
+    Return, // from a call to the instruction after it
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    pub target: u16
+}
+
+// Straight-line code from start up to end (exclusive), only ever entered at start
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start: u16,
+    pub end: u16,
+    pub opcodes: Vec<u16>,
+    // Empty after 00EE, BNNN, an undefined opcode (which runs no further), or the end of the ROM
+    pub edges: Vec<Edge>
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RegionKind {
+    Code,
+    Sprite, // drawn by DXYN
+    Data, // loaded by FX65 or stored to by FX55/FX33
+    Unreachable, // dead code, padding, or data found through a computed I
+}
+
+// Bytes start..end of the ROM's address space, all of the same kind
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub start: u16,
+    pub end: u16
+}
+
+// An FX55 or FX33 at address that stores over bytes start..end, some of which are code
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SelfModifyingWrite {
+    pub address: u16,
+    pub start: u16,
+    pub end: u16
+}
+
+// A BNNN at address, jumping to base + V(register)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComputedJump {
+    pub address: u16,
+    pub base: u16,
+    pub register: u8
+}
+
+#[derive(Clone, Debug)]
+pub struct Analysis {
+    pub blocks: BTreeMap<u16, Block>,
+    // Covers the whole ROM, in order
+    pub regions: Vec<Region>,
+    pub self_modifying: Vec<SelfModifyingWrite>,
+    pub computed_jumps: Vec<ComputedJump>,
+    // Reachable opcodes that don't decode. execute_opcode does nothing for them and leaves
+    // pc where it is, so a program that gets to one hangs there without a fault
+    pub undefined: Vec<u16>
+}
+
+// What is known about I when an instruction starts
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Index {
+    Known(u16),
+    Unknown
+}
+
+pub fn analyze(rom: &[u8], quirks: &Quirks) -> Analysis {
+    let rom = &rom[..rom.len().min(MAX_ROM_SIZE)];
+    let end = START + rom.len() as u16;
+    let in_rom = |address: u16| (START..end).contains(&address);
+    // Past the end of the ROM memory is zero, as after load_rom
+    let byte = |address: u16| if in_rom(address) { rom[(address - START) as usize] } else { 0 };
+    let fetch = |address: u16| u16::from_be_bytes([byte(address), byte(address + 1)]);
+
+    // Every reachable instruction and where it can go next
+    let mut reachable: BTreeMap<u16, Vec<Edge>> = BTreeMap::new();
+    let mut computed_jumps = Vec::new();
+    let mut undefined = Vec::new();
+    let mut pending = if rom.is_empty() { vec![] } else { vec![START] };
+    while let Some(address) = pending.pop() {
+        if reachable.contains_key(&address) || !in_rom(address) {
+            continue;
+        }
+        let next = address + 2;
+        let edge = |kind, target| Edge { kind, target };
+        let edges = match Instruction::decode(fetch(address)) {
+            Ok(Instruction::Jump { addr }) => vec![edge(EdgeKind::Jump, addr)],
+            Ok(Instruction::Call { addr }) => vec![edge(EdgeKind::Call, addr), edge(EdgeKind::Return, next)],
+            Ok(Instruction::Rts) => vec![],
+            Ok(Instruction::Jump0 { addr }) => {
+                let register = if quirks.jump_uses_vx { (addr >> 8) as u8 } else { 0 };
+                computed_jumps.push(ComputedJump { address, base: addr, register });
+                vec![]
+            },
+            Ok(Instruction::Ske { .. }) | Ok(Instruction::Skne { .. }) | Ok(Instruction::Skre { .. }) |
+            Ok(Instruction::Skrne { .. }) | Ok(Instruction::Skpr { .. }) | Ok(Instruction::Skup { .. }) => {
+                vec![edge(EdgeKind::Next, next), edge(EdgeKind::Skip, next + 2)]
+            },
+            Ok(_) => vec![edge(EdgeKind::Next, next)],
+            Err(_) => {
+                undefined.push(address);
+                vec![]
+            }
+        };
+        pending.extend(edges.iter().map(|edge| edge.target));
+        reachable.insert(address, edges);
+    }
+
+    // A block starts at the entry point, at any target other than plain fall-through,
+    // and after an instruction that doesn't simply fall through
+    let falls_through = |edges: &[Edge]| edges.len() == 1 && edges[0].kind == EdgeKind::Next;
+    let mut leaders: BTreeSet<u16> = BTreeSet::new();
+    leaders.insert(START);
+    for edges in reachable.values() {
+        for edge in edges.iter().filter(|_| !falls_through(edges)) {
+            leaders.insert(edge.target);
+        }
+    }
+    let mut blocks = BTreeMap::new();
+    for &start in leaders.iter().filter(|&address| reachable.contains_key(address)) {
+        let mut address = start;
+        let mut opcodes = vec![];
+        loop {
+            opcodes.push(fetch(address));
+            let edges = &reachable[&address];
+            let next = address + 2;
+            if falls_through(edges) && reachable.contains_key(&next) && !leaders.contains(&next) {
+                address = next;
+            } else {
+                blocks.insert(start, Block { start, end: next, opcodes, edges: edges.clone() });
+                break;
+            }
+        }
+    }
+
+    // I on entry to each instruction, joined over every way of getting there
+    let mut index: BTreeMap<u16, Index> = BTreeMap::new();
+    let mut pending = vec![(START, Index::Known(0))];
+    while let Some((address, value)) = pending.pop() {
+        let Some(edges) = reachable.get(&address) else { continue };
+        let value = match index.get(&address) {
+            None => value,
+            Some(&old) if old == value => continue,
+            Some(_) => Index::Unknown
+        };
+        if index.insert(address, value) == Some(value) {
+            continue;
+        }
+        let after = match (Instruction::decode(fetch(address)), value) {
+            (Ok(Instruction::Loadi { addr }), _) => Index::Known(addr),
+            (Ok(Instruction::Addi { .. }), _) | (Ok(Instruction::Ldspr { .. }), _) => Index::Unknown,
+            (Ok(Instruction::Stor { x }), Index::Known(i)) | (Ok(Instruction::Read { x }), Index::Known(i))
+                if quirks.load_store_increments_i => Index::Known((i + x as u16 + 1) & 0xFFF),
+            _ => value
+        };
+        for edge in edges {
+            // The subroutine may have changed I by the time it returns
+            let value = if edge.kind == EdgeKind::Return { Index::Unknown } else { after };
+            pending.push((edge.target, value));
+        }
+    }
+
+    let code: BTreeSet<u16> = reachable.keys().flat_map(|&address| [address, address + 1]).collect();
+    let mut sprites = BTreeSet::new();
+    let mut data = BTreeSet::new();
+    let mut self_modifying = Vec::new();
+    for (&address, &value) in &index {
+        let Index::Known(i) = value else { continue };
+        let (set, length, stores) = match Instruction::decode(fetch(address)) {
+            Ok(Instruction::Draw { n, .. }) => (&mut sprites, n as u16, false),
+            Ok(Instruction::Read { x }) => (&mut data, x as u16 + 1, false),
+            Ok(Instruction::Stor { x }) => (&mut data, x as u16 + 1, true),
+            Ok(Instruction::Bcd { .. }) => (&mut data, 3, true),
+            _ => continue
+        };
+        set.extend(i..i + length);
+        if stores && (i..i + length).any(|byte| code.contains(&byte)) {
+            self_modifying.push(SelfModifyingWrite { address, start: i, end: i + length });
+        }
+    }
+
+    let kind = |address| if code.contains(&address) {
+        RegionKind::Code
+    } else if sprites.contains(&address) {
+        RegionKind::Sprite
+    } else if data.contains(&address) {
+        RegionKind::Data
+    } else {
+        RegionKind::Unreachable
+    };
+    let mut regions: Vec<Region> = Vec::new();
+    for address in START..end {
+        let kind = kind(address);
+        match regions.last_mut() {
+            Some(region) if region.kind == kind => region.end = address + 1,
+            _ => regions.push(Region { kind, start: address, end: address + 1 })
+        }
+    }
+
+    computed_jumps.sort_by_key(|jump| jump.address);
+    undefined.sort_unstable();
+    Analysis { blocks, regions, self_modifying, computed_jumps, undefined }
+}
+
+impl Analysis {
+    pub fn region_at(&self, address: u16) -> Option<RegionKind> {
+        self.regions.iter().find(|region| (region.start..region.end).contains(&address)).map(|region| region.kind)
+    }
+
+    // One finding per line: the regions, then anything unusual about the code
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let code: usize = self.blocks.values().map(|block| block.opcodes.len() * 2).sum();
+        let mut out = format!("{} blocks, {} bytes of code\n", self.blocks.len(), code);
+        for region in &self.regions {
+            let kind = match region.kind {
+                RegionKind::Code => "code",
+                RegionKind::Sprite => "sprite",
+                RegionKind::Data => "data",
+                RegionKind::Unreachable => "unreachable",
+            };
+            let _ = writeln!(out, "{:<12}{} to {}", kind, symbols.describe(region.start), symbols.describe(region.end - 1));
+        }
+        for write in &self.self_modifying {
+            let _ = writeln!(out, "self-modifying write at {} to {:#05x}-{:#05x}", symbols.describe(write.address), write.start, write.end - 1);
+        }
+        for jump in &self.computed_jumps {
+            let _ = writeln!(out, "computed jump at {} to {:#05x} + V{:X}", symbols.describe(jump.address), jump.base, jump.register);
+        }
+        for &address in &self.undefined {
+            let _ = writeln!(out, "undefined opcode at {}, where the program would hang", symbols.describe(address));
+        }
+        out
+    }
+
+    // The control-flow graph in Graphviz format: one node per block with its disassembly,
+    // and dashed edges for calls, dotted ones for returns
+    pub fn dot(&self, symbols: &Symbols) -> String {
+        let mut out = String::from("digraph rom {\n    node [shape=box fontname=monospace];\n");
+        for block in self.blocks.values() {
+            let mut label = format!("{}\\l", escape(&symbols.describe(block.start)));
+            for (k, &opcode) in block.opcodes.iter().enumerate() {
+                let text = match Instruction::decode(opcode) {
+                    Ok(instruction) => instruction.to_string(),
+                    Err(e) => e.to_string()
+                };
+                let _ = write!(label, "{:03X}  {:04X}  {}\\l", block.start as usize + 2 * k, opcode, text);
+            }
+            let _ = writeln!(out, "    \"{:#05x}\" [label=\"{}\"];", block.start, label);
+            for edge in &block.edges {
+                if !self.blocks.contains_key(&edge.target) {
+                    let _ = writeln!(out, "    \"{:#05x}\" [shape=plaintext label=\"{:#05x}\\n(outside the ROM)\"];", edge.target, edge.target);
+                }
+                let style = match edge.kind {
+                    EdgeKind::Next | EdgeKind::Jump => "",
+                    EdgeKind::Skip => " [label=\"skip\"]",
+                    EdgeKind::Call => " [label=\"call\" style=dashed]",
+                    EdgeKind::Return => " [label=\"return\" style=dotted]",
+                };
+                let _ = writeln!(out, "    \"{:#05x}\" -> \"{:#05x}\"{};", block.start, edge.target, style);
+            }
+        }
+        for jump in &self.computed_jumps {
+            let block = self.blocks.range(..=jump.address).next_back().map_or(jump.address, |(&start, _)| start);
+            let _ = writeln!(out, "    \"bnnn {:#05x}\" [shape=diamond label=\"{:#05x} + V{:X}\"];", jump.address, jump.base, jump.register);
+            let _ = writeln!(out, "    \"{:#05x}\" -> \"bnnn {:#05x}\" [style=dashed];", block, jump.address);
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
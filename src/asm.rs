@@ -0,0 +1,813 @@
+// An assembler for Octo, the CHIP-8 assembly language of the Octo IDE. The output is
+// loaded at 0x200, ready for Chip8::load_rom.
+//
+// Supported: labels (`: name`, `:next name`), `:const`, `:alias`, `:calc`, `:byte`, `:org`,
+// `:macro`, `:unpack`, all the CHIP-8 statements, `if ... then`,
+// `if ... begin ... else ... end` and `loop ... while ... again`. SUPER-CHIP and XO-CHIP
+// statements are rejected, since Chip8 can't run them.
+//
+// As in Octo, the program starts with a jump to the `main` label, comparisons with < > <=
+// and >= use VF as a scratch register, and `:calc` evaluates right to left with no
+// operator precedence: { 2 * 3 + 1 } is 8.
+use crate::symbols::Symbols;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::path::Path;
+
+const START: usize = 0x200;
+const MEMORY_SIZE: usize = 4096;
+// Macros that keep expanding into themselves give up here
+const MAX_EXPANSIONS: usize = 100_000;
+
+const UNSUPPORTED: [&str; 13] = [
+    "hires", "lores", "scroll-down", "scroll-up", "scroll-left", "scroll-right", "exit",
+    "saveflags", "loadflags", "plane", "audio", "pitch", "long"
+];
+
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    pub line: usize, // from 1
+    pub column: usize, // from 1
+    pub message: String
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+pub struct Program {
+    pub rom: Vec<u8>, // from 0x200
+    pub labels: BTreeMap<String, u16>
+}
+
+impl Program {
+    pub fn symbols(&self) -> Symbols {
+        Symbols::new(&self.labels)
+    }
+}
+
+// Reads a program to run: .8o files are assembled, as is the source in an Octo cartridge
+// .gif, anything else is taken as a ROM (with no labels). Errors start with the path,
+// assembly errors go on with line:column
+pub fn load_program(path: &Path) -> Result<Program, String> {
+    #[cfg(feature = "cartridge")]
+    if crate::cartridge::is_cartridge(path) {
+        return crate::cartridge::load(path).map(|cartridge| cartridge.program);
+    }
+    if path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("8o")) {
+        let source = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        assemble(&source).map_err(|e| format!("{}:{}", path.display(), e))
+    } else {
+        let rom = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Program { rom, labels: BTreeMap::new() })
+    }
+}
+
+pub fn assemble(source: &str) -> Result<Program, AsmError> {
+    let mut assembler = Assembler::new(source);
+    while let Some(token) = assembler.tokens.pop_front() {
+        assembler.statement(token)?;
+    }
+    assembler.finish()
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize
+}
+
+impl Token {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, AsmError> {
+        Err(AsmError { line: self.line, column: self.column, message: message.into() })
+    }
+
+    fn is(&self, text: &str) -> bool {
+        self.text == text
+    }
+}
+
+// Words are separated by whitespace, braces and parentheses are words of their own,
+// and # starts a comment
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let mut word: Option<Token> = None;
+        for (column, c) in line.chars().enumerate() {
+            let token = |text: String| Token { text, line: index + 1, column: column + 1 };
+            if c == '#' && word.is_none() {
+                break;
+            } else if c.is_whitespace() {
+                tokens.extend(word.take());
+            } else if "{}()".contains(c) {
+                tokens.extend(word.take());
+                tokens.push(token(c.to_string()));
+            } else {
+                match word.as_mut() {
+                    Some(word) => word.text.push(c),
+                    None => word = Some(token(c.to_string()))
+                }
+            }
+        }
+        tokens.extend(word.take());
+    }
+    tokens
+}
+
+// Decimal, 0x hex or 0b binary, optionally negative
+fn parse_number(text: &str) -> Option<f64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text)
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value } as f64)
+}
+
+fn parse_register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v'), Some(digit), None) | (Some('V'), Some(digit), None) => digit.to_digit(16).map(|x| x as u8),
+        _ => None
+    }
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>
+}
+
+// A reference to a label that wasn't defined yet when it was used
+enum FixupKind {
+    Address, // the low 12 bits of the instruction at the fixup
+    Unpack // high nibble into the 6XNN at the fixup, low byte into the one after
+}
+
+struct Fixup {
+    name: Token,
+    at: usize,
+    kind: FixupKind
+}
+
+// Open control structures, with the jumps waiting for their target
+enum Flow {
+    If { token: Token, jump: usize }, // jump skips the if body
+    Else { token: Token, jump: usize }, // jump skips the else body
+    Loop { token: Token, start: usize, exits: Vec<usize> } // exits are the whiles' jumps
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    end: Token, // where errors about a missing word point
+    image: Vec<u8>, // all of memory
+    pc: usize,
+    size: usize, // just past the highest byte written
+    names: HashMap<String, f64>, // constants and labels
+    labels: BTreeMap<String, u16>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    flow: Vec<Flow>,
+    next_label: Option<Token>, // :next, set at the next instruction
+    expansions: usize
+}
+
+impl Assembler {
+    fn new(source: &str) -> Assembler {
+        let end = Token { text: String::new(), line: source.lines().count().max(1), column: 1 };
+        Assembler {
+            tokens: tokenize(source).into(),
+            end,
+            image: vec![0; MEMORY_SIZE],
+            pc: START + 2, // after the jump to main
+            size: START + 2,
+            names: HashMap::new(),
+            labels: BTreeMap::new(),
+            aliases: HashMap::new(),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            flow: Vec::new(),
+            next_label: None,
+            expansions: 0
+        }
+    }
+
+    fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                self.define_label(&name, self.pc)?;
+            },
+            ":next" => self.next_label = Some(self.name()?),
+            ":const" => {
+                let name = self.name()?;
+                let value = self.next()?;
+                let value = self.value(&value)?;
+                self.define(&name, value)?;
+            },
+            ":alias" => {
+                let name = self.name()?;
+                let register = self.next()?;
+                let x = self.register(&register)?;
+                self.aliases.insert(name.text, x);
+            },
+            ":calc" => {
+                let name = self.name()?;
+                let value = self.calc()?;
+                self.define(&name, value)?;
+            },
+            ":byte" => {
+                let value = self.next()?;
+                let byte = if value.is("{") {
+                    self.tokens.push_front(value.clone());
+                    let result = self.calc()?;
+                    to_byte(result, &value)?
+                } else {
+                    self.byte(&value)?
+                };
+                self.emit_byte(byte, &value)?;
+            },
+            ":org" => {
+                let address = self.next()?;
+                let value = self.value(&address)?.floor();
+                if !(START as f64..MEMORY_SIZE as f64).contains(&value) {
+                    return address.error(":org needs an address from 0x200 to 0xFFF");
+                }
+                self.pc = value as usize;
+            },
+            ":macro" => self.define_macro()?,
+            ":unpack" => {
+                let nibble = self.next()?;
+                let nibble = self.value(&nibble)
+                    .and_then(|value| to_nibble(value, &nibble))?;
+                let label = self.next()?;
+                let at = self.pc;
+                let address = self.address(&label, at, FixupKind::Unpack)?;
+                self.emit(0x6000 | (nibble as u16) << 4 | address >> 8, &token)?;
+                self.emit(0x6100 | (address & 0xFF), &token)?;
+            },
+            ":breakpoint" => {
+                self.name()?;
+            },
+            ":monitor" => {
+                self.next()?;
+                self.next()?;
+            },
+            ";" | "return" => self.emit(0x00EE, &token)?,
+            "clear" => self.emit(0x00E0, &token)?,
+            "bcd" => self.register_op(0xF033, &token)?,
+            "save" => self.register_op(0xF055, &token)?,
+            "load" => self.register_op(0xF065, &token)?,
+            "sprite" => {
+                let x = self.next_register()?;
+                let y = self.next_register()?;
+                let n = self.next()?;
+                let rows = self.value(&n).and_then(|value| to_nibble(value, &n))?;
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | rows as u16, &token)?;
+            },
+            "jump" => self.address_op(0x1000, &token)?,
+            "jump0" => self.address_op(0xB000, &token)?,
+            "native" => self.address_op(0x0000, &token)?,
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.next_register()?;
+                let op = if token.is("delay") { 0xF015 } else { 0xF018 };
+                self.emit(op | (x as u16) << 8, &token)?;
+            },
+            "i" => {
+                let op = self.next()?;
+                match op.text.as_str() {
+                    ":=" => {
+                        let target = self.next()?;
+                        if target.is("hex") {
+                            self.register_op(0xF029, &token)?;
+                        } else if UNSUPPORTED.contains(&target.text.as_str()) {
+                            return unsupported(&target);
+                        } else {
+                            let at = self.pc;
+                            let address = self.address(&target, at, FixupKind::Address)?;
+                            self.emit(0xA000 | address, &token)?;
+                        }
+                    },
+                    "+=" => self.register_op(0xF01E, &token)?,
+                    _ => return op.error(format!("expected := or += after i, found {}", op.text))
+                }
+            },
+            "if" => {
+                let mut test = self.condition()?;
+                let next = self.next()?;
+                match next.text.as_str() {
+                    "then" => {
+                        for op in test {
+                            self.emit(op, &token)?;
+                        }
+                    },
+                    "begin" => {
+                        // Skip the jump past the body when the condition holds
+                        invert_last(&mut test);
+                        for op in test {
+                            self.emit(op, &token)?;
+                        }
+                        let jump = self.pc;
+                        self.emit(0x1000, &token)?;
+                        self.flow.push(Flow::If { token, jump });
+                    },
+                    _ => return next.error(format!("expected then or begin, found {}", next.text))
+                }
+            },
+            "else" => match self.flow.pop() {
+                Some(Flow::If { jump, .. }) => {
+                    let skip = self.pc;
+                    self.emit(0x1000, &token)?;
+                    self.patch_jump(jump, &token)?;
+                    self.flow.push(Flow::Else { token, jump: skip });
+                },
+                _ => return token.error("else without if ... begin")
+            },
+            "end" => match self.flow.pop() {
+                Some(Flow::If { jump, .. }) | Some(Flow::Else { jump, .. }) => self.patch_jump(jump, &token)?,
+                _ => return token.error("end without if ... begin")
+            },
+            "loop" => self.flow.push(Flow::Loop { token, start: self.pc, exits: Vec::new() }),
+            "while" => {
+                let mut test = self.condition()?;
+                invert_last(&mut test);
+                for op in test {
+                    self.emit(op, &token)?;
+                }
+                let jump = self.pc;
+                self.emit(0x1000, &token)?;
+                match self.flow.iter_mut().rev().find(|flow| matches!(flow, Flow::Loop { .. })) {
+                    Some(Flow::Loop { exits, .. }) => exits.push(jump),
+                    _ => return token.error("while outside of loop ... again")
+                }
+            },
+            "again" => match self.flow.pop() {
+                Some(Flow::Loop { start, exits, .. }) => {
+                    self.emit(0x1000 | start as u16, &token)?;
+                    for jump in exits {
+                        self.patch_jump(jump, &token)?;
+                    }
+                },
+                _ => return token.error("again without loop")
+            },
+            text if UNSUPPORTED.contains(&text) => return unsupported(&token),
+            text if self.macros.contains_key(text) => self.expand(token)?,
+            _ => {
+                if let Ok(x) = self.register(&token) {
+                    self.register_statement(x, &token)?;
+                } else if let Some(value) = parse_number(&token.text) {
+                    let byte = to_byte(value, &token)?;
+                    self.emit_byte(byte, &token)?;
+                } else if token.text.starts_with(':') {
+                    return token.error(format!("unknown directive {}", token.text));
+                } else {
+                    // A bare name calls the subroutine at that label
+                    let at = self.pc;
+                    let address = self.address(&token, at, FixupKind::Address)?;
+                    self.emit(0x2000 | address, &token)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // vx := ..., vx += ... and the other operations on a register
+    fn register_statement(&mut self, x: u8, token: &Token) -> Result<(), AsmError> {
+        let x = (x as u16) << 8;
+        let op = self.next()?;
+        let rhs = self.next()?;
+        let register = self.register(&rhs).ok().map(|y| (y as u16) << 4);
+        let opcode = match (op.text.as_str(), register) {
+            (":=", Some(y)) => 0x8000 | x | y,
+            (":=", None) => match rhs.text.as_str() {
+                "delay" => 0xF007 | x,
+                "key" => 0xF00A | x,
+                "random" => {
+                    let mask = self.next()?;
+                    0xC000 | x | self.byte(&mask)? as u16
+                },
+                _ => 0x6000 | x | self.byte(&rhs)? as u16
+            },
+            ("+=", Some(y)) => 0x8004 | x | y,
+            ("+=", None) => 0x7000 | x | self.byte(&rhs)? as u16,
+            ("-=", Some(y)) => 0x8005 | x | y,
+            ("-=", None) => 0x7000 | x | self.byte(&rhs)?.wrapping_neg() as u16,
+            ("=-", Some(y)) => 0x8007 | x | y,
+            ("|=", Some(y)) => 0x8001 | x | y,
+            ("&=", Some(y)) => 0x8002 | x | y,
+            ("^=", Some(y)) => 0x8003 | x | y,
+            (">>=", Some(y)) => 0x8006 | x | y,
+            ("<<=", Some(y)) => 0x800E | x | y,
+            ("=-", None) | ("|=", None) | ("&=", None) | ("^=", None) | (">>=", None) | ("<<=", None) => {
+                return rhs.error(format!("{} needs a register, found {}", op.text, rhs.text));
+            },
+            _ => return op.error(format!("unknown operator {}", op.text))
+        };
+        self.emit(opcode, token)
+    }
+
+    // Parses `vx op rhs` and returns the instructions that test it, the last of which
+    // skips the next instruction if the condition is false
+    fn condition(&mut self) -> Result<Vec<u16>, AsmError> {
+        let lhs = self.next()?;
+        let x = self.register(&lhs)?;
+        let op = self.next()?;
+        let vx = (x as u16) << 8;
+        match op.text.as_str() {
+            "key" => return Ok(vec![0xE0A1 | vx]),
+            "-key" => return Ok(vec![0xE09E | vx]),
+            _ => ()
+        }
+        let rhs = self.next()?;
+        let y = self.register(&rhs).ok();
+        match op.text.as_str() {
+            "==" | "!=" => {
+                // 4XNN and 9XY0 skip unless equal, 3XNN and 5XY0 unless not equal
+                let test = match y {
+                    Some(y) => 0x9000 | vx | (y as u16) << 4,
+                    None => 0x4000 | vx | self.byte(&rhs)? as u16
+                };
+                Ok(vec![if op.is("==") { test } else { invert(test) }])
+            },
+            "<" | ">" | "<=" | ">=" => {
+                if x == 0xF {
+                    return lhs.error("vf can't be compared with < > <= or >=, they use it as scratch");
+                }
+                let load = match y {
+                    Some(y) => 0x8F00 | (y as u16) << 4,
+                    None => 0x6F00 | self.byte(&rhs)? as u16
+                };
+                // VF := vx - rhs or rhs - vx, whose flag is 1 for >= or <= respectively
+                let subtract = if op.is("<") || op.is(">=") { 0x8F07 } else { 0x8F05 } | (x as u16) << 4;
+                let flag_when_true = if op.is("<") || op.is(">") { 0 } else { 1 };
+                Ok(vec![load, subtract, 0x4F00 | flag_when_true])
+            },
+            _ => op.error(format!("unknown comparison {}", op.text))
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), AsmError> {
+        let name = self.name()?;
+        let mut params = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.is("{") {
+                break;
+            }
+            params.push(token.text);
+        }
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.tokens.pop_front().map_or_else(|| name.error("unclosed { in macro"), Ok)?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => ()
+            }
+            body.push(token);
+        }
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    // Replaces a macro call with the macro's body, arguments substituted
+    fn expand(&mut self, call: Token) -> Result<(), AsmError> {
+        self.expansions += 1;
+        if self.expansions > MAX_EXPANSIONS {
+            return call.error(format!("too many macro expansions, does {} expand into itself?", call.text));
+        }
+        let params = self.macros[&call.text].params.len();
+        let mut args = Vec::new();
+        for _ in 0..params {
+            args.push(self.next()?);
+        }
+        let def = &self.macros[&call.text];
+        let expanded: Vec<Token> = def.body.iter().map(|token| {
+            match def.params.iter().position(|param| *param == token.text) {
+                Some(index) => args[index].clone(),
+                None => token.clone()
+            }
+        }).collect();
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    // Reads { expression } and evaluates it
+    fn calc(&mut self) -> Result<f64, AsmError> {
+        let open = self.next()?;
+        if !open.is("{") {
+            return open.error(format!("expected {{, found {}", open.text));
+        }
+        let mut body = Vec::new();
+        loop {
+            let token = self.tokens.pop_front().map_or_else(|| open.error("unclosed {"), Ok)?;
+            if token.is("}") {
+                break;
+            }
+            body.push(token);
+        }
+        let mut calc = Calc { tokens: &body, pos: 0, open: &open, assembler: self };
+        let value = calc.expression()?;
+        match body.get(calc.pos) {
+            Some(extra) => extra.error(format!("unexpected {} in expression", extra.text)),
+            None => Ok(value)
+        }
+    }
+
+    fn finish(mut self) -> Result<Program, AsmError> {
+        if let Some(open) = self.flow.last() {
+            let (token, what) = match open {
+                Flow::If { token, .. } | Flow::Else { token, .. } => (token, "if ... begin without end"),
+                Flow::Loop { token, .. } => (token, "loop without again")
+            };
+            return token.error(what);
+        }
+        for fixup in std::mem::take(&mut self.fixups) {
+            let address = match self.labels.get(&fixup.name.text) {
+                Some(&address) => address,
+                None => return fixup.name.error(format!("undefined name {}", fixup.name.text))
+            };
+            match fixup.kind {
+                FixupKind::Address => {
+                    self.image[fixup.at] |= (address >> 8) as u8;
+                    self.image[fixup.at + 1] = address as u8;
+                },
+                FixupKind::Unpack => {
+                    self.image[fixup.at + 1] |= (address >> 8) as u8;
+                    self.image[fixup.at + 3] = address as u8;
+                }
+            }
+        }
+        let main = match self.labels.get("main") {
+            Some(&main) => main,
+            None => return self.end.error("the program has no main label")
+        };
+        self.image[START..START + 2].copy_from_slice(&(0x1000 | main).to_be_bytes());
+        Ok(Program { rom: self.image[START..self.size].to_vec(), labels: self.labels })
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.pop_front() {
+            Some(token) => Ok(token),
+            None => self.end.error("unexpected end of file")
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token.is(text) {
+            Ok(())
+        } else {
+            token.error(format!("expected {}, found {}", text, token.text))
+        }
+    }
+
+    // A word to define: anything but a number or a brace
+    fn name(&mut self) -> Result<Token, AsmError> {
+        let token = self.next()?;
+        if parse_number(&token.text).is_some() || "{}()".contains(token.text.as_str()) {
+            return token.error(format!("expected a name, found {}", token.text));
+        }
+        Ok(token)
+    }
+
+    fn define(&mut self, name: &Token, value: f64) -> Result<(), AsmError> {
+        if self.names.contains_key(&name.text) || self.macros.contains_key(&name.text) {
+            return name.error(format!("{} is already defined", name.text));
+        }
+        self.names.insert(name.text.clone(), value);
+        Ok(())
+    }
+
+    fn define_label(&mut self, name: &Token, address: usize) -> Result<(), AsmError> {
+        self.define(name, address as f64)?;
+        self.labels.insert(name.text.clone(), address as u16);
+        Ok(())
+    }
+
+    fn register(&self, token: &Token) -> Result<u8, AsmError> {
+        match parse_register(&token.text).or_else(|| self.aliases.get(&token.text).copied()) {
+            Some(x) => Ok(x),
+            None => token.error(format!("expected a register, found {}", token.text))
+        }
+    }
+
+    fn next_register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        self.register(&token)
+    }
+
+    // A number, or the name of a constant or an already defined label
+    fn value(&self, token: &Token) -> Result<f64, AsmError> {
+        if let Some(value) = parse_number(&token.text) {
+            return Ok(value);
+        }
+        match self.names.get(&token.text) {
+            Some(&value) => Ok(value),
+            None => token.error(format!("undefined name {}", token.text))
+        }
+    }
+
+    fn byte(&self, token: &Token) -> Result<u8, AsmError> {
+        to_byte(self.value(token)?, token)
+    }
+
+    // A 12-bit address. Labels can be used before they are defined, the instruction
+    // emitted at `at` gets the address when the program is finished
+    fn address(&mut self, token: &Token, at: usize, kind: FixupKind) -> Result<u16, AsmError> {
+        let value = match parse_number(&token.text).or_else(|| self.names.get(&token.text).copied()) {
+            Some(value) => value.floor(),
+            None if parse_register(&token.text).is_none() && !"{}()".contains(token.text.as_str()) => {
+                self.fixups.push(Fixup { name: token.clone(), at, kind });
+                return Ok(0);
+            },
+            None => return token.error(format!("expected an address, found {}", token.text))
+        };
+        if !(0.0..MEMORY_SIZE as f64).contains(&value) {
+            return token.error(format!("{} is not an address, they go from 0 to 0xFFF", value));
+        }
+        Ok(value as u16)
+    }
+
+    fn address_op(&mut self, op: u16, token: &Token) -> Result<(), AsmError> {
+        let target = self.next()?;
+        let at = self.pc;
+        let address = self.address(&target, at, FixupKind::Address)?;
+        self.emit(op | address, token)
+    }
+
+    fn register_op(&mut self, op: u16, token: &Token) -> Result<(), AsmError> {
+        let x = self.next_register()?;
+        self.emit(op | (x as u16) << 8, token)
+    }
+
+    fn patch_jump(&mut self, jump: usize, token: &Token) -> Result<(), AsmError> {
+        if self.pc >= MEMORY_SIZE {
+            return token.error("jump target past the end of memory");
+        }
+        self.image[jump..jump + 2].copy_from_slice(&(0x1000 | self.pc as u16).to_be_bytes());
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16, token: &Token) -> Result<(), AsmError> {
+        if let Some(name) = self.next_label.take() {
+            self.define_label(&name, self.pc + 1)?;
+        }
+        let [high, low] = opcode.to_be_bytes();
+        self.emit_byte(high, token)?;
+        self.emit_byte(low, token)
+    }
+
+    fn emit_byte(&mut self, byte: u8, token: &Token) -> Result<(), AsmError> {
+        if self.pc >= MEMORY_SIZE {
+            return token.error("the program doesn't fit in memory");
+        }
+        self.image[self.pc] = byte;
+        self.pc += 1;
+        self.size = self.size.max(self.pc);
+        Ok(())
+    }
+}
+
+// The skip with the opposite test: 3XNN and 4XNN, 5XY0 and 9XY0, EX9E and EXA1
+fn invert(skip: u16) -> u16 {
+    match skip & 0xF000 {
+        0x3000 | 0x4000 => skip ^ 0x7000,
+        0x5000 | 0x9000 => skip ^ 0xC000,
+        _ => skip ^ 0x003F
+    }
+}
+
+fn invert_last(test: &mut [u16]) {
+    if let Some(skip) = test.last_mut() {
+        *skip = invert(*skip);
+    }
+}
+
+// Bytes can be written signed, -1 for 0xFF
+fn to_byte(value: f64, token: &Token) -> Result<u8, AsmError> {
+    let value = value.floor();
+    if !(-128.0..=255.0).contains(&value) {
+        return token.error(format!("{} doesn't fit in a byte", value));
+    }
+    Ok(value as i64 as u8)
+}
+
+fn to_nibble(value: f64, token: &Token) -> Result<u8, AsmError> {
+    let value = value.floor();
+    if !(0.0..=15.0).contains(&value) {
+        return token.error(format!("{} doesn't fit in a nibble", value));
+    }
+    Ok(value as u8)
+}
+
+fn unsupported<T>(token: &Token) -> Result<T, AsmError> {
+    token.error(format!("{} is a SUPER-CHIP/XO-CHIP statement, which this interpreter doesn't run", token.text))
+}
+
+// Evaluates the inside of a :calc, right to left
+struct Calc<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    open: &'a Token, // the {, for errors at the end of the expression
+    assembler: &'a Assembler
+}
+
+impl Calc<'_> {
+    fn expression(&mut self) -> Result<f64, AsmError> {
+        let lhs = self.term()?;
+        let op = match self.tokens.get(self.pos) {
+            Some(op) if BINARY.contains(&op.text.as_str()) => op,
+            _ => return Ok(lhs)
+        };
+        self.pos += 1;
+        let rhs = self.expression()?;
+        let (a, b) = (lhs as i64, rhs as i64);
+        Ok(match op.text.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" | "%" if rhs == 0.0 => return op.error("division by zero"),
+            "/" => lhs / rhs,
+            "%" => lhs % rhs,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+            ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+            "pow" => lhs.powf(rhs),
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            "<" => (lhs < rhs) as u8 as f64,
+            ">" => (lhs > rhs) as u8 as f64,
+            "<=" => (lhs <= rhs) as u8 as f64,
+            ">=" => (lhs >= rhs) as u8 as f64,
+            "==" => (lhs == rhs) as u8 as f64,
+            _ => (lhs != rhs) as u8 as f64
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, AsmError> {
+        let token = match self.tokens.get(self.pos) {
+            Some(token) => token,
+            None => return self.open.error("expression ends too early")
+        };
+        self.pos += 1;
+        if token.is("(") {
+            let value = self.expression()?;
+            match self.tokens.get(self.pos) {
+                Some(close) if close.is(")") => {
+                    self.pos += 1;
+                    return Ok(value);
+                },
+                _ => return token.error("unclosed (")
+            }
+        }
+        if UNARY.contains(&token.text.as_str()) {
+            let value = self.term()?;
+            return Ok(match token.text.as_str() {
+                "-" => -value,
+                "~" => !(value as i64) as f64,
+                "!" => (value == 0.0) as u8 as f64,
+                "@" => self.assembler.image[value as usize & (MEMORY_SIZE - 1)] as f64,
+                "abs" => value.abs(),
+                "sqrt" => value.sqrt(),
+                "floor" => value.floor(),
+                "ceil" => value.ceil(),
+                "sin" => value.sin(),
+                "cos" => value.cos(),
+                "log" => value.ln(),
+                _ => value.exp()
+            });
+        }
+        match token.text.as_str() {
+            "PI" => Ok(std::f64::consts::PI),
+            "E" => Ok(std::f64::consts::E),
+            "HERE" => Ok(self.assembler.pc as f64),
+            _ => self.assembler.value(token)
+        }
+    }
+}
+
+const BINARY: [&str; 19] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=", ">=", "==", "!="
+];
+const UNARY: [&str; 12] = ["-", "~", "!", "@", "abs", "sqrt", "floor", "ceil", "sin", "cos", "log", "exp"];
@@ -0,0 +1,214 @@
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io::{self, Seek, SeekFrom, Write};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise
+}
+
+#[derive(Debug)]
+pub struct ParseWaveformError;
+
+impl fmt::Display for ParseWaveformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown waveform (expected square, sine, triangle or noise)")
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = ParseWaveformError;
+
+    fn from_str(s: &str) -> Result<Waveform, ParseWaveformError> {
+        [Waveform::Square, Waveform::Sine, Waveform::Triangle, Waveform::Noise].iter()
+            .zip(["square", "sine", "triangle", "noise"].iter())
+            .find(|(_, name)| s.eq_ignore_ascii_case(name))
+            .map(|(&waveform, _)| waveform)
+            .ok_or(ParseWaveformError)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AudioConfig {
+    pub waveform: Waveform,
+    pub pitch: f32, // Hz
+    pub volume: f32, // 0.0 to 1.0
+    pub muted: bool,
+    pub attack: f32, // seconds to fade in when the sound timer starts
+    pub release: f32 // seconds to fade out when it stops
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        AudioConfig {
+            waveform: Waveform::Square,
+            pitch: 220.0,
+            volume: 0.10,
+            muted: false,
+            attack: 0.005,
+            release: 0.005
+        }
+    }
+}
+
+// Oscillator for the CHIP-8 beep. The envelope ramps between silence and full volume
+// instead of switching instantly, which avoids clicks at the start and end of a beep.
+pub struct Beeper {
+    config: AudioConfig,
+    sample_rate: f32,
+    phase: f32,
+    envelope: f32,
+    lfsr: u16,
+    noise: f32
+}
+
+impl Beeper {
+    pub fn new(config: AudioConfig, sample_rate: u32) -> Beeper {
+        Beeper {
+            config,
+            sample_rate: sample_rate as f32,
+            phase: 0.0,
+            envelope: 0.0,
+            lfsr: 0xACE1,
+            noise: 1.0
+        }
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: AudioConfig) {
+        self.config = config;
+    }
+
+    pub fn toggle_mute(&mut self) -> bool {
+        self.config.muted = !self.config.muted;
+        self.config.muted
+    }
+
+    // Produce the next sample; gate is true while the sound timer is running
+    pub fn next_sample(&mut self, gate: bool) -> f32 {
+        let on = gate && !self.config.muted;
+        if on && self.envelope < 1.0 {
+            self.envelope = (self.envelope + self.ramp_step(self.config.attack)).min(1.0);
+        } else if !on && self.envelope > 0.0 {
+            self.envelope = (self.envelope - self.ramp_step(self.config.release)).max(0.0);
+        }
+
+        let value = match self.config.waveform {
+            Waveform::Square => if self.phase < 0.5 { 1.0 } else { -1.0 },
+            Waveform::Sine => libm::sinf(self.phase * 2.0 * core::f32::consts::PI),
+            Waveform::Triangle => 1.0 - 4.0 * (self.phase - 0.5).abs(),
+            Waveform::Noise => self.noise
+        };
+
+        self.phase += self.config.pitch / self.sample_rate;
+        if self.phase >= 1.0 {
+            self.phase %= 1.0;
+            self.step_noise();
+        }
+        value * self.config.volume * self.envelope
+    }
+
+    fn ramp_step(&self, seconds: f32) -> f32 {
+        if seconds <= 0.0 {
+            1.0
+        } else {
+            1.0 / (seconds * self.sample_rate)
+        }
+    }
+
+    // 16-bit Galois LFSR, sampled and held once per period so the pitch colours the noise
+    fn step_noise(&mut self) {
+        let bit = self.lfsr & 1;
+        self.lfsr >>= 1;
+        if bit == 1 {
+            self.lfsr ^= 0xB400;
+        }
+        self.noise = if bit == 1 { 1.0 } else { -1.0 };
+    }
+}
+
+// Receives the samples generated by Chip8::emulate_frame_with_audio
+pub trait AudioSink {
+    fn push_sample(&mut self, sample: f32);
+}
+
+#[cfg(feature = "std")]
+impl AudioSink for Vec<f32> {
+    fn push_sample(&mut self, sample: f32) {
+        self.push(sample);
+    }
+}
+
+// Discards everything, used when the frontend has no audio
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn push_sample(&mut self, _sample: f32) {}
+}
+
+// Writes mono 16-bit PCM. The RIFF sizes are patched in by finish()
+#[cfg(feature = "std")]
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    samples: u32,
+    error: Option<io::Error>
+}
+
+#[cfg(feature = "std")]
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?; // patched by finish()
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?; // PCM
+        out.write_all(&1u16.to_le_bytes())?; // mono
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * 2).to_le_bytes())?; // byte rate
+        out.write_all(&2u16.to_le_bytes())?; // block align
+        out.write_all(&16u16.to_le_bytes())?; // bits per sample
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?; // patched by finish()
+        Ok(WavWriter {
+            out,
+            samples: 0,
+            error: None
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let data_len = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_all(&(36 + data_len).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_all(&data_len.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    // The first write error is kept and reported by finish()
+    fn push_sample(&mut self, sample: f32) {
+        if self.error.is_some() {
+            return;
+        }
+        let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+        match self.out.write_all(&pcm.to_le_bytes()) {
+            Ok(()) => self.samples += 1,
+            Err(e) => self.error = Some(e)
+        }
+    }
+}
@@ -0,0 +1,209 @@
+// chip8-tools: the command-line tools that need no window or terminal, so they build
+// without SDL or crossterm. chip8-rs hands the same subcommands over to main here
+use chip8_rs::analysis;
+use chip8_rs::asm;
+use chip8_rs::chip8::{Chip8, Platform};
+use chip8_rs::detect::detect;
+use chip8_rs::gdb;
+use chip8_rs::rng::XorShift;
+use chip8_rs::trace;
+
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+
+const USAGE : &str = "usage: chip8-tools asm SOURCE.8o [-o ROM.ch8]
+       chip8-tools analyze ROM|SOURCE.8o [--dot FILE] [--platform vip|schip|xochip]
+       chip8-tools trace-diff TRACE TRACE
+       chip8-tools gdb ROM|SOURCE.8o [--port PORT] [--platform vip|schip|xochip]";
+
+// The value of --platform, exiting with the usage when it names none
+fn parse_platform(value: Option<String>) -> Platform {
+    value.unwrap_or_default().parse().unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(2);
+    })
+}
+
+// chip8-tools asm: assembles Octo source into a ROM, next to the source unless -o says otherwise
+fn assemble_file(mut args: impl Iterator<Item = String>) {
+    let mut source = None;
+    let mut output = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next(),
+            _ if source.is_none() && !arg.starts_with('-') => source = Some(arg),
+            _ => {
+                eprintln!("unknown argument {}\n{}", arg, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+    let source = match source {
+        Some(source) => PathBuf::from(source),
+        None => {
+            eprintln!("asm needs a source file\n{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let output = output.map_or_else(|| source.with_extension("ch8"), PathBuf::from);
+    let text = std::fs::read_to_string(&source).unwrap_or_else(|e| {
+        eprintln!("{}: {}", source.display(), e);
+        std::process::exit(1);
+    });
+    let program = asm::assemble(&text).unwrap_or_else(|e| {
+        eprintln!("{}:{}", source.display(), e);
+        std::process::exit(1);
+    });
+    if let Err(e) = std::fs::write(&output, &program.rom) {
+        eprintln!("{}: {}", output.display(), e);
+        std::process::exit(1);
+    }
+}
+
+// chip8-tools analyze: prints what static analysis finds in a program, and writes its
+// control-flow graph for Graphviz with --dot
+fn analyze_file(mut args: impl Iterator<Item = String>) {
+    let mut path = None;
+    let mut dot = None;
+    let mut platform = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dot" => dot = args.next(),
+            "--platform" => platform = Some(parse_platform(args.next())),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => {
+                eprintln!("unknown argument {}\n{}", arg, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!("analyze needs a ROM\n{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let program = asm::load_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let quirks = match platform {
+        Some(platform) => platform.quirks(),
+        None => {
+            let detection = detect(&program.rom);
+            println!("platform: {}", detection.explain());
+            detection.quirks()
+        }
+    };
+    let analysis = analysis::analyze(&program.rom, &quirks);
+    let symbols = program.symbols();
+    print!("{}", analysis.report(&symbols));
+    if let Some(dot) = dot {
+        if let Err(e) = std::fs::write(&dot, analysis.dot(&symbols)) {
+            eprintln!("{}: {}", dot, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// chip8-tools trace-diff: finds the first step where two traces of either format differ.
+// Exits with 1 if they do, like diff
+fn diff_traces(args: impl Iterator<Item = String>) {
+    let paths: Vec<String> = args.collect();
+    let [a, b] = match <[String; 2]>::try_from(paths) {
+        Ok(paths) => paths,
+        Err(_) => {
+            eprintln!("trace-diff needs two traces\n{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let open = |path: &str| trace::Reader::open(Path::new(path)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(2);
+    });
+    match trace::diff(open(&a), open(&b)) {
+        Ok(None) => {},
+        Ok(Some(divergence)) => {
+            println!("{}", divergence);
+            std::process::exit(1);
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+// chip8-tools gdb: runs the program without a window under a debugger, which connects to
+// the port on localhost (1234 unless --port says otherwise)
+fn debug_file(mut args: impl Iterator<Item = String>) {
+    let mut path = None;
+    let mut port = 1234;
+    let mut platform = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                match args.next().and_then(|value| value.parse().ok()) {
+                    Some(number) => port = number,
+                    None => {
+                        eprintln!("--port expects a port number\n{}", USAGE);
+                        std::process::exit(2);
+                    }
+                }
+            },
+            "--platform" => platform = Some(parse_platform(args.next())),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => {
+                eprintln!("unknown argument {}\n{}", arg, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+    let path = match path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!("gdb needs a ROM\n{}", USAGE);
+            std::process::exit(2);
+        }
+    };
+    let program = asm::load_program(&path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let quirks = match platform {
+        Some(platform) => platform.quirks(),
+        None => {
+            let detection = detect(&program.rom);
+            eprintln!("Platform: {}", detection.explain());
+            detection.quirks()
+        }
+    };
+    let mut machine_state : Chip8 = Chip8::with_rng(XorShift::new(rand::random()));
+    machine_state.set_quirks(quirks);
+    machine_state.load_fonts();
+    machine_state.load_rom(&program.rom);
+    let served = std::net::TcpListener::bind(("127.0.0.1", port)).and_then(|listener| {
+        eprintln!("Waiting for a debugger on {}", listener.local_addr()?);
+        let (stream, _) = listener.accept()?;
+        gdb::serve(stream, &mut machine_state, &program.symbols())
+    });
+    if let Err(e) = served {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+pub fn main() {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("asm") => assemble_file(args),
+        Some("analyze") => analyze_file(args),
+        Some("trace-diff") => diff_traces(args),
+        Some("gdb") => debug_file(args),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use chip8_rs::asm::Program;
+use chip8_rs::chip8::{Chip8, Platform};
+use chip8_rs::font::{self, DEFAULT_FONT_ADDRESS, FONT_SIZE};
+use chip8_rs::rng::XorShift;
+use chip8_rs::settings::{Loader, Settings};
+use chip8_rs::trace::{self, Filter, Trace};
+use chip8_rs::watch::Reloader;
+
+use crossterm::{cursor, execute, queue, terminal};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::event::{KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::Print;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const FRAMERATE : u32 = 60;
+const DEFAULT_RELEASE_MS : u64 = 150;
+// How often a watched program file is checked for changes
+const RELOAD_CHECK_FRAMES : u32 = 15;
+
+const USAGE : &str = "usage: chip8-tui [--braille] [--release-ms MS] [--font NAME|FILE] [--font-address ADDR] [--platform vip|schip|xochip] [--database FILE] [--watch] [--keep-registers] [--trace FILE] [--trace-format text|binary] [--trace-addresses START-END] [--trace-class 0-F,...] [--trace-frames START-END] ROM|SOURCE.8o|CARTRIDGE.gif";
+
+// Same layout as the SDL frontend: 1234 / QWER / ASDF / ZXCV
+const KEYMAP : [char; 16] = ['x', '1', '2', '3', 'q', 'w', 'e', 'a', 's', 'd', 'z', 'c', '4', 'r', 'f', 'v'];
+
+#[derive(Clone, Copy)]
+enum Style {
+    HalfBlock, // 64x16 characters, two pixels per cell
+    Braille, // 32x8 characters, eight pixels per cell
+}
+
+struct Options {
+    rom: String,
+    style: Style,
+    release: Duration,
+    font: Option<[u8; FONT_SIZE]>,
+    font_address: u16,
+    platform: Option<Platform>,
+    database: Option<String>,
+    watch: bool,
+    keep_registers: bool,
+    trace: Option<String>,
+    trace_format: trace::Format,
+    trace_filter: Filter,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        rom: String::new(),
+        style: Style::HalfBlock,
+        release: Duration::from_millis(DEFAULT_RELEASE_MS),
+        font: None,
+        font_address: DEFAULT_FONT_ADDRESS,
+        platform: None,
+        database: None,
+        watch: false,
+        keep_registers: false,
+        trace: None,
+        trace_format: trace::Format::Text,
+        trace_filter: Filter::default(),
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--braille" => options.style = Style::Braille,
+            "--release-ms" => {
+                let ms = value()?.parse().map_err(|_| "--release-ms expects a number of milliseconds")?;
+                options.release = Duration::from_millis(ms);
+            },
+            "--font" => {
+                let name = value()?;
+                options.font = Some(font::resolve_font(&name).map_err(|e| format!("--font {}", e))?);
+            },
+            "--font-address" => {
+                options.font_address = font::parse_address(&value()?).map_err(|e| format!("--font-address: {}", e))?;
+            },
+            "--platform" => options.platform = Some(value()?.parse().map_err(|e| format!("{}", e))?),
+            "--database" => options.database = Some(value()?),
+            "--watch" => options.watch = true,
+            "--keep-registers" => {
+                options.watch = true;
+                options.keep_registers = true;
+            },
+            "--trace" => options.trace = Some(value()?),
+            "--trace-format" => options.trace_format = value()?.parse().map_err(|e| format!("{}", e))?,
+            "--trace-addresses" => options.trace_filter.addresses = Filter::parse_addresses(&value()?)?,
+            "--trace-class" => options.trace_filter.classes = Filter::parse_classes(&value()?)?,
+            "--trace-frames" => options.trace_filter.frames = Filter::parse_frames(&value()?)?,
+            _ if !arg.starts_with('-') && options.rom.is_empty() => options.rom = arg,
+            _ => return Err(format!("unknown argument {}", arg))
+        }
+    }
+    if options.rom.is_empty() {
+        return Err("no ROM given".to_string());
+    }
+    Ok(options)
+}
+
+// Puts the terminal in raw mode for as long as it lives, and restores it on drop so a
+// panic in the emulator doesn't leave the shell unusable
+struct RawTerminal {
+    // Terminals implementing the kitty keyboard protocol report key releases,
+    // everywhere else releases are simulated with a timeout
+    reports_release: bool,
+}
+
+impl RawTerminal {
+    fn enter() -> io::Result<RawTerminal> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+        let reports_release = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_release {
+            execute!(out, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(RawTerminal { reports_release })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.reports_release {
+            let _ = execute!(out, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn render(gfx: &[[u8; 32]; 64], style: Style) -> Vec<String> {
+    match style {
+        Style::HalfBlock => (0..16).map(|row| {
+            (0..64).map(|x| {
+                match (gfx[x][2 * row], gfx[x][2 * row + 1]) {
+                    (0, 0) => ' ',
+                    (_, 0) => '▀',
+                    (0, _) => '▄',
+                    _ => '█'
+                }
+            }).collect()
+        }).collect(),
+        Style::Braille => (0..8).map(|row| {
+            (0..32).map(|col| {
+                // Braille dot numbering: 1-3 and 7 down the left column, 4-6 and 8 down the right
+                const DOTS : [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+                let mut bits = 0;
+                for (dx, column) in DOTS.iter().enumerate() {
+                    for (dy, dot) in column.iter().enumerate() {
+                        if gfx[2 * col + dx][4 * row + dy] != 0 {
+                            bits |= dot;
+                        }
+                    }
+                }
+                std::char::from_u32(0x2800 + bits).unwrap()
+            }).collect()
+        }).collect()
+    }
+}
+
+fn draw(out: &mut impl Write, lines: &[String]) -> io::Result<()> {
+    for (row, line) in lines.iter().enumerate() {
+        queue!(out, cursor::MoveTo(0, row as u16), Print(line))?;
+    }
+    queue!(out, cursor::MoveTo(0, lines.len() as u16), Print("Esc to quit"))?;
+    out.flush()
+}
+
+fn run(options: &Options, machine_state: &mut Chip8, program: Program, loader: &Loader, status: &str) -> io::Result<()> {
+    let terminal = RawTerminal::enter()?;
+    let mut out = io::stdout();
+    draw(&mut out, &render(machine_state.get_gfx(), options.style))?;
+    execute!(out, Print(format!(" - {}", status)))?;
+    let mut symbols = program.symbols();
+    if let Some(tracer) = machine_state.tracer_mut() {
+        tracer.set_symbols(&symbols);
+    }
+    let mut reloader = Reloader::for_program(Path::new(&options.rom), &program, options.watch, options.keep_registers);
+    let mut frame : u32 = 0;
+    // When each key was last seen going down (or auto-repeating)
+    let mut held : [Option<Instant>; 16] = [None; 16];
+    let mut was_playing_sound = false;
+    let mut halted = false;
+    let frametime = Duration::new(0, 1_000_000_000u32 / FRAMERATE);
+    loop {
+        let instant = Instant::now();
+        frame = frame.wrapping_add(1);
+        if let Some(reloader) = reloader.as_mut().filter(|_| frame.is_multiple_of(RELOAD_CHECK_FRAMES)) {
+            if let Some(result) = loader.reload(reloader, machine_state) {
+                let status = match result {
+                    Ok((program, settings)) => {
+                        symbols = program.symbols();
+                        if let Some(tracer) = machine_state.tracer_mut() {
+                            tracer.set_symbols(&symbols);
+                        }
+                        halted = false;
+                        format!("reloaded {} ({}) - {}", reloader.path().display(), reloader.reloads(), describe(&settings))
+                    },
+                    // Keep running the last version that loaded
+                    Err(e) => e
+                };
+                execute!(out, terminal::Clear(terminal::ClearType::All))?;
+                draw(&mut out, &render(machine_state.get_gfx(), options.style))?;
+                execute!(out, Print(format!(" - {}", status)))?;
+            }
+        }
+        while event::poll(Duration::from_secs(0))? {
+            match event::read()? {
+                Event::Key(KeyEvent { code: KeyCode::Esc, .. }) => return Ok(()),
+                Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers, .. }) if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                Event::Key(KeyEvent { code: KeyCode::Char(c), kind, .. }) => {
+                    if let Some(k) = KEYMAP.iter().position(|&key| key == c.to_ascii_lowercase()) {
+                        if kind == KeyEventKind::Release {
+                            held[k] = None;
+                            machine_state.set_key(k, false);
+                        } else {
+                            if held[k].is_none() {
+                                machine_state.set_key(k, true);
+                            }
+                            held[k] = Some(Instant::now());
+                        }
+                    }
+                },
+                Event::Resize(..) => {
+                    execute!(out, terminal::Clear(terminal::ClearType::All))?;
+                    machine_state.set_draw(true);
+                },
+                _ => {}
+            }
+        }
+        if !terminal.reports_release {
+            for (k, pressed) in held.iter_mut().enumerate() {
+                if pressed.is_some_and(|t| t.elapsed() >= options.release) {
+                    *pressed = None;
+                    machine_state.set_key(k, false);
+                }
+            }
+        }
+
+        machine_state.emulate_frame();
+        if machine_state.get_draw() {
+            draw(&mut out, &render(machine_state.get_gfx(), options.style))?;
+            machine_state.set_draw(false);
+        }
+
+        if let (Some(fault), false) = (machine_state.fault(), halted) {
+            draw(&mut out, &render(machine_state.get_gfx(), options.style))?;
+            execute!(out, Print(format!(" - halted: {}", symbols.fault(fault))))?;
+            halted = true;
+        }
+
+        let playing_sound = machine_state.is_playing_sound();
+        if playing_sound && !was_playing_sound {
+            execute!(out, Print('\x07'))?;
+        }
+        was_playing_sound = playing_sound;
+
+        match frametime.checked_sub(instant.elapsed()) {
+            None => (),
+            Some(t) => ::std::thread::sleep(t)
+        };
+    }
+}
+
+// The status line: the title and where the quirks came from, then the keys
+fn describe(settings: &Settings) -> String {
+    let info = &settings.info;
+    let mut status = settings.origin.clone();
+    if !info.title.is_empty() {
+        status = format!("{} - {}", info.title, status);
+    }
+    if let Some(hints) = info.key_hints() {
+        status = format!("{} - keys: {}", status, hints);
+    }
+    status
+}
+
+pub fn main() {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let loader = Loader::new(options.database.as_deref().map(Path::new), options.platform, options.font, options.font_address).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    // Cartridges bring their own settings, other programs are looked up in the database
+    let (program, settings) = match loader.load(Path::new(&options.rom)) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("Failed to load {}", e);
+            std::process::exit(1);
+        }
+    };
+    let mut machine_state : Chip8 = Chip8::with_rng(XorShift::new(rand::random()));
+    settings.apply(&mut machine_state);
+    machine_state.load_rom(&program.rom);
+    if let Some(path) = options.trace.as_ref() {
+        match Trace::create(Path::new(path), options.trace_format, options.trace_filter.clone()) {
+            Ok(trace) => machine_state.set_tracer(Some(Box::new(trace))),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    let result = run(&options, &mut machine_state, program, &loader, &describe(&settings));
+    if let Some(mut tracer) = machine_state.set_tracer(None) {
+        if let Err(e) = tracer.finish() {
+            eprintln!("Failed to write trace: {}", e);
+        }
+    }
+    if let Err(e) = result {
+        eprintln!("Terminal error: {}", e);
+        std::process::exit(1);
+    }
+}
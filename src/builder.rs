@@ -0,0 +1,88 @@
+// Puts a machine into a given state without running a ROM to get there, so single
+// instructions can be tested in isolation:
+//   let mut machine_state = Chip8Builder::new().v(0, 0xFF).v(1, 1).build();
+//   machine_state.run_opcode(0x8014);
+use crate::chip8::{Chip8, Quirks};
+use crate::rng::XorShift;
+
+pub struct Chip8Builder {
+    machine_state: Chip8
+}
+
+impl Default for Chip8Builder {
+    fn default() -> Chip8Builder {
+        Chip8Builder::new()
+    }
+}
+
+impl Chip8Builder {
+    // Starts from a reset machine with the standard font loaded
+    pub fn new() -> Chip8Builder {
+        let mut machine_state = Chip8::new();
+        machine_state.load_fonts();
+        Chip8Builder { machine_state }
+    }
+
+    pub fn v(mut self, x: usize, value: u8) -> Chip8Builder {
+        self.machine_state.set_v(x, value);
+        self
+    }
+
+    // Sets V0, V1, ... from the slice
+    pub fn registers(mut self, values: &[u8]) -> Chip8Builder {
+        for (x, &value) in values.iter().enumerate() {
+            self.machine_state.set_v(x, value);
+        }
+        self
+    }
+
+    pub fn i(mut self, i: u16) -> Chip8Builder {
+        self.machine_state.set_i(i);
+        self
+    }
+
+    pub fn pc(mut self, pc: u16) -> Chip8Builder {
+        self.machine_state.set_pc(pc);
+        self
+    }
+
+    pub fn memory(mut self, address: u16, data: &[u8]) -> Chip8Builder {
+        self.machine_state.write_memory(address, data);
+        self
+    }
+
+    // Holds key k down
+    pub fn key(mut self, k: usize) -> Chip8Builder {
+        self.machine_state.set_key(k, true);
+        self
+    }
+
+    pub fn pixel(mut self, x: usize, y: usize) -> Chip8Builder {
+        self.machine_state.set_pixel(x, y, true);
+        self
+    }
+
+    pub fn delay_timer(mut self, value: u8) -> Chip8Builder {
+        self.machine_state.set_delay_timer(value);
+        self
+    }
+
+    pub fn sound_timer(mut self, value: u8) -> Chip8Builder {
+        self.machine_state.set_sound_timer(value);
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Chip8Builder {
+        self.machine_state.set_quirks(quirks);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Chip8Builder {
+        *self.machine_state.rng_mut() = XorShift::new(seed);
+        self
+    }
+
+    pub fn build(self) -> Chip8 {
+        self.machine_state
+    }
+}
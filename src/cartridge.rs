@@ -0,0 +1,98 @@
+// Octo cartridges: GIFs with a program hidden in the image. The low two bits of each
+// pixel's palette index in the first frame carry the payload, four pixels to a byte with
+// the high bits first. The payload is a big-endian 32-bit length and then that many bytes
+// of JSON: {"program": Octo source, "options": emulator settings}. The source is run
+// through the assembler and the settings are turned into the database's RomInfo, so the
+// frontends apply them the same way
+use crate::asm::{assemble, Program};
+use crate::chip8::Quirks;
+use crate::database::{parse_color, RomInfo};
+use crate::font::Font;
+use serde_json::Value;
+use std::path::Path;
+
+pub struct Cartridge {
+    pub source: String,
+    pub program: Program,
+    // Title, quirks, tickrate, colours and font from the options, never keys
+    pub info: RomInfo
+}
+
+pub fn is_cartridge(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+}
+
+// Errors start with the path. The title is the file name, since the label on a
+// cartridge is only drawn in the picture
+pub fn load(path: &Path) -> Result<Cartridge, String> {
+    let gif = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut cartridge = read(&gif).map_err(|e| format!("{}: {}", path.display(), e))?;
+    cartridge.info.title = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    Ok(cartridge)
+}
+
+pub fn read(gif: &[u8]) -> Result<Cartridge, String> {
+    let payload = payload(gif)?;
+    let json: Value = serde_json::from_slice(&payload).map_err(|e| format!("not an Octo cartridge: {}", e))?;
+    let source = json.get("program").and_then(Value::as_str).ok_or("the cartridge has no program")?.to_string();
+    let program = assemble(&source).map_err(|e| format!("program {}", e))?;
+    let options = json.get("options").cloned().unwrap_or(Value::Null);
+    Ok(Cartridge { source, program, info: settings(&options) })
+}
+
+fn payload(gif: &[u8]) -> Result<Vec<u8>, String> {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    let mut decoder = options.read_info(gif).map_err(|e| format!("not a GIF: {}", e))?;
+    let frame = decoder.read_next_frame().map_err(|e| format!("not a GIF: {}", e))?.ok_or("the GIF has no image")?;
+    let bytes: Vec<u8> = frame.buffer.chunks_exact(4)
+        .map(|pixels| pixels.iter().fold(0, |byte, &index| byte << 2 | (index & 3)))
+        .collect();
+    let length = match bytes.get(..4) {
+        Some(&[a, b, c, d]) => u32::from_be_bytes([a, b, c, d]) as usize,
+        _ => return Err("not an Octo cartridge: the image is too small".to_string())
+    };
+    bytes.get(4..4 + length).map(<[u8]>::to_vec).ok_or_else(|| "not an Octo cartridge: the payload is cut short".to_string())
+}
+
+// Octo's option names. Its quirks are all off by default, which is the VIP behaviour.
+// vfOrderQuirks (whether VF is written before or after the result) and screenRotation
+// have nothing to apply to here and are dropped. The options don't say what the keys do
+// (touchInputMode only picks how a touchscreen stands in for the keypad), so a
+// cartridge's keys are left empty
+fn settings(options: &Value) -> RomInfo {
+    let flag = |name| options.get(name).and_then(Value::as_bool).unwrap_or(false);
+    let color = |name| options.get(name).and_then(Value::as_str).and_then(parse_color);
+    let quirks = Quirks {
+        shift_uses_vy: !flag("shiftQuirks"),
+        load_store_increments_i: !flag("loadStoreQuirks"),
+        clip_sprites: flag("clipQuirks"),
+        jump_uses_vx: flag("jumpQuirks"),
+        display_wait: flag("vBlankQuirks"),
+        vf_reset: flag("logicQuirks"),
+        ..Quirks::default()
+    };
+    RomInfo {
+        quirks: Some(quirks),
+        tickrate: options.get("tickrate").and_then(Value::as_u64).map(|rate| rate as u32),
+        // Octo draws plane 1 in fillColor, plane 2 in fillColor2 and both in blendColor
+        pixels: ["backgroundColor", "fillColor", "fillColor2", "blendColor"].iter().map_while(|&name| color(name)).collect(),
+        buzzer: color("buzzColor"),
+        silence: color("quietColor"),
+        font: options.get("fontStyle").and_then(Value::as_str).and_then(font),
+        ..RomInfo::default()
+    }
+}
+
+// Octo's fontStyle names. Its own font and its SUPER-CHIP one get the standard font,
+// the nearest here; unknown names keep the frontend's
+fn font(style: &str) -> Option<Font> {
+    match style {
+        "octo" | "schip" => Some(Font::Standard),
+        "vip" => Some(Font::Vip),
+        "dream6800" => Some(Font::Dream6800),
+        "eti660" => Some(Font::Eti660),
+        "fish" => Some(Font::FishNChips),
+        _ => None
+    }
+}
@@ -1,40 +1,255 @@
-use rand::Rng;
-const FONT_SPRITE_DATA: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0,
-    0x20, 0x60, 0x20, 0x20, 0x70,
-    0xF0, 0x10, 0xF0, 0x80, 0xF0,
-    0xF0, 0x10, 0xF0, 0x10, 0xF0,
-    0x90, 0x90, 0xF0, 0x10, 0x10,
-    0xF0, 0x80, 0xF0, 0x10, 0xF0,
-    0xF0, 0x80, 0xF0, 0x90, 0xF0,
-    0xF0, 0x10, 0x20, 0x40, 0x40,
-    0xF0, 0x90, 0xF0, 0x90, 0xF0,
-    0xF0, 0x90, 0xF0, 0x10, 0xF0,
-    0xF0, 0x90, 0xF0, 0x90, 0x90,
-    0xE0, 0x90, 0xE0, 0x90, 0xE0,
-    0xF0, 0x80, 0x80, 0x80, 0xF0,
-    0xE0, 0x90, 0x90, 0x90, 0xE0,
-    0xF0, 0x80, 0xF0, 0x80, 0xF0,
-    0xF0, 0x80, 0xF0, 0x80, 0x80
-    ];
-
-pub struct Chip8 {
+use crate::audio::{AudioConfig, AudioSink, Beeper, NullSink};
+use crate::font::{Font, DEFAULT_FONT_ADDRESS, FONT_SIZE};
+#[cfg(feature = "std")]
+use crate::instruction::DecodeError;
+use crate::instruction::Instruction;
+#[cfg(feature = "jit")]
+use crate::jit::{self, Jit};
+use crate::rng::{RandomSource, XorShift};
+#[cfg(feature = "std")]
+use crate::trace::{Step, Tracer};
+use core::fmt;
+use core::str::FromStr;
+
+pub const CYCLES_PER_FRAME: u32 = 9;
+const FRAMES_PER_SECOND: u32 = 60;
+pub const STACK_SIZE: usize = 16;
+// Where the COSMAC VIP interpreter kept its stack, used when Quirks::stack_in_memory is set
+pub const MEMORY_STACK_ADDRESS: usize = 0xEA0;
+pub const MAX_ROM_SIZE: usize = 4096 - 0x200;
+const ADDRESS_MASK: usize = 0xFFF;
+
+// Save states have a fixed size so frontends can preallocate them
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 3;
+const STATE_STACK_SLOTS: usize = STACK_SIZE;
+pub const STATE_SIZE: usize = 4 + 1 // magic, version
+    + 16 + 2 + 2 + 1 + 1 // v, pc, i, sound timer, delay timer
+    + 4096 + 2 + 64 * 32 // memory, opcode, gfx
+    + 1 + STATE_STACK_SLOTS * 2 // stack depth, stack
+    + 16 + 3 + 1 + 1 // keys, key wait, draw, playing sound
+    + 8 // rng
+    + 3; // fault
+
+#[derive(Debug, PartialEq)]
+pub enum StateError {
+    WrongSize,
+    BadHeader,
+    StackTooDeep,
+    Corrupt
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    // 8XY1, 8XY2 and 8XY3 clear VF, as a side effect of how the VIP interpreter did them
+    pub vf_reset: bool,
+    // FX55 and FX65 leave I pointing past the last register stored or loaded
+    pub load_store_increments_i: bool,
+    // 8XY6 and 8XYE shift VY into VX. Otherwise VX is shifted in place and VY is ignored
+    pub shift_uses_vy: bool,
+    // BNNN jumps to NNN + VX, where X is the high nibble of NNN (SCHIP), instead of NNN + V0
+    pub jump_uses_vx: bool,
+    // DXYN waits for the next frame, so at most one sprite is drawn per frame
+    pub display_wait: bool,
+    // Sprites are cut off at the screen edges instead of wrapping around to the other side
+    pub clip_sprites: bool,
+    // FX0A completes when the key is released (COSMAC VIP) rather than as soon as it is pressed
+    pub key_wait_release: bool,
+    // Nesting limit for 2NNN, at most STACK_SIZE. 12 on the COSMAC VIP, 16 on the SCHIP
+    pub stack_depth: usize,
+    // Keep return addresses in memory at MEMORY_STACK_ADDRESS, big-endian, as the VIP did
+    pub stack_in_memory: bool,
+    // Stop with a Fault on stack overflow/underflow. Otherwise a call on a full stack
+    // loses its return address and 00EE on an empty stack just moves on
+    pub halt_on_stack_fault: bool
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            load_store_increments_i: true,
+            shift_uses_vy: true,
+            jump_uses_vx: false,
+            display_wait: false,
+            clip_sprites: true,
+            key_wait_release: true,
+            stack_depth: STACK_SIZE,
+            stack_in_memory: false,
+            halt_on_stack_fault: true
+        }
+    }
+}
+
+// The platforms ROMs were written for, each with the quirks of its interpreter
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Platform {
+    Vip, // the original CHIP-8 interpreter on the COSMAC VIP
+    Schip, // SUPER-CHIP 1.1 on the HP 48
+    XoChip // Octo's XO-CHIP
+}
+
+const PLATFORM_NAMES: [(Platform, &str); 3] = [
+    (Platform::Vip, "vip"),
+    (Platform::Schip, "schip"),
+    (Platform::XoChip, "xochip")
+];
+
+impl Platform {
+    pub fn quirks(self) -> Quirks {
+        match self {
+            Platform::Vip => Quirks {
+                vf_reset: true,
+                load_store_increments_i: true,
+                shift_uses_vy: true,
+                jump_uses_vx: false,
+                display_wait: true,
+                clip_sprites: true,
+                key_wait_release: true,
+                stack_depth: 12,
+                stack_in_memory: true,
+                halt_on_stack_fault: true
+            },
+            Platform::Schip => Quirks {
+                vf_reset: false,
+                load_store_increments_i: false,
+                shift_uses_vy: false,
+                jump_uses_vx: true,
+                display_wait: false,
+                clip_sprites: true,
+                key_wait_release: true,
+                stack_depth: STACK_SIZE,
+                stack_in_memory: false,
+                halt_on_stack_fault: true
+            },
+            Platform::XoChip => Quirks {
+                vf_reset: false,
+                load_store_increments_i: true,
+                shift_uses_vy: true,
+                jump_uses_vx: false,
+                display_wait: false,
+                clip_sprites: false,
+                key_wait_release: true,
+                stack_depth: STACK_SIZE,
+                stack_in_memory: false,
+                halt_on_stack_fault: true
+            }
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        PLATFORM_NAMES.iter().find(|(platform, _)| *platform == self).unwrap().1
+    }
+}
+
+#[derive(Debug)]
+pub struct ParsePlatformError;
+
+impl fmt::Display for ParsePlatformError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown platform (expected vip, schip or xochip)")
+    }
+}
+
+impl FromStr for Platform {
+    type Err = ParsePlatformError;
+
+    fn from_str(s: &str) -> Result<Platform, ParsePlatformError> {
+        PLATFORM_NAMES.iter()
+            .find(|(_, name)| s.eq_ignore_ascii_case(name))
+            .map(|&(platform, _)| platform)
+            .ok_or(ParsePlatformError)
+    }
+}
+
+// Why the machine stopped; pc is the address of the offending instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 }
+}
+
+impl Fault {
+    pub fn pc(self) -> u16 {
+        match self {
+            Fault::StackOverflow { pc } | Fault::StackUnderflow { pc } => pc
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::StackOverflow { pc } => write!(f, "stack overflow at {:#05x}", pc),
+            Fault::StackUnderflow { pc } => write!(f, "return with empty stack at {:#05x}", pc)
+        }
+    }
+}
+
+// State of an FX0A instruction that is blocking execution
+#[derive(Clone, Copy, PartialEq)]
+enum KeyWait {
+    Idle,
+    Waiting(usize), // waiting for a key to go down, result goes in VX
+    Held(usize, u8) // key went down, waiting for it to be released
+}
+
+// An entry of the predecoded instruction cache
+#[cfg(feature = "std")]
+#[derive(Clone, Copy)]
+enum Cached {
+    Empty,
+    Decoded(Instruction, u16), // and the opcode it came from
+    Undefined
+}
+
+pub struct Chip8<R: RandomSource = XorShift> {
     v: [u8; 16], // general registers
     pc: u16, // program counter
     i: u16, // register I
     sound_timer: u8,
     delay_timer: u8,
     memory: [u8; 4096],
+    #[cfg(feature = "std")]
+    decode_cache: Option<Box<[Cached; 4096]>>, // by address, allocated by set_decode_cache
+    #[cfg(feature = "jit")]
+    jit: Option<Jit>,
     opcode: u16,
     gfx: [[u8; 32]; 64],
-    stack: Vec<u16>,
+    stack: [u16; STACK_SIZE],
+    sp: usize, // number of return addresses on the stack
+    fault: Option<Fault>,
+    font_address: u16, // where FX29 finds the hex digit sprites
     keys: [bool; 16],
+    key_wait: KeyWait,
+    vblank_wait: bool, // a draw with Quirks::display_wait has ended this frame
+    quirks: Quirks,
+    cycles_per_frame: u32,
+    frame: u32, // frames run since the last reset
+    #[cfg(feature = "std")]
+    tracer: Option<Box<dyn Tracer>>,
     draw: bool,
-    playing_sound: bool
+    playing_sound: bool,
+    beeper: Option<Beeper>,
+    sample_rate: u32,
+    pending_samples: u32, // carried between cycles, in 1/(60 * cycles_per_frame) of a sample
+    rng: R
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
 }
 
 impl Chip8 {
     pub fn new() -> Chip8 {
+        Chip8::with_rng(XorShift::default())
+    }
+}
+
+impl<R: RandomSource> Chip8<R> {
+    pub fn with_rng(rng: R) -> Chip8<R> {
         Chip8 {
             v: [0; 16], // general registers
             pc: 0x0200,
@@ -42,47 +257,299 @@ impl Chip8 {
             sound_timer: 0,
             delay_timer: 0,
             memory: [0; 4096],
+            #[cfg(feature = "std")]
+            decode_cache: None,
+            #[cfg(feature = "jit")]
+            jit: None,
             opcode: 0,
             gfx: [[0; 32]; 64],
-            stack: Vec::new(),
+            stack: [0; STACK_SIZE],
+            sp: 0,
+            fault: None,
+            font_address: DEFAULT_FONT_ADDRESS,
             keys: [false; 16],
+            key_wait: KeyWait::Idle,
+            vblank_wait: false,
+            quirks: Quirks::default(),
+            cycles_per_frame: CYCLES_PER_FRAME,
+            frame: 0,
+            #[cfg(feature = "std")]
+            tracer: None,
             draw: false,
-            playing_sound: false
+            playing_sound: false,
+            beeper: None,
+            sample_rate: 0,
+            pending_samples: 0,
+            rng
         }
     }
 
     pub fn set_key(&mut self, k: usize, pressed: bool) {
+        let was_pressed = self.keys[k];
         self.keys[k] = pressed;
+        match self.key_wait {
+            KeyWait::Waiting(x) if pressed && !was_pressed => {
+                if self.quirks.key_wait_release {
+                    self.key_wait = KeyWait::Held(x, k as u8);
+                } else {
+                    self.finish_key_wait(x, k as u8);
+                }
+            },
+            KeyWait::Held(x, key) if !pressed && was_pressed && key as usize == k => {
+                self.finish_key_wait(x, key);
+            },
+            _ => ()
+        }
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        // Compiled blocks have the quirks built in
+        self.flush_code_caches();
+    }
+
+    pub fn rng_mut(&mut self) -> &mut R {
+        &mut self.rng
+    }
+
+    pub fn cycles_per_frame(&self) -> u32 {
+        self.cycles_per_frame
+    }
+
+    pub fn set_cycles_per_frame(&mut self, cycles: u32) {
+        self.cycles_per_frame = cycles;
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    // Hands each instruction to tracer before it runs, returning the tracer it replaces
+    // so that one can be finished. The decode cache and JIT are bypassed while tracing
+    #[cfg(feature = "std")]
+    pub fn set_tracer(&mut self, tracer: Option<Box<dyn Tracer>>) -> Option<Box<dyn Tracer>> {
+        core::mem::replace(&mut self.tracer, tracer)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn tracer_mut(&mut self) -> Option<&mut (dyn Tracer + 'static)> {
+        self.tracer.as_deref_mut()
+    }
+
+    #[cfg(feature = "std")]
+    fn is_tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+
+    // Set once the machine has halted; emulate_cycle does nothing after that
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    pub fn is_waiting_for_key(&self) -> bool {
+        self.key_wait != KeyWait::Idle
+    }
+
+    // Direct access to the registers and memory, for tests and debugging tools
+    pub fn v(&self, x: usize) -> u8 {
+        self.v[x]
+    }
+
+    pub fn set_v(&mut self, x: usize, value: u8) {
+        self.v[x] = value;
+    }
+
+    pub fn i(&self) -> u16 {
+        self.i
+    }
+
+    pub fn set_i(&mut self, i: u16) {
+        self.i = i;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    pub fn memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
+    pub fn write_memory(&mut self, address: u16, data: &[u8]) {
+        let start = address as usize;
+        self.memory[start..start + data.len()].copy_from_slice(data);
+        self.flush_code_caches();
+    }
+
+    pub fn key(&self, k: usize) -> bool {
+        self.keys[k]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        self.gfx[x][y] = on as u8;
+    }
+
+    // Return addresses, innermost last, wherever Quirks::stack_in_memory keeps them
+    pub fn stack(&self) -> impl Iterator<Item = u16> + '_ {
+        (0..self.sp).map(move |level| {
+            if self.quirks.stack_in_memory {
+                let slot = MEMORY_STACK_ADDRESS + 2 * level;
+                u16::from_be_bytes([self.memory[slot], self.memory[slot + 1]])
+            } else {
+                self.stack[level]
+            }
+        })
+    }
+
+    // Executes a single instruction as if it had just been fetched from pc
+    pub fn run_opcode(&mut self, opcode: u16) {
+        self.opcode = opcode;
+        self.execute_opcode();
     }
 
     pub fn get_gfx(&mut self) -> &[[u8; 32]; 64] {
-        return &self.gfx;
+        &self.gfx
     }
 
     pub fn get_draw(&mut self) -> bool {
-        return self.draw;
+        self.draw
     }
 
     pub fn set_draw(&mut self, draw: bool) {
         self.draw = draw;
     }
 
-    pub fn load_rom(&mut self, data: Vec<u8>) {
-        for j in 0..=(data.len()-1) {
-            self.memory[0x0200 + j] = data[j];
+    // Back to power-on, ready for another load_rom: registers, timers, stack, screen and
+    // memory are cleared. The font, quirks, speed and audio settings stay as they are
+    pub fn reset(&mut self) {
+        let font_address = self.font_address as usize;
+        let mut font = [0; FONT_SIZE];
+        font.copy_from_slice(&self.memory[font_address..font_address + FONT_SIZE]);
+        self.memory = [0; 4096];
+        self.memory[font_address..font_address + FONT_SIZE].copy_from_slice(&font);
+        self.flush_code_caches();
+        self.v = [0; 16];
+        self.pc = 0x0200;
+        self.i = 0;
+        self.sound_timer = 0;
+        self.delay_timer = 0;
+        self.opcode = 0;
+        self.gfx = [[0; 32]; 64];
+        self.stack = [0; STACK_SIZE];
+        self.sp = 0;
+        self.fault = None;
+        self.key_wait = KeyWait::Idle;
+        self.vblank_wait = false;
+        self.frame = 0;
+        self.draw = true;
+        self.playing_sound = false;
+    }
+
+    // Swaps the program under a running machine: memory from 0x200 on is cleared and
+    // loaded with data, while registers, timers, stack and screen are kept. A fault is
+    // cleared so execution carries on from pc in the new program
+    pub fn replace_rom(&mut self, data: &[u8]) {
+        let font_address = self.font_address as usize;
+        let mut font = [0; FONT_SIZE];
+        font.copy_from_slice(&self.memory[font_address..font_address + FONT_SIZE]);
+        let mut stack = [0; 2 * STACK_SIZE];
+        stack.copy_from_slice(&self.memory[MEMORY_STACK_ADDRESS..MEMORY_STACK_ADDRESS + 2 * STACK_SIZE]);
+        self.memory[0x0200..].fill(0);
+        self.memory[font_address..font_address + FONT_SIZE].copy_from_slice(&font);
+        if self.quirks.stack_in_memory {
+            self.memory[MEMORY_STACK_ADDRESS..MEMORY_STACK_ADDRESS + 2 * STACK_SIZE].copy_from_slice(&stack);
         }
+        self.fault = None;
+        self.draw = true;
+        self.load_rom(data);
+    }
+
+    // Anything past MAX_ROM_SIZE doesn't fit in memory and is dropped
+    pub fn load_rom(&mut self, data: &[u8]) {
+        let data = &data[..data.len().min(MAX_ROM_SIZE)];
+        self.memory[0x0200..0x0200 + data.len()].copy_from_slice(data);
+        self.flush_code_caches();
     }
 
     pub fn load_fonts(&mut self) {
-        for j in 0..50 {
-            self.memory[j] = FONT_SPRITE_DATA[j];
-        }
+        self.load_font(Font::Standard.data(), DEFAULT_FONT_ADDRESS);
+    }
+
+    // The font has to fit in memory: address + FONT_SIZE <= 4096
+    pub fn load_font(&mut self, data: &[u8; FONT_SIZE], address: u16) {
+        let address = address as usize;
+        self.memory[address..address + FONT_SIZE].copy_from_slice(data);
+        self.flush_code_caches();
+        self.font_address = address as u16;
+    }
+
+    // Start generating audio in emulate_frame_with_audio
+    pub fn enable_audio(&mut self, config: AudioConfig, sample_rate: u32) {
+        self.beeper = Some(Beeper::new(config, sample_rate));
+        self.sample_rate = sample_rate;
+        self.pending_samples = 0;
+    }
+
+    pub fn beeper_mut(&mut self) -> Option<&mut Beeper> {
+        self.beeper.as_mut()
     }
 
     pub fn emulate_frame(&mut self) {
-        for _j in 0..9 {
+        self.emulate_frame_with_audio(&mut NullSink);
+    }
+
+    // Runs a frame, pushing the audio for each cycle as it executes so the beep starts
+    // and stops on the exact instruction that changed the sound timer
+    pub fn emulate_frame_with_audio(&mut self, sink: &mut dyn AudioSink) {
+        self.vblank_wait = false;
+        let mut cycle = 0;
+        while cycle < self.cycles_per_frame {
+            #[cfg(feature = "jit")]
+            {
+                let executed = self.run_block(self.cycles_per_frame - cycle);
+                if executed > 0 {
+                    for _ in 0..executed {
+                        self.generate_audio(sink);
+                    }
+                    cycle += executed;
+                    continue;
+                }
+            }
             self.emulate_cycle();
+            self.generate_audio(sink);
+            cycle += 1;
         }
+        self.end_frame();
+    }
+
+    // The 60 Hz tick at the end of each frame: the timers count down and a display wait
+    // is over. For running a frame's cycles one at a time with emulate_cycle
+    pub fn end_frame(&mut self) {
+        self.vblank_wait = false;
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
@@ -92,79 +559,318 @@ impl Chip8 {
         } else {
             self.playing_sound = false;
         }
+        self.frame = self.frame.wrapping_add(1);
     }
 
     pub fn emulate_cycle(&mut self) {
+        if self.is_waiting_for_key() || self.vblank_wait || self.fault.is_some() {
+            return;
+        }
+        #[cfg(feature = "std")]
+        if self.decode_cache.is_some() && !self.is_tracing() {
+            if let Ok(instruction) = self.predecoded() {
+                self.execute(instruction);
+            }
+            return;
+        }
         self.fetch_opcode();
         self.execute_opcode();    
     }
 
-    pub fn is_playing_sound(&mut self) -> bool {
-        return self.playing_sound;
+    // Decodes each address once and keeps the result, for running far faster than real
+    // time. Memory written by the ROM (FX33, FX55, the VIP stack) or through load_rom,
+    // load_state and write_memory drops the affected entries.
+    #[cfg(feature = "std")]
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache = if enabled { Some(Box::new([Cached::Empty; 4096])) } else { None };
     }
 
-    pub fn fetch_opcode(&mut self) {
-        self.opcode = (self.memory[self.pc as usize] as u16) << 8 | (self.memory[(self.pc + 1) as usize] as u16);
+    // Translates the ROM into x86-64 code a block at a time as it runs, like the decode
+    // cache dropping blocks the ROM writes over. Returns false if executable memory
+    // couldn't be mapped, in which case the interpreter carries on alone
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, enabled: bool) -> bool {
+        self.jit = if enabled { Jit::new() } else { None };
+        self.jit.is_some() == enabled
     }
-    pub fn execute_opcode(&mut self) {
-        let x = ((self.opcode >> 8) & 0xF) as usize;
-        let y = ((self.opcode >> 4) & 0xF) as usize;
-        let n = (self.opcode & 0xFF) as u8;
-        //println!("{:04x}", self.opcode);
-        match self.opcode & 0xF000 {
-            0x0000 => match self.opcode {
-                0x00E0 => self.cls(), // Clear screen
-                0x00EE => self.rts(), // Return from a subroutine
-                _ => () // (0x0NNN) (Ignored). Execute machine language subroutine at address NNN
-            },
-            0x1000 => self.jump(self.opcode & 0x0FFF), // (0x1NNN) Jump to address NNN
-            0x2000 => self.call(self.opcode & 0x0FFF), // (0x2NNN) Execute subroutine starting at address NNN
-            0x3000 => self.ske(x, n), // (0x3XNN) Skip the following instruction if the value of register VX equals NN
-            0x4000 => self.skne(x, n), // (0x4XNN) Skip the following instruction if the value of register VX does not equal NN
-            0x5000 => match self.opcode & 0xF00F {
-                0x5000 => self.skre(x, y), // (0x5XY0) Skip the following instruction if the value of register VX is equal to the value of register VY
-                _ => () // Undefined
-            },
-            0x6000 => self.load(x, n), // (0x6XNN) Store number NN in register VX
-            0x7000 => self.add(x, n), // (0x7XNN) Add the value NN to register VX
-            0x8000 => match self.opcode & 0xF00F {
-                0x8000 => self.r#move(x, y), // (0x8XY0) Store the value of register VY in register VX
-                0x8001 => self.or(x, y), // (0x8XY1) Set VX to VX OR VY
-                0x8002 => self.and(x, y), // (0x8XY2) Set VX to VX AND VY
-                0x8003 => self.xor(x, y), // (0x8XY3) Set VX to VX XOR VY
-                0x8004 => self.addr(x, y), // (0x8XY4) Add the value of register VY to register VX. Set VF to 01 if a carry occurs; set VF to 00 if a carry does not occur
-                0x8005 => self.sub(x, y), // (0x8XY5) Subtract the value of register VY from register VX. Set VF to 00 if a borrow occurs; set VF to 01 if a borrow does not occur
-                0x8006 => self.shr(x, y), // (0x8XY6) Store the value of register VY shifted right one bit in register VX. Set register VF to the least significant bit prior to the shift. VY is unchanged
-                0x8007 => self.subn(x, y), // (0x8XY7) Set register VX to the value of VY minus VX. Set VF to 00 if a borrow occurs. Set VF to 01 if a borrow does not occur
-                0x800E => self.shl(x, y), // (0x8XYE) Store the value of register VY shifted left one bit in register VX. Set register VF to the most significant bit prior to the shift. VY is unchanged
-                _ => () // Undefined
+
+    #[cfg(feature = "std")]
+    fn predecoded(&mut self) -> Result<Instruction, DecodeError> {
+        self.pc &= ADDRESS_MASK as u16;
+        let Some(cache) = self.decode_cache.as_deref() else {
+            self.fetch_opcode();
+            return Instruction::decode(self.opcode);
+        };
+        match cache[self.pc as usize] {
+            Cached::Decoded(instruction, opcode) => {
+                self.opcode = opcode;
+                Ok(instruction)
             },
-            0x9000 => match self.opcode & 0xF00F {
-                0x9000 => self.skrne(x, y), // (0x9XY0) Skip the following instruction if the value of register VX is not equal to the value of register VY
-                _ => () // Undefined
+            Cached::Undefined => {
+                self.fetch_opcode();
+                Err(DecodeError(self.opcode))
             },
-            0xA000 => self.loadi(self.opcode & 0x0FFF), // (0xANNN) Store memory address NNN in register I
-            0xB000 => self.jump0(self.opcode & 0x0FFF), // (0xBNNN) Jump to address NNN + V0
-            0xC000 => self.rand(x, n), // (0xCXNN) Set VX to a random number with a mask of NN
-            0xD000 => self.draw(x, y, (self.opcode & 0x000F) as usize), // (0xDXYN) Draw a sprite at position VX, VY with N bytes of sprite data starting at the address stored in I. Set VF to 01 if any set pixels are changed to unset, and 00 otherwise
-            0xE000 => match self.opcode & 0xF0FF {
-                0xE09E => self.skpr(x), // (0xEX9E) Skip the following instruction if the key corresponding to the hex value currently stored in register VX is pressed
-                0xE0A1 => self.skup(x), // (0xEXA1) Skip the following instruction if the key corresponding to the hex value currently stored in register VX is not pressed
-                _ => (), // Undefined
+            Cached::Empty => {
+                self.fetch_opcode();
+                let decoded = Instruction::decode(self.opcode);
+                if let Some(cache) = self.decode_cache.as_deref_mut() {
+                    cache[self.pc as usize] = match decoded {
+                        Ok(instruction) => Cached::Decoded(instruction, self.opcode),
+                        Err(_) => Cached::Undefined
+                    };
+                }
+                decoded
             }
-            0xF000 => match self.opcode & 0xF0FF {
-                0xF007 => self.moved(x), // (0xFX07) Store the current value of the delay timer in register VX
-                0xF00A => self.keyd(x), // (0xFX0A) Wait for a keypress and store the result in register VX
-                0xF015 => self.loadd(x), // (0xFX15) Set the delay timer to the value of register VX
-                0xF018 => self.loads(x), // (0xFX18) Set the sound timer to the value of register VX
-                0xF01E => self.addi(x), // (0xFX1E) Add the value stored in register VX in register I
-                0xF029 => self.ldspr(x), // (0xFX29) Set register I to the memory address of the sprite data corresponding to the hexadecimal digit stored in register VX
-                0xF033 => self.bcd(x), // (0xFX33) Store the binary-coded decimal equivalent of the value stored in register VX at addresses I, I+1, and I+2
-                0xF055 => self.stor(x), // (0xFX55) Store the values of registers V0 to VX inclusive in memory starting at address I. I is set to I+X+1 after operation
-                0xF065 => self.read(x), // (0xFX65) Fill registers V0 to VX inclusive with the values stored in memory starting at address I. I is set to I+X+1 after operation
-                _ => (), // Undefined
+        }
+    }
+
+    fn flush_code_caches(&mut self) {
+        #[cfg(feature = "std")]
+        if let Some(cache) = self.decode_cache.as_deref_mut() {
+            cache.fill(Cached::Empty);
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit) = self.jit.as_mut() {
+            jit.flush();
+        }
+    }
+
+    // Runs the ROM from pc as native code (see jit.rs) until a block ends, returning the
+    // number of instructions executed. 0 leaves the next instruction to emulate_cycle:
+    // the JIT is off, the block is longer than budget, or it can't be compiled
+    #[cfg(feature = "jit")]
+    fn run_block(&mut self, budget: u32) -> u32 {
+        if self.jit.is_none() || self.is_tracing() || self.is_waiting_for_key() || self.vblank_wait || self.fault.is_some() {
+            return 0;
+        }
+        let base = self as *const Self as usize;
+        let target = jit::Target {
+            v: (self.v.as_ptr() as usize - base) as i32,
+            i: (&self.i as *const u16 as usize - base) as i32,
+            pc: (&self.pc as *const u16 as usize - base) as i32,
+            opcode: (&self.opcode as *const u16 as usize - base) as i32,
+            delay_timer: (&self.delay_timer as *const u8 as usize - base) as i32,
+            helper: jit_helper::<R> as extern "C" fn(*mut u8, u32) as usize
+        };
+        self.pc &= ADDRESS_MASK as u16;
+        let block = match self.jit.as_mut() {
+            Some(jit) => jit.block(&self.memory, self.pc, self.quirks, &target),
+            None => None
+        };
+        match block {
+            Some((code, len)) if len <= budget => {
+                // The block only touches the fields in target, and hands the same pointer to jit_helper
+                unsafe { code(self as *mut Self as *mut u8) };
+                len
+            },
+            _ => 0
+        }
+    }
+
+    // Every write the ROM itself makes goes through here, so the instructions that
+    // overlap the byte (the one starting at it and the one before) get decoded again
+    fn write_byte(&mut self, address: usize, value: u8) {
+        self.memory[address] = value;
+        #[cfg(feature = "std")]
+        if let Some(cache) = self.decode_cache.as_deref_mut() {
+            cache[address] = Cached::Empty;
+            cache[address.wrapping_sub(1) & ADDRESS_MASK] = Cached::Empty;
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit) = self.jit.as_mut() {
+            jit.invalidate(address);
+        }
+    }
+
+    pub fn is_playing_sound(&mut self) -> bool {
+        self.playing_sound
+    }
+
+    fn generate_audio(&mut self, sink: &mut dyn AudioSink) {
+        let gate = self.sound_timer > 0;
+        if let Some(beeper) = self.beeper.as_mut() {
+            // Counted in whole numbers so each frame gets exactly sample_rate / 60 samples
+            let cycle_rate = FRAMES_PER_SECOND * self.cycles_per_frame;
+            self.pending_samples += self.sample_rate;
+            while self.pending_samples >= cycle_rate {
+                sink.push_sample(beeper.next_sample(gate));
+                self.pending_samples -= cycle_rate;
             }
-            _ => (), // Undefined
+        }
+    }
+
+    // Writes the machine state (not the quirks or audio settings) into out, which must be STATE_SIZE bytes
+    pub fn save_state(&self, out: &mut [u8]) -> Result<(), StateError> {
+        if out.len() != STATE_SIZE {
+            return Err(StateError::WrongSize);
+        }
+        let mut w = StateWriter { out, pos: 0 };
+        w.put(STATE_MAGIC);
+        w.put(&[STATE_VERSION]);
+        w.put(&self.v);
+        w.put(&self.pc.to_le_bytes());
+        w.put(&self.i.to_le_bytes());
+        w.put(&[self.sound_timer, self.delay_timer]);
+        w.put(&self.memory);
+        w.put(&self.opcode.to_le_bytes());
+        for column in self.gfx.iter() {
+            w.put(column);
+        }
+        w.put(&[self.sp as u8]);
+        for entry in self.stack.iter() {
+            w.put(&entry.to_le_bytes());
+        }
+        for &key in self.keys.iter() {
+            w.put(&[key as u8]);
+        }
+        w.put(&match self.key_wait {
+            KeyWait::Idle => [0, 0, 0],
+            KeyWait::Waiting(x) => [1, x as u8, 0],
+            KeyWait::Held(x, key) => [2, x as u8, key]
+        });
+        w.put(&[self.draw as u8, self.playing_sound as u8]);
+        w.put(&self.rng.state().to_le_bytes());
+        let (tag, pc) = match self.fault {
+            None => (0, 0),
+            Some(Fault::StackOverflow { pc }) => (1, pc),
+            Some(Fault::StackUnderflow { pc }) => (2, pc)
+        };
+        w.put(&[tag]);
+        w.put(&pc.to_le_bytes());
+        Ok(())
+    }
+
+    // The state is validated completely before anything is overwritten
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        if data.len() != STATE_SIZE {
+            return Err(StateError::WrongSize);
+        }
+        let mut r = StateReader { data };
+        if r.take(4) != STATE_MAGIC || r.byte() != STATE_VERSION {
+            return Err(StateError::BadHeader);
+        }
+        let mut v = [0; 16];
+        v.copy_from_slice(r.take(16));
+        let pc = r.word();
+        let i = r.word();
+        let sound_timer = r.byte();
+        let delay_timer = r.byte();
+        let memory = r.take(4096);
+        let opcode = r.word();
+        let gfx = r.take(64 * 32);
+        let depth = r.byte() as usize;
+        let slots = r.take(STATE_STACK_SLOTS * 2);
+        let keys = r.take(16);
+        let key_wait = match r.take(3) {
+            [0, _, _] => KeyWait::Idle,
+            &[1, x, _] if x < 16 => KeyWait::Waiting(x as usize),
+            &[2, x, key] if x < 16 && key < 16 => KeyWait::Held(x as usize, key),
+            _ => return Err(StateError::Corrupt)
+        };
+        let flags = r.take(2);
+        let mut rng = [0; 8];
+        rng.copy_from_slice(r.take(8));
+        let rng = u64::from_le_bytes(rng);
+        let fault = match (r.byte(), r.word()) {
+            (0, _) => None,
+            (1, pc) => Some(Fault::StackOverflow { pc }),
+            (2, pc) => Some(Fault::StackUnderflow { pc }),
+            _ => return Err(StateError::Corrupt)
+        };
+        if depth > STATE_STACK_SLOTS {
+            return Err(StateError::StackTooDeep);
+        }
+        if pc as usize >= self.memory.len() || i as usize >= self.memory.len() {
+            return Err(StateError::Corrupt);
+        }
+
+        self.v = v;
+        self.pc = pc;
+        self.i = i;
+        self.sound_timer = sound_timer;
+        self.delay_timer = delay_timer;
+        self.memory.copy_from_slice(memory);
+        self.flush_code_caches();
+        self.opcode = opcode;
+        for (column, data) in self.gfx.iter_mut().zip(gfx.chunks(32)) {
+            column.copy_from_slice(data);
+        }
+        for (entry, b) in self.stack.iter_mut().zip(slots.chunks(2)) {
+            *entry = u16::from_le_bytes([b[0], b[1]]);
+        }
+        self.sp = depth;
+        self.fault = fault;
+        for (key, &pressed) in self.keys.iter_mut().zip(keys) {
+            *key = pressed != 0;
+        }
+        self.key_wait = key_wait;
+        self.draw = flags[0] != 0;
+        self.playing_sound = flags[1] != 0;
+        self.rng.restore(rng);
+        Ok(())
+    }
+
+    pub fn fetch_opcode(&mut self) {
+        // Addresses are 12 bits, so execution past the end of memory wraps to the start
+        self.pc &= ADDRESS_MASK as u16;
+        self.opcode = (self.memory[self.pc as usize] as u16) << 8 | (self.memory[(self.pc as usize + 1) & ADDRESS_MASK] as u16);
+    }
+    pub fn execute_opcode(&mut self) {
+        #[cfg(feature = "std")]
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.step(&Step {
+                frame: self.frame,
+                pc: self.pc,
+                opcode: self.opcode,
+                v: self.v,
+                i: self.i,
+                delay_timer: self.delay_timer,
+                sound_timer: self.sound_timer
+            });
+        }
+        if let Ok(instruction) = Instruction::decode(self.opcode) {
+            self.execute(instruction);
+        }
+        // Undefined opcodes do nothing, and leave pc where it is
+    }
+
+    pub fn execute(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::Sys { .. } => self.pc += 2,
+            Instruction::Cls => self.cls(),
+            Instruction::Rts => self.rts(),
+            Instruction::Jump { addr } => self.jump(addr),
+            Instruction::Call { addr } => self.call(addr),
+            Instruction::Ske { x, nn } => self.ske(x as usize, nn),
+            Instruction::Skne { x, nn } => self.skne(x as usize, nn),
+            Instruction::Skre { x, y } => self.skre(x as usize, y as usize),
+            Instruction::Load { x, nn } => self.load(x as usize, nn),
+            Instruction::Add { x, nn } => self.add(x as usize, nn),
+            Instruction::Move { x, y } => self.r#move(x as usize, y as usize),
+            Instruction::Or { x, y } => self.or(x as usize, y as usize),
+            Instruction::And { x, y } => self.and(x as usize, y as usize),
+            Instruction::Xor { x, y } => self.xor(x as usize, y as usize),
+            Instruction::Addr { x, y } => self.addr(x as usize, y as usize),
+            Instruction::Sub { x, y } => self.sub(x as usize, y as usize),
+            Instruction::Shr { x, y } => self.shr(x as usize, y as usize),
+            Instruction::Subn { x, y } => self.subn(x as usize, y as usize),
+            Instruction::Shl { x, y } => self.shl(x as usize, y as usize),
+            Instruction::Skrne { x, y } => self.skrne(x as usize, y as usize),
+            Instruction::Loadi { addr } => self.loadi(addr),
+            Instruction::Jump0 { addr } => self.jump0(addr),
+            Instruction::Rand { x, nn } => self.rand(x as usize, nn),
+            Instruction::Draw { x, y, n } => self.draw(x as usize, y as usize, n as usize),
+            Instruction::Skpr { x } => self.skpr(x as usize),
+            Instruction::Skup { x } => self.skup(x as usize),
+            Instruction::Moved { x } => self.moved(x as usize),
+            Instruction::Keyd { x } => self.keyd(x as usize),
+            Instruction::Loadd { x } => self.loadd(x as usize),
+            Instruction::Loads { x } => self.loads(x as usize),
+            Instruction::Addi { x } => self.addi(x as usize),
+            Instruction::Ldspr { x } => self.ldspr(x as usize),
+            Instruction::Bcd { x } => self.bcd(x as usize),
+            Instruction::Stor { x } => self.stor(x as usize),
+            Instruction::Read { x } => self.read(x as usize),
         }
     }
 
@@ -175,7 +881,7 @@ impl Chip8 {
     }
 
     pub fn addi(&mut self, x: usize) {
-        self.i += self.v[x] as u16;
+        self.i = self.i.wrapping_add(self.v[x] as u16);
         self.pc += 2;
     }
 
@@ -193,19 +899,36 @@ impl Chip8 {
     // (0x8XY2) Set VX to VX AND VY
     pub fn and(&mut self, x: usize, y: usize) {
         self.v[x] &= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
         self.pc += 2;
     }
 
     // Store the binary-coded decimal equivalent of the value stored in register VX at addresses I, I+1, and I+2
     pub fn bcd(&mut self, x: usize) {
-        self.memory[self.i as usize] = self.v[x] / 100;
-        self.memory[(self.i + 1) as usize] = (self.v[x] / 10) % 10;
-        self.memory[(self.i + 2) as usize] = self.v[x] % 10;
+        self.write_byte(self.i_address(0), self.v[x] / 100);
+        self.write_byte(self.i_address(1), (self.v[x] / 10) % 10);
+        self.write_byte(self.i_address(2), self.v[x] % 10);
         self.pc += 2;
     }
 
     pub fn call(&mut self, addr: u16) {
-        self.stack.push(self.pc);
+        if self.sp >= self.quirks.stack_depth.min(STACK_SIZE) {
+            if self.quirks.halt_on_stack_fault {
+                self.fault = Some(Fault::StackOverflow { pc: self.pc });
+                return;
+            }
+        } else if self.quirks.stack_in_memory {
+            let slot = MEMORY_STACK_ADDRESS + 2 * self.sp;
+            let [high, low] = self.pc.to_be_bytes();
+            self.write_byte(slot, high);
+            self.write_byte(slot + 1, low);
+            self.sp += 1;
+        } else {
+            self.stack[self.sp] = self.pc;
+            self.sp += 1;
+        }
         self.pc = addr;
     }
 
@@ -223,19 +946,20 @@ impl Chip8 {
         let mut draw_flag : bool = false;
         let col = (self.v[x] % 64) as usize;
         let row = (self.v[y] % 32) as usize;
-        for j in 0..=n-1 {
-            if j + row >= 32 {
+        for j in 0..n {
+            if j + row >= 32 && self.quirks.clip_sprites {
                 break;
             }
             for k in 0..=7 {
-                if k + col >= 64 {
+                if k + col >= 64 && self.quirks.clip_sprites {
                     break;
                 } else {
-                    let on : u8 = (self.memory[(self.i as usize) + j] >> (7-k)) & 1;
-                    if on & self.gfx[k + col][j + row] == 1 {
+                    let on : u8 = (self.memory[self.i_address(j)] >> (7-k)) & 1;
+                    let (px, py) = ((k + col) % 64, (j + row) % 32);
+                    if on & self.gfx[px][py] == 1 {
                         draw_flag = true;
                     }
-                    self.gfx[k + col][j + row] ^= on;
+                    self.gfx[px][py] ^= on;
                 }
             }
         }
@@ -246,6 +970,7 @@ impl Chip8 {
             self.v[0xF] = 0;
         }
         self.draw = true;
+        self.vblank_wait = self.quirks.display_wait;
         self.pc += 2;
     }
 
@@ -255,21 +980,29 @@ impl Chip8 {
     }
 
     pub fn jump0(&mut self, addr: u16) {
-        self.pc = addr + (self.v[0] as u16);
+        let offset = if self.quirks.jump_uses_vx { self.v[(addr >> 8) as usize] } else { self.v[0] };
+        self.pc = (addr + offset as u16) & ADDRESS_MASK as u16;
     }
 
+    // (0xFX0A) Block until a key is pressed (and released, depending on quirks); set_key completes the instruction
     pub fn keyd(&mut self, x: usize) {
-        for key in 0..0xF {
-            if self.keys[key] {
-                self.v[x] = key as u8;
-                self.pc += 2;
-                return;
-            }
-        }
+        self.key_wait = KeyWait::Waiting(x);
+    }
+
+    fn finish_key_wait(&mut self, x: usize, key: u8) {
+        self.v[x] = key;
+        self.key_wait = KeyWait::Idle;
+        self.pc += 2;
+    }
+
+    // Memory address I + offset; like pc, it wraps around at the end of memory
+    fn i_address(&self, offset: usize) -> usize {
+        (self.i as usize + offset) & ADDRESS_MASK
     }
 
+    // Only the low nibble of VX selects the digit
     pub fn ldspr(&mut self, x: usize) {
-        self.i = (self.v[x] as u16) * 5;
+        self.i = self.font_address + (self.v[x] & 0xF) as u16 * 5;
         self.pc += 2;
     }
 
@@ -305,44 +1038,58 @@ impl Chip8 {
 
     pub fn or(&mut self, x: usize, y: usize) {
         self.v[x] |= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
         self.pc += 2;
     }
 
     pub fn rand(&mut self, x: usize, n: u8) {
-        let mut rng = rand::thread_rng();
-        let r : u8 = rng.gen();
+        let r : u8 = self.rng.next_byte();
         self.v[x] = r & n;
         self.pc += 2;
     }
 
     pub fn read(&mut self, x: usize) {
         for j in 0..=x {
-            self.v[j] = self.memory[(self.i as usize) + j];
+            self.v[j] = self.memory[self.i_address(j)];
+        }
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add((x + 1) as u16);
         }
-        self.i += (x + 1) as u16;
         self.pc += 2;
     }
 
     // Return from a subroutine
     pub fn rts(&mut self) {
-        let s = self.stack.pop();
-        self.pc = match s {
-            None => self.pc,
-            _=> s.unwrap()
-        };
+        if self.sp == 0 {
+            if self.quirks.halt_on_stack_fault {
+                self.fault = Some(Fault::StackUnderflow { pc: self.pc });
+                return;
+            }
+        } else if self.quirks.stack_in_memory {
+            self.sp -= 1;
+            let slot = MEMORY_STACK_ADDRESS + 2 * self.sp;
+            self.pc = u16::from_be_bytes([self.memory[slot], self.memory[slot + 1]]) & ADDRESS_MASK as u16;
+        } else {
+            self.sp -= 1;
+            self.pc = self.stack[self.sp] & ADDRESS_MASK as u16;
+        }
         self.pc += 2;
     }
 
     pub fn shl(&mut self, x: usize, y: usize) {
-        let b = (self.v[y] & 0x80) >> 7;
-        self.v[x] = self.v[y] << 1;
+        let source = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+        let b = (source & 0x80) >> 7;
+        self.v[x] = source << 1;
         self.v[0xF] = b;
         self.pc += 2;
     }
 
     pub fn shr(&mut self, x: usize, y: usize) {
-        self.v[0xF] = self.v[y] & 1;
-        self.v[x] = self.v[y] >> 1;
+        let source = if self.quirks.shift_uses_vy { self.v[y] } else { self.v[x] };
+        self.v[x] = source >> 1;
+        self.v[0xF] = source & 1;
         self.pc += 2;
     }
 
@@ -361,14 +1108,14 @@ impl Chip8 {
     }
 
     pub fn skpr(&mut self, x: usize) {
-        if self.keys[self.v[x] as usize] {
+        if self.keys[(self.v[x] & 0xF) as usize] {
             self.pc += 2
         }
         self.pc += 2;
     }
 
     pub fn skup(&mut self, x: usize) {
-        if !self.keys[self.v[x] as usize] {
+        if !self.keys[(self.v[x] & 0xF) as usize] {
             self.pc += 2
         }
         self.pc += 2;
@@ -390,9 +1137,11 @@ impl Chip8 {
 
     pub fn stor(&mut self, x: usize) {
         for j in 0..=x {
-            self.memory[(self.i as usize) + j] = self.v[j];
+            self.write_byte(self.i_address(j), self.v[j]);
+        }
+        if self.quirks.load_store_increments_i {
+            self.i = self.i.wrapping_add((x + 1) as u16);
         }
-        self.i += (x + 1) as u16;
         self.pc += 2;
     }
 
@@ -421,6 +1170,49 @@ impl Chip8 {
 
     pub fn xor(&mut self, x: usize, y: usize) {
         self.v[x] ^= self.v[y];
+        if self.quirks.vf_reset {
+            self.v[0xF] = 0;
+        }
         self.pc += 2;
     }
-}
\ No newline at end of file
+}
+
+// Compiled blocks call this for the instructions they leave to Chip8 (DXYN, EX9E, EXA1)
+#[cfg(feature = "jit")]
+extern "C" fn jit_helper<R: RandomSource>(machine: *mut u8, opcode: u32) {
+    let machine_state = unsafe { &mut *(machine as *mut Chip8<R>) };
+    machine_state.run_opcode(opcode as u16);
+}
+
+struct StateWriter<'a> {
+    out: &'a mut [u8],
+    pos: usize
+}
+
+impl StateWriter<'_> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.out[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+    }
+}
+
+struct StateReader<'a> {
+    data: &'a [u8]
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        head
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn word(&mut self) -> u16 {
+        let b = self.take(2);
+        u16::from_le_bytes([b[0], b[1]])
+    }
+}
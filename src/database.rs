@@ -0,0 +1,196 @@
+// Settings for known ROMs, looked up by the SHA-1 of the ROM file. Reads programs.json
+// from the community CHIP-8 database (github.com/chip-8/chip-8-database): a list of
+// programs, each with its ROM versions keyed by hash. A few entries are built in, and a
+// full copy of the file can be loaded instead
+use crate::chip8::{Platform, Quirks};
+use crate::font::Font;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+const BUILTIN: &str = include_str!("../database/programs.json");
+
+// An sRGB colour, from "#rrggbb"
+pub type Rgb = [u8; 3];
+
+// What the database knows about one ROM. Anything it doesn't say is left empty
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub release: Option<String>,
+    // The database's platform ids, most suitable first
+    pub platforms: Vec<String>,
+    // The first of those this interpreter has a profile for, None for modernChip8
+    pub platform: Option<Platform>,
+    // Quirks of that platform, with the ROM's own exceptions applied
+    pub quirks: Option<Quirks>,
+    // Instructions per frame
+    pub tickrate: Option<u32>,
+    // Background, then foreground (further entries are for XO-CHIP planes)
+    pub pixels: Vec<Rgb>,
+    pub buzzer: Option<Rgb>,
+    pub silence: Option<Rgb>,
+    // Hex digit font, which only cartridges choose
+    pub font: Option<Font>,
+    // What the keys do, e.g. ("up", 5)
+    pub keys: Vec<(String, u8)>
+}
+
+impl RomInfo {
+    // "up 5, left 7, a 6", for showing the player
+    pub fn key_hints(&self) -> Option<String> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        let hints: Vec<String> = self.keys.iter().map(|(action, key)| format!("{} {:X}", action, key)).collect();
+        Some(hints.join(", "))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Database {
+    roms: HashMap<String, RomInfo> // by lowercase hex SHA-1
+}
+
+impl Database {
+    pub fn builtin() -> Database {
+        Database::parse(BUILTIN).expect("the built-in ROM database is valid")
+    }
+
+    pub fn load(path: &Path) -> Result<Database, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Database::parse(&json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(json: &str) -> Result<Database, String> {
+        let programs: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        let programs = programs.as_array().ok_or("expected a list of programs")?;
+        let mut roms = HashMap::new();
+        for program in programs {
+            let versions = program.get("roms").and_then(Value::as_object);
+            for (hash, rom) in versions.into_iter().flatten() {
+                roms.insert(hash.to_ascii_lowercase(), rom_info(program, rom));
+            }
+        }
+        Ok(Database { roms })
+    }
+
+    pub fn len(&self) -> usize {
+        self.roms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roms.is_empty()
+    }
+
+    pub fn get(&self, sha1: &str) -> Option<&RomInfo> {
+        self.roms.get(&sha1.to_ascii_lowercase())
+    }
+
+    pub fn lookup(&self, rom: &[u8]) -> Option<&RomInfo> {
+        self.get(&sha1(rom))
+    }
+}
+
+pub fn sha1(data: &[u8]) -> String {
+    sha1_smol::Sha1::from(data).digest().to_string()
+}
+
+fn rom_info(program: &Value, rom: &Value) -> RomInfo {
+    let text = |value: &Value, key| value.get(key).and_then(Value::as_str).map(str::to_string);
+    let platforms: Vec<String> = rom.get("platforms").and_then(Value::as_array).into_iter().flatten()
+        .filter_map(|id| id.as_str().map(str::to_string))
+        .collect();
+    let profile = platforms.iter().find_map(|id| profile(id).map(|profile| (id, profile)));
+    let quirks = profile.map(|(id, (platform, flags))| {
+        let mut quirks = platform.map_or_else(Quirks::default, |platform| platform.quirks());
+        apply(&mut quirks, flags);
+        // Exceptions the ROM needs on top of its platform's usual behaviour
+        if let Some(exceptions) = rom.get("quirkyPlatforms").and_then(|quirky| quirky.get(id.as_str())).and_then(Value::as_object) {
+            apply_exceptions(&mut quirks, exceptions);
+        }
+        quirks
+    });
+    let colors = rom.get("colors");
+    let color = |key| colors.and_then(|colors| colors.get(key)).and_then(Value::as_str).and_then(parse_color);
+    RomInfo {
+        title: text(program, "title").unwrap_or_default(),
+        authors: program.get("authors").and_then(Value::as_array).into_iter().flatten()
+            .filter_map(|author| author.as_str().map(str::to_string))
+            .collect(),
+        release: text(program, "release"),
+        platform: profile.and_then(|(_, (platform, _))| platform),
+        quirks,
+        tickrate: rom.get("tickrate").and_then(Value::as_u64).map(|rate| rate as u32),
+        pixels: colors.and_then(|colors| colors.get("pixels")).and_then(Value::as_array).into_iter().flatten()
+            .filter_map(|pixel| pixel.as_str().and_then(parse_color))
+            .collect(),
+        buzzer: color("buzzer"),
+        silence: color("silence"),
+        font: None,
+        keys: rom.get("keys").and_then(Value::as_object).into_iter().flatten()
+            .filter_map(|(action, key)| Some((action.clone(), key.as_u64().filter(|&key| key < 16)? as u8)))
+            .collect(),
+        platforms
+    }
+}
+
+// The database's quirk flags, as its platforms.json names them
+#[derive(Clone, Copy)]
+struct Flags {
+    shift: bool, // 8XY6/8XYE shift VX in place
+    memory_leave_i_unchanged: bool, // FX55/FX65 don't move I
+    wrap: bool, // sprites wrap around the screen edges
+    jump: bool, // BXNN jumps to XNN + VX
+    vblank: bool, // DXYN waits for the next frame
+    logic: bool // 8XY1-8XY3 reset VF
+}
+
+// The interpreter profile closest to a database platform, and that platform's flags.
+// memoryIncrementByX (CHIP-48, SUPER-CHIP 1.0) has no quirk here and is treated as
+// moving I past the last register
+fn profile(id: &str) -> Option<(Option<Platform>, Flags)> {
+    let flags = |shift, memory_leave_i_unchanged, wrap, jump, vblank, logic| Flags { shift, memory_leave_i_unchanged, wrap, jump, vblank, logic };
+    Some(match id {
+        "originalChip8" | "hybridVIP" => (Some(Platform::Vip), flags(false, false, false, false, true, true)),
+        "modernChip8" => (None, flags(false, false, false, false, false, false)),
+        "chip48" | "superchip1" => (Some(Platform::Schip), flags(true, false, false, true, false, false)),
+        "superchip" => (Some(Platform::Schip), flags(true, true, false, true, false, false)),
+        "xochip" => (Some(Platform::XoChip), flags(false, false, true, false, false, false)),
+        _ => return None
+    })
+}
+
+fn apply(quirks: &mut Quirks, flags: Flags) {
+    quirks.shift_uses_vy = !flags.shift;
+    quirks.load_store_increments_i = !flags.memory_leave_i_unchanged;
+    quirks.clip_sprites = !flags.wrap;
+    quirks.jump_uses_vx = flags.jump;
+    quirks.display_wait = flags.vblank;
+    quirks.vf_reset = flags.logic;
+}
+
+fn apply_exceptions(quirks: &mut Quirks, exceptions: &Map<String, Value>) {
+    for (name, value) in exceptions {
+        let Some(on) = value.as_bool() else { continue };
+        match name.as_str() {
+            "shift" => quirks.shift_uses_vy = !on,
+            "memoryLeaveIUnchanged" => quirks.load_store_increments_i = !on,
+            "wrap" => quirks.clip_sprites = !on,
+            "jump" => quirks.jump_uses_vx = on,
+            "vblank" => quirks.display_wait = on,
+            "logic" => quirks.vf_reset = on,
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn parse_color(text: &str) -> Option<Rgb> {
+    let hex = text.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
@@ -0,0 +1,132 @@
+// Guesses which platform a ROM was written for from the instructions it can reach, so
+// it can run with the right quirks without being told. Instructions only some platforms
+// have decide it: XO-CHIP's are a superset of SUPER-CHIP's, and 0NNN machine code calls
+// only ran on the COSMAC VIP. Without any of those the operands of the shifts are a
+// weaker hint, since 8XY6 and 8XYE only read VY on the VIP
+use crate::analysis::analyze;
+use crate::chip8::{Platform, Quirks};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisplayMode {
+    Low, // 64x32, the only mode this interpreter draws
+    High // 128x64, switched on by 00FF on SUPER-CHIP and XO-CHIP
+}
+
+// A reachable instruction pointing at a platform
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Evidence {
+    pub address: u16,
+    pub opcode: u16,
+    pub platform: Platform,
+    pub meaning: &'static str,
+    // Shift operands only suggest a platform, opcodes the others lack settle it
+    pub telltale: bool
+}
+
+impl fmt::Display for Evidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04X} at {:#05x} ({})", self.opcode, self.address, self.meaning)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Detection {
+    // None when nothing in the ROM is specific to a platform
+    pub platform: Option<Platform>,
+    pub display: DisplayMode,
+    pub evidence: Vec<Evidence>
+}
+
+// How many instructions explain lists before summing up the rest
+const EXPLAINED: usize = 3;
+
+impl Detection {
+    // The detected platform's quirks, or the interpreter's own defaults
+    pub fn quirks(&self) -> Quirks {
+        self.platform.map_or_else(Quirks::default, |platform| platform.quirks())
+    }
+
+    // What was chosen and which instructions it was chosen for, in a line
+    pub fn explain(&self) -> String {
+        let mut text = match self.platform {
+            None => "default quirks, no platform-specific instructions found".to_string(),
+            Some(platform) => {
+                let reasons: Vec<&Evidence> = self.evidence.iter().filter(|evidence| evidence.platform == platform).collect();
+                let mut listed: Vec<String> = reasons.iter().take(EXPLAINED).map(|evidence| evidence.to_string()).collect();
+                if reasons.len() > EXPLAINED {
+                    listed.push(format!("{} more", reasons.len() - EXPLAINED));
+                }
+                format!("{} quirks for {}", platform.name(), listed.join(", "))
+            }
+        };
+        if self.display == DisplayMode::High {
+            text.push_str("; it uses the 128x64 display, which this interpreter can't show");
+        }
+        text
+    }
+}
+
+pub fn detect(rom: &[u8]) -> Detection {
+    let analysis = analyze(rom, &Quirks::default());
+    let mut evidence = Vec::new();
+    let mut display = DisplayMode::Low;
+    for block in analysis.blocks.values() {
+        for (k, &opcode) in block.opcodes.iter().enumerate() {
+            let address = block.start + 2 * k as u16;
+            if opcode == 0x00FF {
+                display = DisplayMode::High;
+            }
+            if let Some((platform, meaning, telltale)) = classify(opcode) {
+                evidence.push(Evidence { address, opcode, platform, meaning, telltale });
+            }
+        }
+    }
+    let telltale = |platform| evidence.iter().any(|evidence| evidence.telltale && evidence.platform == platform);
+    let hints = |platform| evidence.iter().filter(|evidence| !evidence.telltale && evidence.platform == platform).count();
+    let platform = if telltale(Platform::XoChip) {
+        Some(Platform::XoChip)
+    } else if telltale(Platform::Schip) {
+        Some(Platform::Schip)
+    } else if telltale(Platform::Vip) {
+        Some(Platform::Vip)
+    } else {
+        match hints(Platform::Vip).cmp(&hints(Platform::Schip)) {
+            std::cmp::Ordering::Greater => Some(Platform::Vip),
+            std::cmp::Ordering::Less => Some(Platform::Schip),
+            std::cmp::Ordering::Equal => None
+        }
+    };
+    Detection { platform, display, evidence }
+}
+
+fn classify(opcode: u16) -> Option<(Platform, &'static str, bool)> {
+    let x = opcode >> 8 & 0xF;
+    let y = opcode >> 4 & 0xF;
+    Some(match opcode {
+        0xF000 => (Platform::XoChip, "16-bit I load", true),
+        0x5002..=0x5FF3 if opcode & 0xF == 2 => (Platform::XoChip, "save register range", true),
+        0x5003..=0x5FF3 if opcode & 0xF == 3 => (Platform::XoChip, "load register range", true),
+        0xF001..=0xFF01 if opcode & 0xFF == 0x01 => (Platform::XoChip, "bit plane select", true),
+        0xF002 => (Platform::XoChip, "audio pattern", true),
+        0xF03A..=0xFF3A if opcode & 0xFF == 0x3A => (Platform::XoChip, "audio pitch", true),
+        0x00D0..=0x00DF => (Platform::XoChip, "scroll up", true),
+        0x00C0..=0x00CF => (Platform::Schip, "scroll down", true),
+        0x00FB => (Platform::Schip, "scroll right", true),
+        0x00FC => (Platform::Schip, "scroll left", true),
+        0x00FD => (Platform::Schip, "exit", true),
+        0x00FE => (Platform::Schip, "low resolution", true),
+        0x00FF => (Platform::Schip, "high resolution", true),
+        0xD000..=0xDFF0 if opcode & 0xF == 0 => (Platform::Schip, "16x16 sprite", true),
+        0xF030..=0xFF30 if opcode & 0xFF == 0x30 => (Platform::Schip, "large hex digit", true),
+        0xF075..=0xFF75 if opcode & 0xFF == 0x75 => (Platform::Schip, "save flags", true),
+        0xF085..=0xFF85 if opcode & 0xFF == 0x85 => (Platform::Schip, "load flags", true),
+        0x00E0 | 0x00EE => return None,
+        // 0000 is more likely execution running into empty memory than a machine call
+        0x0001..=0x0FFF => (Platform::Vip, "machine code call", true),
+        // SHR VX as SUPER-CHIP assemblers wrote it, VY left as 0
+        0x8006..=0x8F0E if y == 0 && x != 0 && matches!(opcode & 0xF, 0x6 | 0xE) => (Platform::Schip, "shift ignoring VY", false),
+        0x8006..=0x8FFE if y != x && matches!(opcode & 0xF, 0x6 | 0xE) => (Platform::Vip, "shift of VY into VX", false),
+        _ => return None
+    })
+}
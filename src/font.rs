@@ -0,0 +1,202 @@
+use core::fmt;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+// 16 hex digits, 5 rows each
+pub const FONT_SIZE: usize = 80;
+pub const DEFAULT_FONT_ADDRESS: u16 = 0x000;
+// The last address a whole font fits at
+pub const MAX_FONT_ADDRESS: u16 = (4096 - FONT_SIZE) as u16;
+
+const STANDARD: [u8; FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0,
+    0x20, 0x60, 0x20, 0x20, 0x70,
+    0xF0, 0x10, 0xF0, 0x80, 0xF0,
+    0xF0, 0x10, 0xF0, 0x10, 0xF0,
+    0x90, 0x90, 0xF0, 0x10, 0x10,
+    0xF0, 0x80, 0xF0, 0x10, 0xF0,
+    0xF0, 0x80, 0xF0, 0x90, 0xF0,
+    0xF0, 0x10, 0x20, 0x40, 0x40,
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,
+    0xF0, 0x90, 0xF0, 0x10, 0xF0,
+    0xF0, 0x90, 0xF0, 0x90, 0x90,
+    0xE0, 0x90, 0xE0, 0x90, 0xE0,
+    0xF0, 0x80, 0x80, 0x80, 0xF0,
+    0xE0, 0x90, 0x90, 0x90, 0xE0,
+    0xF0, 0x80, 0xF0, 0x80, 0xF0,
+    0xF0, 0x80, 0xF0, 0x80, 0x80
+    ];
+
+const VIP: [u8; FONT_SIZE] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0,
+    0x60, 0x20, 0x20, 0x20, 0x70,
+    0xF0, 0x10, 0xF0, 0x80, 0xF0,
+    0xF0, 0x10, 0xF0, 0x10, 0xF0,
+    0xA0, 0xA0, 0xF0, 0x20, 0x20,
+    0xF0, 0x80, 0xF0, 0x10, 0xF0,
+    0xF0, 0x80, 0xF0, 0x90, 0xF0,
+    0xF0, 0x10, 0x10, 0x10, 0x10,
+    0xF0, 0x90, 0xF0, 0x90, 0xF0,
+    0xF0, 0x90, 0xF0, 0x10, 0xF0,
+    0xF0, 0x90, 0xF0, 0x90, 0x90,
+    0xF0, 0x50, 0x70, 0x50, 0xF0,
+    0xF0, 0x80, 0x80, 0x80, 0xF0,
+    0xF0, 0x50, 0x50, 0x50, 0xF0,
+    0xF0, 0x80, 0xF0, 0x80, 0xF0,
+    0xF0, 0x80, 0xF0, 0x80, 0x80
+    ];
+
+const ETI_660: [u8; FONT_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0,
+    0x20, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0x20, 0xE0, 0x80, 0xE0,
+    0xE0, 0x20, 0xE0, 0x20, 0xE0,
+    0xA0, 0xA0, 0xE0, 0x20, 0x20,
+    0xE0, 0x80, 0xE0, 0x20, 0xE0,
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0,
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0,
+    0x80, 0x80, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x80, 0x80, 0x80, 0xE0,
+    0x20, 0x20, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x80, 0xE0, 0x80, 0xE0,
+    0xE0, 0x80, 0xC0, 0x80, 0x80
+    ];
+
+const DREAM_6800: [u8; FONT_SIZE] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0,
+    0x40, 0x40, 0x40, 0x40, 0x40,
+    0xE0, 0x20, 0xE0, 0x80, 0xE0,
+    0xE0, 0x20, 0xE0, 0x20, 0xE0,
+    0x80, 0xA0, 0xA0, 0xE0, 0x20,
+    0xE0, 0x80, 0xE0, 0x20, 0xE0,
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0,
+    0xE0, 0x20, 0x20, 0x20, 0x20,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0,
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0,
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0,
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0,
+    0xE0, 0x80, 0x80, 0x80, 0xE0,
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0,
+    0xE0, 0x80, 0xE0, 0x80, 0xE0,
+    0xE0, 0x80, 0xC0, 0x80, 0x80
+    ];
+
+const FISH_N_CHIPS: [u8; FONT_SIZE] = [
+    0x60, 0xA0, 0xA0, 0xA0, 0xC0,
+    0x40, 0xC0, 0x40, 0x40, 0xE0,
+    0xC0, 0x20, 0x40, 0x80, 0xE0,
+    0xC0, 0x20, 0x40, 0x20, 0xC0,
+    0x20, 0xA0, 0xE0, 0x20, 0x20,
+    0xE0, 0x80, 0xC0, 0x20, 0xC0,
+    0x40, 0x80, 0xC0, 0xA0, 0x40,
+    0xE0, 0x20, 0x60, 0x40, 0x40,
+    0x40, 0xA0, 0x40, 0xA0, 0x40,
+    0x40, 0xA0, 0x60, 0x20, 0x40,
+    0x40, 0xA0, 0xE0, 0xA0, 0xA0,
+    0xC0, 0xA0, 0xC0, 0xA0, 0xC0,
+    0x60, 0x80, 0x80, 0x80, 0x60,
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0,
+    0xE0, 0x80, 0xC0, 0x80, 0xE0,
+    0xE0, 0x80, 0xC0, 0x80, 0x80
+    ];
+
+// The small hex font used by FX29, as shipped with various historical interpreters
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Font {
+    Standard, // the common font from Cowgod's reference and most modern interpreters
+    Vip, // COSMAC VIP
+    Eti660,
+    Dream6800,
+    FishNChips
+}
+
+const NAMES: [(Font, &str); 5] = [
+    (Font::Standard, "standard"),
+    (Font::Vip, "vip"),
+    (Font::Eti660, "eti660"),
+    (Font::Dream6800, "dream6800"),
+    (Font::FishNChips, "fishnchips")
+];
+
+impl Font {
+    pub fn data(self) -> &'static [u8; FONT_SIZE] {
+        match self {
+            Font::Standard => &STANDARD,
+            Font::Vip => &VIP,
+            Font::Eti660 => &ETI_660,
+            Font::Dream6800 => &DREAM_6800,
+            Font::FishNChips => &FISH_N_CHIPS
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        NAMES.iter().find(|(font, _)| *font == self).unwrap().1
+    }
+}
+
+#[derive(Debug)]
+pub struct ParseFontError;
+
+impl fmt::Display for ParseFontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown font (expected standard, vip, eti660, dream6800 or fishnchips)")
+    }
+}
+
+impl FromStr for Font {
+    type Err = ParseFontError;
+
+    fn from_str(s: &str) -> Result<Font, ParseFontError> {
+        NAMES.iter()
+            .find(|(_, name)| s.eq_ignore_ascii_case(name))
+            .map(|&(font, _)| font)
+            .ok_or(ParseFontError)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseAddressError;
+
+impl fmt::Display for ParseAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected an address at most 0x{:03X}", MAX_FONT_ADDRESS)
+    }
+}
+
+// Parses a --font-address argument, decimal or 0x-prefixed hex
+pub fn parse_address(s: &str) -> Result<u16, ParseAddressError> {
+    let address = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    };
+    address.filter(|&address| address <= MAX_FONT_ADDRESS).ok_or(ParseAddressError)
+}
+
+// A custom font file holds the 80 bytes of sprite data and nothing else
+#[cfg(feature = "std")]
+pub fn read_font_file(path: &Path) -> io::Result<[u8; FONT_SIZE]> {
+    let data = std::fs::read(path)?;
+    if data.len() != FONT_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("font files must be {} bytes, got {}", FONT_SIZE, data.len())));
+    }
+    let mut font = [0; FONT_SIZE];
+    font.copy_from_slice(&data);
+    Ok(font)
+}
+
+// Resolves a --font argument: one of the built-in names, or the path of a custom font file.
+// Errors start with the argument
+#[cfg(feature = "std")]
+pub fn resolve_font(arg: &str) -> Result<[u8; FONT_SIZE], String> {
+    match arg.parse::<Font>() {
+        Ok(font) => Ok(*font.data()),
+        Err(e) if !Path::new(arg).exists() => Err(format!("{}: {}, or a font file", arg, e)),
+        Err(_) => read_font_file(Path::new(arg)).map_err(|e| format!("{}: {}", arg, e))
+    }
+}
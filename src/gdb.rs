@@ -0,0 +1,406 @@
+// A GDB remote serial protocol stub, so a debugger can stop, step and inspect a program
+// over a TCP connection. The registers are V0-VF, I, PC, the two timers, the stack depth
+// and the 16 stack slots, described to the debugger by target.xml; 16-bit ones are sent
+// little-endian. The address space is the machine's 4 KB of memory. Breakpoints are kept
+// here and checked before each instruction, and time passes by cycles: every
+// cycles_per_frame instructions run the timers tick, however long that takes.
+// The debugger has no symbol file, so the labels of Octo source are reached through
+// monitor commands: `monitor labels`, `monitor where` and `monitor break LABEL`
+use crate::chip8::{Chip8, STACK_SIZE};
+use crate::rng::RandomSource;
+use crate::symbols::Symbols;
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+// Signals reported when the program stops
+const SIGINT: u8 = 2; // the debugger asked it to
+const SIGTRAP: u8 = 5; // a step or a breakpoint
+const SIGSEGV: u8 = 11; // a stack fault halted the machine
+
+const INTERRUPT: u8 = 0x03;
+const PACKET_SIZE: usize = 0x4000;
+
+// Register numbers: v0-vf, then these, then the stack slots s0-s15
+const I: usize = 16;
+const PC: usize = 17;
+const DT: usize = 18;
+const ST: usize = 19;
+const SP: usize = 20;
+const STACK: usize = 21;
+const REGISTERS: usize = STACK + STACK_SIZE;
+
+fn register_size(n: usize) -> usize {
+    match n {
+        I | PC => 2,
+        _ if n >= STACK => 2,
+        _ => 1
+    }
+}
+
+fn target_xml() -> String {
+    let mut xml = String::from("<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target version=\"1.0\">\n<feature name=\"org.chip8.core\">\n");
+    let mut reg = |name: String, bits, kind| xml.push_str(&format!("<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>\n", name, bits, kind));
+    for x in 0..16 {
+        reg(format!("v{:x}", x), 8, "uint8");
+    }
+    reg("i".to_string(), 16, "data_ptr");
+    reg("pc".to_string(), 16, "code_ptr");
+    reg("dt".to_string(), 8, "uint8");
+    reg("st".to_string(), 8, "uint8");
+    reg("sp".to_string(), 8, "uint8");
+    for level in 0..STACK_SIZE {
+        reg(format!("s{}", level), 16, "code_ptr");
+    }
+    xml.push_str("</feature>\n</target>\n");
+    xml
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len()).step_by(2).map(|k| u8::from_str_radix(s.get(k..k + 2)?, 16).ok()).collect()
+}
+
+fn number(s: &str) -> Option<usize> {
+    usize::from_str_radix(s, 16).ok()
+}
+
+// "addr,length" as in m and M packets
+fn range(s: &str) -> Option<(usize, usize)> {
+    let (address, length) = s.split_once(',')?;
+    Some((number(address)?, number(length)?))
+}
+
+// One connected debugger
+struct Session {
+    input: BufReader<TcpStream>,
+    output: TcpStream,
+    acks: bool, // until QStartNoAckMode
+    breakpoints: BTreeSet<u16>,
+    cycle: u32, // cycles run in the current frame
+    signal: u8, // why the program last stopped
+    symbols: Symbols
+}
+
+// Serves one debugger until it detaches, kills the session or disconnects. The machine is
+// left as the debugger last had it. symbols are the program's labels, if it has any
+pub fn serve<R: RandomSource>(stream: TcpStream, machine_state: &mut Chip8<R>, symbols: &Symbols) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut session = Session {
+        input: BufReader::new(stream.try_clone()?),
+        output: stream,
+        acks: true,
+        breakpoints: BTreeSet::new(),
+        cycle: 0,
+        signal: SIGTRAP,
+        symbols: symbols.clone()
+    };
+    while let Some(packet) = session.read_packet()? {
+        match packet.as_str() {
+            "k" => return Ok(()),
+            "D" | "D;1" => return session.send("OK"),
+            _ => {}
+        }
+        let Some(reply) = session.handle(&packet, machine_state)? else { return Ok(()) };
+        session.send(&reply)?;
+    }
+    Ok(())
+}
+
+impl Session {
+    // The next packet's contents, or None once the debugger has gone. Interrupts and
+    // acknowledgements between packets are skipped
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+            if self.input.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] != b'$' {
+                continue;
+            }
+            let mut packet = Vec::new();
+            if self.input.read_until(b'#', &mut packet)? == 0 || packet.pop() != Some(b'#') {
+                return Ok(None);
+            }
+            let mut checksum = [0; 2];
+            self.input.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum).ok().and_then(|s| u8::from_str_radix(s, 16).ok());
+            let valid = expected == Some(packet.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)));
+            if self.acks {
+                self.output.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&packet).into_owned()));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        let packet = format!("${}#{:02x}", data, checksum);
+        loop {
+            self.output.write_all(packet.as_bytes())?;
+            if !self.acks {
+                return Ok(());
+            }
+            // Sent again on a '-'
+            let mut ack = [0];
+            loop {
+                if self.input.read(&mut ack)? == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                if ack[0] == b'+' || ack[0] == b'-' {
+                    break;
+                }
+            }
+            if ack[0] == b'+' {
+                return Ok(());
+            }
+        }
+    }
+
+    // The reply to a packet, or None when the debugger goes away while the program runs.
+    // Packets the stub doesn't know get the empty reply, which tells the debugger so
+    fn handle<R: RandomSource>(&mut self, packet: &str, machine_state: &mut Chip8<R>) -> io::Result<Option<String>> {
+        let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => format!("S{:02x}", self.signal),
+            "g" => hex(&registers(machine_state)),
+            "G" => match unhex(arguments) {
+                Some(bytes) if bytes.len() == registers(machine_state).len() => {
+                    for (n, value) in values(&bytes).enumerate().take(SP) {
+                        set_register(machine_state, n, value);
+                    }
+                    "OK".to_string()
+                },
+                _ => "E00".to_string()
+            },
+            "p" => match number(arguments).filter(|&n| n < REGISTERS) {
+                Some(n) => {
+                    let offset: usize = (0..n).map(register_size).sum();
+                    hex(&registers(machine_state)[offset..offset + register_size(n)])
+                },
+                None => "E00".to_string()
+            },
+            "P" => {
+                let register = arguments.split_once('=').and_then(|(n, value)| Some((number(n)?, unhex(value)?)));
+                match register {
+                    Some((n, value)) if n < SP && value.len() == register_size(n) => {
+                        set_register(machine_state, n, little_endian(&value));
+                        "OK".to_string()
+                    },
+                    // The stack is only changed by running the program
+                    Some((n, _)) if n < REGISTERS => "E01".to_string(),
+                    _ => "E00".to_string()
+                }
+            },
+            "m" => match range(arguments) {
+                Some((address, length)) if address < 4096 => {
+                    let end = address.saturating_add(length).min(4096);
+                    hex(&machine_state.memory()[address..end])
+                },
+                _ => "E01".to_string()
+            },
+            "M" => {
+                let write = arguments.split_once(':').and_then(|(range_text, data)| Some((range(range_text)?, unhex(data)?)));
+                match write {
+                    Some(((address, length), data)) if data.len() == length && address.saturating_add(length) <= 4096 => {
+                        machine_state.write_memory(address as u16, &data);
+                        "OK".to_string()
+                    },
+                    _ => "E01".to_string()
+                }
+            },
+            "c" | "s" => {
+                if let Some(address) = number(arguments) {
+                    machine_state.set_pc(address as u16);
+                }
+                match self.resume(machine_state, command == "s")? {
+                    Some(signal) => {
+                        self.signal = signal;
+                        format!("S{:02x}", signal)
+                    },
+                    None => return Ok(None)
+                }
+            },
+            // Software and hardware breakpoints are the same thing here
+            "Z" | "z" => {
+                let breakpoint = arguments.split(',').collect::<Vec<_>>();
+                match breakpoint.as_slice() {
+                    [kind, address, ..] if *kind == "0" || *kind == "1" => match number(address).filter(|&address| address < 4096) {
+                        Some(address) => {
+                            if command == "Z" {
+                                self.breakpoints.insert(address as u16);
+                            } else {
+                                self.breakpoints.remove(&(address as u16));
+                            }
+                            "OK".to_string()
+                        },
+                        None => "E01".to_string()
+                    },
+                    // Watchpoints
+                    _ => String::new()
+                }
+            },
+            "H" | "T" => "OK".to_string(),
+            "q" if packet.starts_with("qRcmd,") => match unhex(&packet["qRcmd,".len()..]) {
+                Some(command) => hex(self.monitor(&String::from_utf8_lossy(&command), machine_state).as_bytes()),
+                None => "E01".to_string()
+            },
+            "q" | "Q" => self.query(packet),
+            _ => String::new()
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            return format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", PACKET_SIZE);
+        }
+        if let Some(request) = packet.strip_prefix("qXfer:features:read:") {
+            let Some((annex, window)) = request.split_once(':') else { return "E00".to_string() };
+            let (Some((offset, length)), "target.xml") = (range(window), annex) else { return "E00".to_string() };
+            let xml = target_xml();
+            let start = offset.min(xml.len());
+            let end = start.saturating_add(length).min(xml.len());
+            let more = if end < xml.len() { "m" } else { "l" };
+            return format!("{}{}", more, &xml[start..end]);
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.acks = false;
+                "OK".to_string()
+            },
+            "qAttached" => "1".to_string(),
+            "qC" => "QC1".to_string(),
+            "qfThreadInfo" => "m1".to_string(),
+            "qsThreadInfo" => "l".to_string(),
+            _ => String::new()
+        }
+    }
+
+    // The output of a monitor command, which the debugger prints
+    fn monitor<R: RandomSource>(&mut self, command: &str, machine_state: &Chip8<R>) -> String {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        match words.as_slice() {
+            ["labels"] => {
+                let mut labels: Vec<(u16, &str)> = self.symbols.labels().map(|(name, address)| (address, name)).collect();
+                labels.sort();
+                labels.iter().map(|(address, name)| format!("{:#05x} {}\n", address, name)).collect()
+            },
+            ["where"] => match machine_state.fault() {
+                Some(fault) => format!("{}\n", self.symbols.fault(fault)),
+                None => format!("{}\n", self.symbols.describe(machine_state.pc()))
+            },
+            ["break", label] => match self.symbols.address(label) {
+                Some(address) => {
+                    self.breakpoints.insert(address);
+                    format!("breakpoint at {}\n", self.symbols.describe(address))
+                },
+                None => format!("no label {}\n", label)
+            },
+            _ => "monitor commands: labels, where, break LABEL\n".to_string()
+        }
+    }
+
+    // Runs one instruction, or until a breakpoint, a fault or an interrupt from the
+    // debugger. None if the debugger disconnected meanwhile
+    fn resume<R: RandomSource>(&mut self, machine_state: &mut Chip8<R>, step: bool) -> io::Result<Option<u8>> {
+        loop {
+            if machine_state.fault().is_some() {
+                return Ok(Some(SIGSEGV));
+            }
+            machine_state.emulate_cycle();
+            self.cycle += 1;
+            let frame_ended = self.cycle >= machine_state.cycles_per_frame();
+            if frame_ended {
+                machine_state.end_frame();
+                self.cycle = 0;
+            }
+            if machine_state.fault().is_some() {
+                return Ok(Some(SIGSEGV));
+            }
+            if step || self.breakpoints.contains(&machine_state.pc()) {
+                return Ok(Some(SIGTRAP));
+            }
+            // Checked once a frame, without waiting for anything to arrive
+            if frame_ended {
+                match self.interrupted()? {
+                    Some(true) => return Ok(Some(SIGINT)),
+                    Some(false) => {},
+                    None => return Ok(None)
+                }
+            }
+        }
+    }
+
+    // Whether the debugger has sent an interrupt, None if it has disconnected. Stray
+    // acknowledgements ahead of it are dropped
+    fn interrupted(&mut self) -> io::Result<Option<bool>> {
+        self.input.get_ref().set_nonblocking(true)?;
+        let received = self.input.fill_buf().map(|bytes| {
+            let acks = bytes.iter().take_while(|&&byte| byte == b'+' || byte == b'-').count();
+            (bytes.is_empty(), acks, bytes.get(acks) == Some(&INTERRUPT))
+        });
+        self.input.get_ref().set_nonblocking(false)?;
+        match received {
+            Ok((true, _, _)) => Ok(None),
+            Ok((false, acks, interrupt)) => {
+                self.input.consume(acks + interrupt as usize);
+                Ok(Some(interrupt))
+            },
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(Some(false)),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+// The g packet's bytes, in register order
+fn registers<R: RandomSource>(machine_state: &Chip8<R>) -> Vec<u8> {
+    let mut bytes: Vec<u8> = (0..16).map(|x| machine_state.v(x)).collect();
+    bytes.extend_from_slice(&machine_state.i().to_le_bytes());
+    bytes.extend_from_slice(&machine_state.pc().to_le_bytes());
+    bytes.push(machine_state.delay_timer());
+    bytes.push(machine_state.sound_timer());
+    let stack: Vec<u16> = machine_state.stack().collect();
+    bytes.push(stack.len() as u8);
+    for level in 0..STACK_SIZE {
+        bytes.extend_from_slice(&stack.get(level).copied().unwrap_or(0).to_le_bytes());
+    }
+    bytes
+}
+
+// Register values out of g packet bytes
+fn values(bytes: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    let mut offset = 0;
+    (0..REGISTERS).map_while(move |n| {
+        let value = bytes.get(offset..offset + register_size(n))?;
+        offset += value.len();
+        Some(little_endian(value))
+    })
+}
+
+fn little_endian(bytes: &[u8]) -> u16 {
+    match *bytes {
+        [low, high] => u16::from_le_bytes([low, high]),
+        [byte] => byte as u16,
+        _ => 0
+    }
+}
+
+fn set_register<R: RandomSource>(machine_state: &mut Chip8<R>, n: usize, value: u16) {
+    match n {
+        I => machine_state.set_i(value),
+        PC => machine_state.set_pc(value),
+        DT => machine_state.set_delay_timer(value as u8),
+        ST => machine_state.set_sound_timer(value as u8),
+        x => machine_state.set_v(x, value as u8)
+    }
+}
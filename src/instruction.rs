@@ -0,0 +1,201 @@
+// The CHIP-8 instruction set, decoded. Everything that reads or writes opcodes
+// (the interpreter, tools, tests) goes through decode and encode here.
+use core::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Sys { addr: u16 }, // (0x0NNN) Execute machine language subroutine at address NNN (ignored)
+    Cls, // (0x00E0) Clear screen
+    Rts, // (0x00EE) Return from a subroutine
+    Jump { addr: u16 }, // (0x1NNN) Jump to address NNN
+    Call { addr: u16 }, // (0x2NNN) Execute subroutine starting at address NNN
+    Ske { x: u8, nn: u8 }, // (0x3XNN) Skip the following instruction if the value of register VX equals NN
+    Skne { x: u8, nn: u8 }, // (0x4XNN) Skip the following instruction if the value of register VX does not equal NN
+    Skre { x: u8, y: u8 }, // (0x5XY0) Skip the following instruction if the value of register VX is equal to the value of register VY
+    Load { x: u8, nn: u8 }, // (0x6XNN) Store number NN in register VX
+    Add { x: u8, nn: u8 }, // (0x7XNN) Add the value NN to register VX
+    Move { x: u8, y: u8 }, // (0x8XY0) Store the value of register VY in register VX
+    Or { x: u8, y: u8 }, // (0x8XY1) Set VX to VX OR VY
+    And { x: u8, y: u8 }, // (0x8XY2) Set VX to VX AND VY
+    Xor { x: u8, y: u8 }, // (0x8XY3) Set VX to VX XOR VY
+    Addr { x: u8, y: u8 }, // (0x8XY4) Add the value of register VY to register VX. Set VF to 01 if a carry occurs; set VF to 00 if a carry does not occur
+    Sub { x: u8, y: u8 }, // (0x8XY5) Subtract the value of register VY from register VX. Set VF to 00 if a borrow occurs; set VF to 01 if a borrow does not occur
+    Shr { x: u8, y: u8 }, // (0x8XY6) Store the value of register VY shifted right one bit in register VX. Set register VF to the least significant bit prior to the shift. VY is unchanged
+    Subn { x: u8, y: u8 }, // (0x8XY7) Set register VX to the value of VY minus VX. Set VF to 00 if a borrow occurs. Set VF to 01 if a borrow does not occur
+    Shl { x: u8, y: u8 }, // (0x8XYE) Store the value of register VY shifted left one bit in register VX. Set register VF to the most significant bit prior to the shift. VY is unchanged
+    Skrne { x: u8, y: u8 }, // (0x9XY0) Skip the following instruction if the value of register VX is not equal to the value of register VY
+    Loadi { addr: u16 }, // (0xANNN) Store memory address NNN in register I
+    Jump0 { addr: u16 }, // (0xBNNN) Jump to address NNN + V0, or NNN + VX with Quirks::jump_uses_vx
+    Rand { x: u8, nn: u8 }, // (0xCXNN) Set VX to a random number with a mask of NN
+    Draw { x: u8, y: u8, n: u8 }, // (0xDXYN) Draw a sprite at position VX, VY with N bytes of sprite data starting at the address stored in I. Set VF to 01 if any set pixels are changed to unset, and 00 otherwise
+    Skpr { x: u8 }, // (0xEX9E) Skip the following instruction if the key corresponding to the hex value currently stored in register VX is pressed
+    Skup { x: u8 }, // (0xEXA1) Skip the following instruction if the key corresponding to the hex value currently stored in register VX is not pressed
+    Moved { x: u8 }, // (0xFX07) Store the current value of the delay timer in register VX
+    Keyd { x: u8 }, // (0xFX0A) Wait for a keypress and store the result in register VX
+    Loadd { x: u8 }, // (0xFX15) Set the delay timer to the value of register VX
+    Loads { x: u8 }, // (0xFX18) Set the sound timer to the value of register VX
+    Addi { x: u8 }, // (0xFX1E) Add the value stored in register VX in register I
+    Ldspr { x: u8 }, // (0xFX29) Set register I to the memory address of the sprite data corresponding to the hexadecimal digit stored in register VX
+    Bcd { x: u8 }, // (0xFX33) Store the binary-coded decimal equivalent of the value stored in register VX at addresses I, I+1, and I+2
+    Stor { x: u8 }, // (0xFX55) Store the values of registers V0 to VX inclusive in memory starting at address I. I is set to I+X+1 after operation, depending on quirks
+    Read { x: u8 }, // (0xFX65) Fill registers V0 to VX inclusive with the values stored in memory starting at address I. I is set to I+X+1 after operation, depending on quirks
+}
+
+// An opcode that isn't part of the instruction set
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeError(pub u16);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "undefined opcode {:04X}", self.0)
+    }
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+        let x = (opcode >> 8 & 0xF) as u8;
+        let y = (opcode >> 4 & 0xF) as u8;
+        let n = (opcode & 0xF) as u8;
+        let nn = (opcode & 0xFF) as u8;
+        let addr = opcode & 0xFFF;
+        let instruction = match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => Instruction::Cls,
+                0x00EE => Instruction::Rts,
+                _ => Instruction::Sys { addr }
+            },
+            0x1000 => Instruction::Jump { addr },
+            0x2000 => Instruction::Call { addr },
+            0x3000 => Instruction::Ske { x, nn },
+            0x4000 => Instruction::Skne { x, nn },
+            0x5000 if n == 0 => Instruction::Skre { x, y },
+            0x6000 => Instruction::Load { x, nn },
+            0x7000 => Instruction::Add { x, nn },
+            0x8000 => match n {
+                0x0 => Instruction::Move { x, y },
+                0x1 => Instruction::Or { x, y },
+                0x2 => Instruction::And { x, y },
+                0x3 => Instruction::Xor { x, y },
+                0x4 => Instruction::Addr { x, y },
+                0x5 => Instruction::Sub { x, y },
+                0x6 => Instruction::Shr { x, y },
+                0x7 => Instruction::Subn { x, y },
+                0xE => Instruction::Shl { x, y },
+                _ => return Err(DecodeError(opcode))
+            },
+            0x9000 if n == 0 => Instruction::Skrne { x, y },
+            0xA000 => Instruction::Loadi { addr },
+            0xB000 => Instruction::Jump0 { addr },
+            0xC000 => Instruction::Rand { x, nn },
+            0xD000 => Instruction::Draw { x, y, n },
+            0xE000 => match nn {
+                0x9E => Instruction::Skpr { x },
+                0xA1 => Instruction::Skup { x },
+                _ => return Err(DecodeError(opcode))
+            },
+            0xF000 => match nn {
+                0x07 => Instruction::Moved { x },
+                0x0A => Instruction::Keyd { x },
+                0x15 => Instruction::Loadd { x },
+                0x18 => Instruction::Loads { x },
+                0x1E => Instruction::Addi { x },
+                0x29 => Instruction::Ldspr { x },
+                0x33 => Instruction::Bcd { x },
+                0x55 => Instruction::Stor { x },
+                0x65 => Instruction::Read { x },
+                _ => return Err(DecodeError(opcode))
+            },
+            _ => return Err(DecodeError(opcode))
+        };
+        Ok(instruction)
+    }
+
+    // Operands are masked to their field widths. Decoding the result gives back any
+    // instruction decode returned, but not one with operands out of range, or Sys 0x0E0
+    // and 0x0EE, which are the opcodes of Cls and Rts
+    pub fn encode(self) -> u16 {
+        let xy = |high: u16, x: u8, y: u8, low: u16| high | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | low;
+        let xnn = |high: u16, x: u8, nn: u8| high | (x as u16 & 0xF) << 8 | nn as u16;
+        let nnn = |high: u16, addr: u16| high | addr & 0xFFF;
+        match self {
+            Instruction::Sys { addr } => nnn(0x0000, addr),
+            Instruction::Cls => 0x00E0,
+            Instruction::Rts => 0x00EE,
+            Instruction::Jump { addr } => nnn(0x1000, addr),
+            Instruction::Call { addr } => nnn(0x2000, addr),
+            Instruction::Ske { x, nn } => xnn(0x3000, x, nn),
+            Instruction::Skne { x, nn } => xnn(0x4000, x, nn),
+            Instruction::Skre { x, y } => xy(0x5000, x, y, 0x0),
+            Instruction::Load { x, nn } => xnn(0x6000, x, nn),
+            Instruction::Add { x, nn } => xnn(0x7000, x, nn),
+            Instruction::Move { x, y } => xy(0x8000, x, y, 0x0),
+            Instruction::Or { x, y } => xy(0x8000, x, y, 0x1),
+            Instruction::And { x, y } => xy(0x8000, x, y, 0x2),
+            Instruction::Xor { x, y } => xy(0x8000, x, y, 0x3),
+            Instruction::Addr { x, y } => xy(0x8000, x, y, 0x4),
+            Instruction::Sub { x, y } => xy(0x8000, x, y, 0x5),
+            Instruction::Shr { x, y } => xy(0x8000, x, y, 0x6),
+            Instruction::Subn { x, y } => xy(0x8000, x, y, 0x7),
+            Instruction::Shl { x, y } => xy(0x8000, x, y, 0xE),
+            Instruction::Skrne { x, y } => xy(0x9000, x, y, 0x0),
+            Instruction::Loadi { addr } => nnn(0xA000, addr),
+            Instruction::Jump0 { addr } => nnn(0xB000, addr),
+            Instruction::Rand { x, nn } => xnn(0xC000, x, nn),
+            Instruction::Draw { x, y, n } => xy(0xD000, x, y, n as u16 & 0xF),
+            Instruction::Skpr { x } => xnn(0xE000, x, 0x9E),
+            Instruction::Skup { x } => xnn(0xE000, x, 0xA1),
+            Instruction::Moved { x } => xnn(0xF000, x, 0x07),
+            Instruction::Keyd { x } => xnn(0xF000, x, 0x0A),
+            Instruction::Loadd { x } => xnn(0xF000, x, 0x15),
+            Instruction::Loads { x } => xnn(0xF000, x, 0x18),
+            Instruction::Addi { x } => xnn(0xF000, x, 0x1E),
+            Instruction::Ldspr { x } => xnn(0xF000, x, 0x29),
+            Instruction::Bcd { x } => xnn(0xF000, x, 0x33),
+            Instruction::Stor { x } => xnn(0xF000, x, 0x55),
+            Instruction::Read { x } => xnn(0xF000, x, 0x65),
+        }
+    }
+}
+
+// Disassembles in the usual CHIP-8 mnemonics, e.g. "LD V0, 0x05" or "DRW V1, V2, 5"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys { addr } => write!(f, "SYS {:#05x}", addr),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Rts => write!(f, "RET"),
+            Instruction::Jump { addr } => write!(f, "JP {:#05x}", addr),
+            Instruction::Call { addr } => write!(f, "CALL {:#05x}", addr),
+            Instruction::Ske { x, nn } => write!(f, "SE V{:X}, {:#04x}", x, nn),
+            Instruction::Skne { x, nn } => write!(f, "SNE V{:X}, {:#04x}", x, nn),
+            Instruction::Skre { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::Load { x, nn } => write!(f, "LD V{:X}, {:#04x}", x, nn),
+            Instruction::Add { x, nn } => write!(f, "ADD V{:X}, {:#04x}", x, nn),
+            Instruction::Move { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::Addr { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::Skrne { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::Loadi { addr } => write!(f, "LD I, {:#05x}", addr),
+            Instruction::Jump0 { addr } => write!(f, "JP V0, {:#05x}", addr),
+            Instruction::Rand { x, nn } => write!(f, "RND V{:X}, {:#04x}", x, nn),
+            Instruction::Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skpr { x } => write!(f, "SKP V{:X}", x),
+            Instruction::Skup { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::Moved { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::Keyd { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::Loadd { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::Loads { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::Addi { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::Ldspr { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::Bcd { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::Stor { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::Read { x } => write!(f, "LD V{:X}, [I]", x),
+        }
+    }
+}
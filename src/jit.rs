@@ -0,0 +1,381 @@
+// A dynamic recompiler for x86-64. Straight runs of register, I and delay timer
+// instructions are translated into native code working on the Chip8 struct in place,
+// ending in a native jump or skip, or in a call back into Chip8 for DXYN, EX9E and EXA1.
+// Everything else (calls, returns, FX55 and the like) is left to the interpreter.
+//
+// A block is dropped when the ROM writes into it, and an address whose block keeps
+// being rewritten is handed back to the interpreter for good. Blocks leave opcode set to
+// the last instruction they ran, as the interpreter would, so save states match.
+use crate::chip8::Quirks;
+use crate::instruction::Instruction;
+
+use std::ptr;
+
+const CODE_SIZE: usize = 1 << 20;
+const ADDRESS_MASK: usize = 0xFFF;
+// Instructions per block, so a block never runs far past the end of a frame
+const MAX_BLOCK_LEN: u32 = 32;
+// Times a block can be rewritten before its address is only interpreted
+const MAX_INVALIDATIONS: u8 = 4;
+
+// Called with a pointer to the Chip8 the block was compiled for
+pub type BlockFn = unsafe extern "C" fn(machine: *mut u8);
+
+// Where the registers are inside Chip8, and the function blocks call with
+// (machine, opcode) to run the instructions they don't translate themselves
+pub struct Target {
+    pub v: i32,
+    pub i: i32,
+    pub pc: i32,
+    pub opcode: i32,
+    pub delay_timer: i32,
+    pub helper: usize
+}
+
+#[derive(Clone, Copy)]
+enum Entry {
+    Unknown,
+    Block { offset: usize, len: u32, end: u16 }, // end is the address after the last instruction
+    Interpret
+}
+
+pub struct Jit {
+    code: *mut u8, // CODE_SIZE bytes, only ever writable or executable, never both
+    used: usize,
+    entries: Vec<Entry>, // by start address
+    in_block: Vec<bool>, // by address, whether any block was compiled from the byte
+    invalidations: Vec<u8> // by start address
+}
+
+// The code buffer belongs to this Jit alone
+unsafe impl Send for Jit {}
+
+impl Jit {
+    // None if the system won't map memory that can be written, then executed
+    pub fn new() -> Option<Jit> {
+        let code = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                CODE_SIZE,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0
+            )
+        };
+        if code == libc::MAP_FAILED {
+            return None;
+        }
+        if unsafe { libc::mprotect(code, CODE_SIZE, libc::PROT_READ | libc::PROT_EXEC) } != 0 {
+            unsafe { libc::munmap(code, CODE_SIZE) };
+            return None;
+        }
+        Some(Jit {
+            code: code as *mut u8,
+            used: 0,
+            entries: vec![Entry::Unknown; ADDRESS_MASK + 1],
+            in_block: vec![false; ADDRESS_MASK + 1],
+            invalidations: vec![0; ADDRESS_MASK + 1]
+        })
+    }
+
+    // The block starting at pc and how many instructions it executes, compiling it if
+    // needed. None when the instruction at pc has to go through the interpreter
+    pub fn block(&mut self, memory: &[u8; 4096], pc: u16, quirks: Quirks, target: &Target) -> Option<(BlockFn, u32)> {
+        let start = pc as usize & ADDRESS_MASK;
+        if let Entry::Unknown = self.entries[start] {
+            self.entries[start] = self.compile(memory, start, quirks, target);
+        }
+        match self.entries[start] {
+            Entry::Block { offset, len, .. } => {
+                let code = unsafe { std::mem::transmute::<*mut u8, BlockFn>(self.code.add(offset)) };
+                Some((code, len))
+            },
+            _ => None
+        }
+    }
+
+    // Everything is compiled again, after the ROM or the quirks change
+    pub fn flush(&mut self) {
+        self.used = 0;
+        self.entries.iter_mut().for_each(|entry| *entry = Entry::Unknown);
+        self.in_block.iter_mut().for_each(|byte| *byte = false);
+        self.invalidations.iter_mut().for_each(|count| *count = 0);
+    }
+
+    // The ROM wrote to address: drop every block compiled from it
+    pub fn invalidate(&mut self, address: usize) {
+        if !self.in_block[address] {
+            return;
+        }
+        for start in 0..self.entries.len() {
+            if let Entry::Block { end, .. } = self.entries[start] {
+                if (start..end as usize).contains(&address) {
+                    self.invalidations[start] += 1;
+                    self.entries[start] = if self.invalidations[start] >= MAX_INVALIDATIONS {
+                        Entry::Interpret
+                    } else {
+                        Entry::Unknown
+                    };
+                }
+            }
+        }
+        self.in_block.iter_mut().for_each(|byte| *byte = false);
+        for start in 0..self.entries.len() {
+            if let Entry::Block { end, .. } = self.entries[start] {
+                self.in_block[start..end as usize].iter_mut().for_each(|byte| *byte = true);
+            }
+        }
+    }
+
+    fn compile(&mut self, memory: &[u8; 4096], start: usize, quirks: Quirks, target: &Target) -> Entry {
+        let mut asm = Assembler { code: Vec::new(), target, last: 0 };
+        let mut address = start;
+        let mut len = 0;
+        loop {
+            // An instruction at the end of memory wraps around, the interpreter deals with it
+            if len == MAX_BLOCK_LEN || address >= ADDRESS_MASK {
+                asm.exit(address as u16);
+                break;
+            }
+            let opcode = u16::from_be_bytes([memory[address], memory[address + 1]]);
+            let next = address as u16 + 2;
+            let previous = asm.last;
+            asm.last = opcode;
+            // Whether the instruction left the block
+            let exits = match Instruction::decode(opcode) {
+                Ok(instruction) if asm.native(instruction, quirks) => false,
+                Ok(Instruction::Jump { addr }) => {
+                    asm.exit(addr);
+                    true
+                },
+                Ok(Instruction::Ske { x, nn }) => {
+                    asm.mem(&[0x80], 7, asm.target.v + x as i32); // cmp byte [vx], nn
+                    asm.code.push(nn);
+                    asm.skip(next, JNE);
+                    true
+                },
+                Ok(Instruction::Skne { x, nn }) => {
+                    asm.mem(&[0x80], 7, asm.target.v + x as i32);
+                    asm.code.push(nn);
+                    asm.skip(next, JE);
+                    true
+                },
+                Ok(Instruction::Skre { x, y }) => {
+                    asm.load_al(asm.target.v + x as i32);
+                    asm.mem(&[0x3A], AL, asm.target.v + y as i32); // cmp al, [vy]
+                    asm.skip(next, JNE);
+                    true
+                },
+                Ok(Instruction::Skrne { x, y }) => {
+                    asm.load_al(asm.target.v + x as i32);
+                    asm.mem(&[0x3A], AL, asm.target.v + y as i32);
+                    asm.skip(next, JE);
+                    true
+                },
+                Ok(Instruction::Draw { .. }) | Ok(Instruction::Skpr { .. }) | Ok(Instruction::Skup { .. }) => {
+                    asm.set_pc(address as u16);
+                    asm.tail_call(opcode);
+                    true
+                },
+                _ => {
+                    asm.last = previous;
+                    asm.exit(address as u16);
+                    break;
+                }
+            };
+            len += 1;
+            address += 2;
+            if exits {
+                break;
+            }
+        }
+        if len == 0 {
+            return Entry::Interpret;
+        }
+        if self.used + asm.code.len() > CODE_SIZE {
+            self.flush();
+        }
+        let offset = self.used;
+        if !self.write(offset, &asm.code) {
+            return Entry::Interpret;
+        }
+        self.used += asm.code.len();
+        self.in_block[start..address].iter_mut().for_each(|byte| *byte = true);
+        Entry::Block { offset, len, end: address as u16 }
+    }
+
+    // Copies code in at offset, making the pages it lands on writable for the copy and
+    // executable again after it. False if the system refused either
+    fn write(&mut self, offset: usize, code: &[u8]) -> bool {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let start = offset / page * page;
+        let pages = unsafe { self.code.add(start) } as *mut libc::c_void;
+        let len = offset + code.len() - start;
+        if unsafe { libc::mprotect(pages, len, libc::PROT_READ | libc::PROT_WRITE) } != 0 {
+            return false;
+        }
+        unsafe { ptr::copy_nonoverlapping(code.as_ptr(), self.code.add(offset), code.len()) };
+        unsafe { libc::mprotect(pages, len, libc::PROT_READ | libc::PROT_EXEC) == 0 }
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.code as *mut libc::c_void, CODE_SIZE) };
+    }
+}
+
+const AL: u8 = 0;
+const CL: u8 = 1;
+const JE: u8 = 0x74;
+const JNE: u8 = 0x75;
+const RET: u8 = 0xC3;
+
+// Emits code for a block. rdi holds the Chip8 pointer throughout, al and cl are scratch
+struct Assembler<'a> {
+    code: Vec<u8>,
+    target: &'a Target,
+    last: u16 // the opcode of the last instruction the block runs so far
+}
+
+impl Assembler<'_> {
+    // Translates instructions that just fall through to the next one, returning false
+    // for anything else
+    fn native(&mut self, instruction: Instruction, quirks: Quirks) -> bool {
+        let target = self.target;
+        let v = |register: u8| target.v + register as i32;
+        let vf = v(0xF);
+        match instruction {
+            Instruction::Sys { .. } => {},
+            Instruction::Load { x, nn } => {
+                self.mem(&[0xC6], 0, v(x)); // mov byte [vx], nn
+                self.code.push(nn);
+            },
+            Instruction::Add { x, nn } => {
+                self.mem(&[0x80], 0, v(x)); // add byte [vx], nn
+                self.code.push(nn);
+            },
+            Instruction::Move { x, y } => {
+                self.load_al(v(y));
+                self.store_al(v(x));
+            },
+            Instruction::Or { x, y } | Instruction::And { x, y } | Instruction::Xor { x, y } => {
+                let op = match instruction {
+                    Instruction::Or { .. } => 0x0A,
+                    Instruction::And { .. } => 0x22,
+                    _ => 0x32
+                };
+                self.load_al(v(x));
+                self.mem(&[op], AL, v(y)); // or/and/xor al, [vy]
+                self.store_al(v(x));
+                if quirks.vf_reset {
+                    self.mem(&[0xC6], 0, vf);
+                    self.code.push(0);
+                }
+            },
+            Instruction::Addr { x, y } => {
+                self.load_al(v(x));
+                self.mem(&[0x02], AL, v(y)); // add al, [vy]
+                self.code.extend([0x0F, 0x92, 0xC1]); // setc cl
+                self.store_al(v(x));
+                self.mem(&[0x88], CL, vf);
+            },
+            Instruction::Sub { x, y } => {
+                self.load_al(v(x));
+                self.mem(&[0x2A], AL, v(y)); // sub al, [vy]
+                self.code.extend([0x0F, 0x93, 0xC1]); // setnc cl
+                self.store_al(v(x));
+                self.mem(&[0x88], CL, vf);
+            },
+            Instruction::Subn { x, y } => {
+                self.load_al(v(y));
+                self.mem(&[0x2A], AL, v(x)); // sub al, [vx]
+                self.code.extend([0x0F, 0x93, 0xC1]);
+                self.store_al(v(x));
+                self.mem(&[0x88], CL, vf);
+            },
+            Instruction::Shr { x, y } => {
+                self.load_al(v(if quirks.shift_uses_vy { y } else { x }));
+                self.code.extend([0x88, 0xC1, 0x80, 0xE1, 0x01]); // mov cl, al; and cl, 1
+                self.code.extend([0xD0, 0xE8]); // shr al, 1
+                self.store_al(v(x));
+                self.mem(&[0x88], CL, vf);
+            },
+            Instruction::Shl { x, y } => {
+                self.load_al(v(if quirks.shift_uses_vy { y } else { x }));
+                self.code.extend([0x88, 0xC1, 0xC0, 0xE9, 0x07]); // mov cl, al; shr cl, 7
+                self.code.extend([0xD0, 0xE0]); // shl al, 1
+                self.store_al(v(x));
+                self.mem(&[0x88], CL, vf);
+            },
+            Instruction::Loadi { addr } => {
+                self.mem(&[0x66, 0xC7], 0, target.i); // mov word [i], addr
+                self.code.extend(addr.to_le_bytes());
+            },
+            Instruction::Addi { x } => {
+                self.mem(&[0x0F, 0xB6], AL, v(x)); // movzx eax, byte [vx]
+                self.mem(&[0x66, 0x01], AL, target.i); // add word [i], ax
+            },
+            Instruction::Moved { x } => {
+                self.load_al(target.delay_timer);
+                self.store_al(v(x));
+            },
+            Instruction::Loadd { x } => {
+                self.load_al(v(x));
+                self.store_al(target.delay_timer);
+            },
+            _ => return false
+        }
+        true
+    }
+
+    // An instruction with a [rdi + displacement] operand
+    fn mem(&mut self, opcode: &[u8], reg: u8, displacement: i32) {
+        self.code.extend(opcode);
+        self.code.push(0x87 | reg << 3);
+        self.code.extend(displacement.to_le_bytes());
+    }
+
+    fn load_al(&mut self, displacement: i32) {
+        self.mem(&[0x8A], AL, displacement);
+    }
+
+    fn store_al(&mut self, displacement: i32) {
+        self.mem(&[0x88], AL, displacement);
+    }
+
+    fn set_pc(&mut self, pc: u16) {
+        self.mem(&[0x66, 0xC7], 0, self.target.pc); // mov word [pc], imm16
+        self.code.extend(pc.to_le_bytes());
+    }
+
+    fn set_opcode(&mut self) {
+        self.mem(&[0x66, 0xC7], 0, self.target.opcode); // mov word [opcode], imm16
+        self.code.extend(self.last.to_le_bytes());
+    }
+
+    fn exit(&mut self, pc: u16) {
+        self.set_opcode();
+        self.set_pc(pc);
+        self.code.push(RET);
+    }
+
+    // After a compare: pc moves past the next instruction unless the jump is taken
+    fn skip(&mut self, next: u16, no_skip: u8) {
+        self.set_opcode(); // a mov, which leaves the flags alone
+        self.set_pc(next);
+        self.code.extend([no_skip, 9]); // over the second set_pc
+        self.set_pc(next + 2);
+        self.code.push(RET);
+    }
+
+    // Jumps to the helper with (rdi, opcode), which returns straight to the block's caller
+    fn tail_call(&mut self, opcode: u16) {
+        self.code.push(0xBE); // mov esi, opcode
+        self.code.extend((opcode as u32).to_le_bytes());
+        self.code.extend([0x48, 0xB8]); // mov rax, helper
+        self.code.extend((self.target.helper as u64).to_le_bytes());
+        self.code.extend([0xFF, 0xE0]); // jmp rax
+    }
+}
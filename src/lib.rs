@@ -0,0 +1,37 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod asm;
+pub mod audio;
+pub mod builder;
+#[cfg(feature = "cartridge")]
+pub mod cartridge;
+pub mod chip8;
+#[cfg(feature = "database")]
+pub mod database;
+#[cfg(feature = "std")]
+pub mod detect;
+pub mod font;
+#[cfg(feature = "std")]
+pub mod gdb;
+pub mod instruction;
+#[cfg(feature = "jit")]
+mod jit;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+pub mod rng;
+#[cfg(feature = "cartridge")]
+pub mod settings;
+#[cfg(feature = "std")]
+pub mod symbols;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod watch;
+
+#[cfg(all(feature = "jit", not(all(target_arch = "x86_64", unix))))]
+compile_error!("the jit feature needs x86-64 and a Unix-like system");
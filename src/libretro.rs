@@ -0,0 +1,526 @@
+// libretro core, so the interpreter can run inside RetroArch and other libretro frontends.
+// Built as a cdylib with `cargo rustc --lib --crate-type cdylib --features libretro`.
+use crate::audio::AudioConfig;
+use crate::chip8::{Chip8, Platform, Quirks, CYCLES_PER_FRAME, MAX_ROM_SIZE, STATE_SIZE};
+use crate::font::{Font, DEFAULT_FONT_ADDRESS};
+use crate::rng::XorShift;
+
+use std::ffi::{c_void, CStr};
+use std::os::raw::{c_char, c_uint};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const RETRO_API_VERSION: c_uint = 1;
+const RETRO_REGION_NTSC: c_uint = 0;
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+
+const RETRO_ENVIRONMENT_GET_CAN_DUPE: c_uint = 3;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const RETRO_ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const RETRO_ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+const SAMPLE_RATE: u32 = 44_100;
+
+// Joypad button for each CHIP-8 key. The d-pad sits on 2/4/6/8 and A on 5,
+// which is what most games use for movement and action.
+const KEY_BUTTONS: [(c_uint, &str); 16] = [
+    (0, "0\0"), // B
+    (1, "1\0"), // Y
+    (4, "2 (Up)\0"), // UP
+    (9, "3\0"), // X
+    (6, "4 (Left)\0"), // LEFT
+    (8, "5 (Action)\0"), // A
+    (7, "6 (Right)\0"), // RIGHT
+    (10, "7\0"), // L
+    (5, "8 (Down)\0"), // DOWN
+    (11, "9\0"), // R
+    (2, "A\0"), // SELECT
+    (3, "B\0"), // START
+    (12, "C\0"), // L2
+    (13, "D\0"), // R2
+    (14, "E\0"), // L3
+    (15, "F\0"), // R3
+];
+
+// Strings handed to the frontend are nul-terminated &str constants
+const VAR_IPF: &str = "chip8_ipf\0";
+const VAR_PLATFORM: &str = "chip8_platform\0";
+const VAR_VF_RESET: &str = "chip8_vf_reset\0";
+const VAR_LOAD_STORE: &str = "chip8_load_store_increments_i\0";
+const VAR_SHIFT: &str = "chip8_shift_uses_vy\0";
+const VAR_JUMP: &str = "chip8_jump_uses_vx\0";
+const VAR_DISPLAY_WAIT: &str = "chip8_display_wait\0";
+const VAR_CLIP: &str = "chip8_clip_sprites\0";
+const VAR_KEY_WAIT: &str = "chip8_key_wait\0";
+const VAR_STACK_DEPTH: &str = "chip8_stack_depth\0";
+const VAR_STACK_IN_MEMORY: &str = "chip8_stack_in_memory\0";
+const VAR_FONT: &str = "chip8_font\0";
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    library_name: *const c_char,
+    library_version: *const c_char,
+    valid_extensions: *const c_char,
+    need_fullpath: bool,
+    block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    geometry: RetroGameGeometry,
+    timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct RetroVariable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+struct RetroInputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+type EnvironmentFn = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+type VideoRefreshFn = unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+type AudioSampleFn = unsafe extern "C" fn(left: i16, right: i16);
+type AudioSampleBatchFn = unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+type InputPollFn = unsafe extern "C" fn();
+type InputStateFn = unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Core {
+    machine_state: Chip8,
+    rom: Vec<u8>,
+    pixels: [u32; WIDTH * HEIGHT],
+    // Whether the frontend repeats the last frame when it is passed a null one
+    can_dupe: bool,
+    samples: Vec<f32>,
+    frames: Vec<i16>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    let environment = CALLBACKS.lock().unwrap().environment;
+    match environment {
+        Some(f) => unsafe { f(cmd, data) },
+        None => false
+    }
+}
+
+fn get_variable(key: &str) -> Option<String> {
+    let mut var = RetroVariable {
+        key: key.as_ptr() as *const c_char,
+        value: std::ptr::null(),
+    };
+    if !environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut var as *mut RetroVariable as *mut c_void) || var.value.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(var.value) }.to_string_lossy().into_owned())
+}
+
+// Each quirk option is "platform" to follow the platform option, or on or off
+fn get_quirk(key: &str, on: &str, off: &str, platform: bool) -> bool {
+    match get_variable(key) {
+        Some(value) if value == on => true,
+        Some(value) if value == off => false,
+        _ => platform
+    }
+}
+
+// Core options are re-read on load and whenever the frontend reports a change. The
+// stack layout only changes on reset: return addresses already pushed would otherwise
+// be looked for in the wrong place
+fn apply_variables(machine_state: &mut Chip8, reset: bool) {
+    let ipf = get_variable(VAR_IPF).and_then(|v| v.parse().ok()).unwrap_or(CYCLES_PER_FRAME);
+    machine_state.set_cycles_per_frame(ipf);
+    let platform = get_variable(VAR_PLATFORM).and_then(|v| v.parse().ok()).map_or_else(Quirks::default, Platform::quirks);
+    let current = machine_state.quirks();
+    let quirks = Quirks {
+        vf_reset: get_quirk(VAR_VF_RESET, "enabled", "disabled", platform.vf_reset),
+        load_store_increments_i: get_quirk(VAR_LOAD_STORE, "enabled", "disabled", platform.load_store_increments_i),
+        shift_uses_vy: get_quirk(VAR_SHIFT, "enabled", "disabled", platform.shift_uses_vy),
+        jump_uses_vx: get_quirk(VAR_JUMP, "enabled", "disabled", platform.jump_uses_vx),
+        display_wait: get_quirk(VAR_DISPLAY_WAIT, "enabled", "disabled", platform.display_wait),
+        clip_sprites: get_quirk(VAR_CLIP, "enabled", "disabled", platform.clip_sprites),
+        key_wait_release: get_quirk(VAR_KEY_WAIT, "release", "press", platform.key_wait_release),
+        stack_depth: match get_variable(VAR_STACK_DEPTH).and_then(|v| v.parse().ok()) {
+            Some(depth) if reset => depth,
+            None if reset => platform.stack_depth,
+            _ => current.stack_depth
+        },
+        stack_in_memory: if reset {
+            get_quirk(VAR_STACK_IN_MEMORY, "enabled", "disabled", platform.stack_in_memory)
+        } else {
+            current.stack_in_memory
+        },
+        halt_on_stack_fault: platform.halt_on_stack_fault
+    };
+    machine_state.set_quirks(quirks);
+    let font = get_variable(VAR_FONT).and_then(|v| v.parse().ok()).unwrap_or(Font::Standard);
+    machine_state.load_font(font.data(), DEFAULT_FONT_ADDRESS);
+}
+
+fn reset(core: &mut Core) {
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_nanos() as u64);
+    core.machine_state = Chip8::with_rng(XorShift::new(seed));
+    core.machine_state.load_rom(&core.rom);
+    core.machine_state.enable_audio(AudioConfig::default(), SAMPLE_RATE);
+    apply_variables(&mut core.machine_state, true);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(f: EnvironmentFn) {
+    CALLBACKS.lock().unwrap().environment = Some(f);
+    let variables = [
+        RetroVariable {
+            key: VAR_IPF.as_ptr() as *const c_char,
+            value: "Instructions per frame; 9|7|10|12|15|20|30|50|100|200|500|1000\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_PLATFORM.as_ptr() as *const c_char,
+            value: "Platform quirks; default|vip|schip|xochip\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_VF_RESET.as_ptr() as *const c_char,
+            value: "8XY1-8XY3 clear VF; platform|enabled|disabled\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_LOAD_STORE.as_ptr() as *const c_char,
+            value: "FX55/FX65 increment I; platform|enabled|disabled\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_SHIFT.as_ptr() as *const c_char,
+            value: "8XY6/8XYE shift VY; platform|enabled|disabled\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_JUMP.as_ptr() as *const c_char,
+            value: "BNNN jumps to NNN + VX; platform|enabled|disabled\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_DISPLAY_WAIT.as_ptr() as *const c_char,
+            value: "DXYN waits for the next frame; platform|enabled|disabled\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_CLIP.as_ptr() as *const c_char,
+            value: "Clip sprites at the screen edges; platform|enabled|disabled\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_KEY_WAIT.as_ptr() as *const c_char,
+            value: "FX0A completes on key; platform|release|press\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_STACK_DEPTH.as_ptr() as *const c_char,
+            value: "Call stack depth (on reset); platform|16|12\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_STACK_IN_MEMORY.as_ptr() as *const c_char,
+            value: "Call stack in memory at 0xEA0 (on reset); platform|disabled|enabled\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: VAR_FONT.as_ptr() as *const c_char,
+            value: "Hex font; standard|vip|eti660|dream6800|fishnchips\0".as_ptr() as *const c_char,
+        },
+        RetroVariable {
+            key: std::ptr::null(),
+            value: std::ptr::null(),
+        },
+    ];
+    environment(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(f: VideoRefreshFn) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(f);
+}
+
+// Only the batch callback is used
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_f: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(f: AudioSampleBatchFn) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(f: InputPollFn) {
+    CALLBACKS.lock().unwrap().input_poll = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(f: InputStateFn) {
+    CALLBACKS.lock().unwrap().input_state = Some(f);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: "chip8-rs\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: "ch8|rom\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a writable `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: 2.0,
+        },
+        timing: RetroSystemTiming {
+            fps: 60.0,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = CORE.lock().unwrap().as_mut() {
+        reset(core);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let mut guard = CORE.lock().unwrap();
+    let core = match guard.as_mut() {
+        Some(core) => core,
+        None => return
+    };
+
+    let mut updated = false;
+    if environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut bool as *mut c_void) && updated {
+        apply_variables(&mut core.machine_state, false);
+    }
+
+    let callbacks = *CALLBACKS.lock().unwrap();
+    if let (Some(poll), Some(state)) = (callbacks.input_poll, callbacks.input_state) {
+        unsafe { poll() };
+        for (key, &(button, _)) in KEY_BUTTONS.iter().enumerate() {
+            let pressed = unsafe { state(0, RETRO_DEVICE_JOYPAD, 0, button) } != 0;
+            core.machine_state.set_key(key, pressed);
+        }
+    }
+
+    core.samples.clear();
+    core.machine_state.emulate_frame_with_audio(&mut core.samples);
+
+    if let Some(video_refresh) = callbacks.video_refresh {
+        if core.machine_state.get_draw() {
+            let gfx = core.machine_state.get_gfx();
+            for (i, pixel) in core.pixels.iter_mut().enumerate() {
+                *pixel = if gfx[i % WIDTH][i / WIDTH] != 0 { 0x00FF_FFFF } else { 0 };
+            }
+            core.machine_state.set_draw(false);
+            unsafe { video_refresh(core.pixels.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, WIDTH * 4) };
+        } else if core.can_dupe {
+            // A null frame tells the frontend to repeat the previous one
+            unsafe { video_refresh(std::ptr::null(), WIDTH as c_uint, HEIGHT as c_uint, WIDTH * 4) };
+        } else {
+            unsafe { video_refresh(core.pixels.as_ptr() as *const c_void, WIDTH as c_uint, HEIGHT as c_uint, WIDTH * 4) };
+        }
+    }
+
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        core.frames.clear();
+        for &sample in core.samples.iter() {
+            let pcm = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            core.frames.push(pcm);
+            core.frames.push(pcm);
+        }
+        unsafe { audio_sample_batch(core.frames.as_ptr(), core.samples.len()) };
+    }
+}
+
+/// # Safety
+/// `game` must be null or point to a valid `retro_game_info` whose data covers `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+    if rom.len() > MAX_ROM_SIZE {
+        return false;
+    }
+
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut c_uint as *mut c_void) {
+        return false;
+    }
+    let mut descriptors: Vec<RetroInputDescriptor> = KEY_BUTTONS.iter().map(|&(button, description)| {
+        RetroInputDescriptor {
+            port: 0,
+            device: RETRO_DEVICE_JOYPAD,
+            index: 0,
+            id: button,
+            description: description.as_ptr() as *const c_char,
+        }
+    }).collect();
+    descriptors.push(RetroInputDescriptor {
+        port: 0,
+        device: 0,
+        index: 0,
+        id: 0,
+        description: std::ptr::null(),
+    });
+    environment(RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_mut_ptr() as *mut c_void);
+    // Frontends that don't answer get every frame in full
+    let mut dupe = false;
+    let can_dupe = environment(RETRO_ENVIRONMENT_GET_CAN_DUPE, &mut dupe as *mut bool as *mut c_void) && dupe;
+
+    let mut core = Core {
+        machine_state: Chip8::new(),
+        rom,
+        pixels: [0; WIDTH * HEIGHT],
+        can_dupe,
+        samples: Vec::new(),
+        frames: Vec::new(),
+    };
+    reset(&mut core);
+    core.machine_state.set_draw(true);
+    *CORE.lock().unwrap() = Some(core);
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    STATE_SIZE
+}
+
+/// # Safety
+/// `data` must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    match CORE.lock().unwrap().as_ref() {
+        Some(core) if size >= STATE_SIZE => {
+            let out = std::slice::from_raw_parts_mut(data as *mut u8, STATE_SIZE);
+            core.machine_state.save_state(out).is_ok()
+        },
+        _ => false
+    }
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    match CORE.lock().unwrap().as_mut() {
+        Some(core) if size >= STATE_SIZE => {
+            let state = std::slice::from_raw_parts(data as *const u8, STATE_SIZE);
+            let loaded = core.machine_state.load_state(state).is_ok();
+            core.machine_state.set_draw(true);
+            loaded
+        },
+        _ => false
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
@@ -1,79 +1,230 @@
-mod chip8;
-use crate::chip8::Chip8;
+use chip8_rs::audio::{AudioConfig, AudioSink, WavWriter};
+use chip8_rs::chip8::{Chip8, Platform};
+use chip8_rs::database::RomInfo;
+use chip8_rs::font::{self, DEFAULT_FONT_ADDRESS, FONT_SIZE};
+use chip8_rs::rng::XorShift;
+use chip8_rs::settings::{Loader, Settings};
+use chip8_rs::trace::{self, Filter, Trace, Tracer};
+use chip8_rs::watch::Reloader;
 
 extern crate sdl2;
 extern crate tinyfiledialogs;
 
-use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::pixels::{Color, PixelFormat, PixelFormatEnum};
 use sdl2::surface::Surface;
 use sdl2::render::Texture;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
 use std::time::{Duration, Instant};
 use crate::tinyfiledialogs::open_file_dialog;
 
-use sdl2::audio::{AudioCallback, AudioSpecDesired};
+// The subcommands chip8-tools has are the same code in chip8-rs
+#[path = "bin/chip8-tools.rs"]
+mod tools;
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
 
 const FRAMERATE : u32 = 60;
+const SAMPLE_RATE : i32 = 44_100;
+// Drop queued audio beyond this many frames so sound doesn't lag behind the picture
+const MAX_QUEUED_FRAMES : u32 = 4;
+// How often a watched program file is checked for changes
+const RELOAD_CHECK_FRAMES : u32 = 15;
 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
-}
+const USAGE : &str = "usage: chip8-rs [--waveform square|sine|triangle|noise] [--pitch HZ] [--volume 0-1] [--mute] [--wav FILE] [--font NAME|FILE] [--font-address ADDR] [--platform vip|schip|xochip] [--database FILE] [--watch] [--keep-registers] [--trace FILE] [--trace-format text|binary] [--trace-addresses START-END] [--trace-class 0-F,...] [--trace-frames START-END] [ROM|SOURCE.8o]
+       chip8-rs asm SOURCE.8o [-o ROM.ch8]
+       chip8-rs analyze ROM|SOURCE.8o [--dot FILE] [--platform vip|schip|xochip]
+       chip8-rs trace-diff TRACE TRACE
+       chip8-rs gdb ROM|SOURCE.8o [--port PORT] [--platform vip|schip|xochip]";
 
+struct Options {
+    audio: AudioConfig,
+    wav: Option<String>,
+    font: Option<[u8; FONT_SIZE]>,
+    font_address: u16,
+    platform: Option<Platform>,
+    rom: Option<String>,
+    database: Option<String>,
+    watch: bool,
+    keep_registers: bool,
+    trace: Option<String>,
+    trace_format: trace::Format,
+    trace_filter: Filter,
+}
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options {
+        audio: AudioConfig::default(),
+        wav: None,
+        font: None,
+        font_address: DEFAULT_FONT_ADDRESS,
+        platform: None,
+        rom: None,
+        database: None,
+        watch: false,
+        keep_registers: false,
+        trace: None,
+        trace_format: trace::Format::Text,
+        trace_filter: Filter::default(),
+    };
+    let audio = &mut options.audio;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--waveform" => audio.waveform = value()?.parse().map_err(|e| format!("{}", e))?,
+            "--pitch" => {
+                audio.pitch = value()?.parse().ok().filter(|pitch: &f32| pitch.is_finite() && *pitch > 0.0)
+                    .ok_or("--pitch expects a frequency in Hz above 0")?;
+            },
+            // Volumes out of range are clamped once the arguments are read, NaN is refused
+            "--volume" => {
+                audio.volume = value()?.parse().ok().filter(|volume: &f32| volume.is_finite())
+                    .ok_or("--volume expects a number between 0 and 1")?;
+            },
+            "--mute" => audio.muted = true,
+            "--wav" => options.wav = Some(value()?),
+            "--font" => {
+                let name = value()?;
+                options.font = Some(font::resolve_font(&name).map_err(|e| format!("--font {}", e))?);
+            },
+            "--font-address" => {
+                options.font_address = font::parse_address(&value()?).map_err(|e| format!("--font-address: {}", e))?;
+            },
+            "--platform" => options.platform = Some(value()?.parse().map_err(|e| format!("{}", e))?),
+            "--database" => options.database = Some(value()?),
+            "--watch" => options.watch = true,
+            "--keep-registers" => {
+                options.watch = true;
+                options.keep_registers = true;
+            },
+            "--trace" => options.trace = Some(value()?),
+            "--trace-format" => options.trace_format = value()?.parse().map_err(|e| format!("{}", e))?,
+            "--trace-addresses" => options.trace_filter.addresses = Filter::parse_addresses(&value()?)?,
+            "--trace-class" => options.trace_filter.classes = Filter::parse_classes(&value()?)?,
+            "--trace-frames" => options.trace_filter.frames = Filter::parse_frames(&value()?)?,
+            _ if !arg.starts_with('-') && options.rom.is_none() => options.rom = Some(arg),
+            _ => return Err(format!("unknown argument {}", arg))
+        }
+    }
+    audio.volume = audio.volume.clamp(0.0, 1.0);
+    Ok(options)
+}
 
-    fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+// Prints what the cartridge or database says about the program and where its quirks
+// came from, returning the window title
+fn describe(settings: &Settings) -> String {
+    let info = &settings.info;
+    if !info.title.is_empty() {
+        if info.authors.is_empty() {
+            eprintln!("{}", info.title);
+        } else {
+            eprintln!("{} by {}", info.title, info.authors.join(", "));
         }
     }
+    if let Some(hints) = info.key_hints() {
+        eprintln!("Keys: {}", hints);
+    }
+    eprintln!("Platform: {}", settings.origin);
+    if info.title.is_empty() { "CHIP-8 Interpreter".to_string() } else { info.title.clone() }
 }
- 
-pub fn main() {
-    let filter : Option<(&[&str], &str)> = Some((&["*.ch8", "*.rom"], "CHIP-8 binaries (.ch8, .rom)"));
-    let rom_path = open_file_dialog(
-        "Open rom",
-        "./",
-        filter
-    ).expect("Failed to open file!");
-    let binary = match std::fs::read(rom_path) {
-        Ok(s) => s,
-        Err(_) => Vec::new()
-    };
-    let mut machine_state : Chip8 = Chip8::new();
-    machine_state.load_fonts();
-    machine_state.load_rom(binary);
 
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
+// Background and foreground as texture pixels: the database's colours for the ROM, or
+// white on black
+fn colors(info: &RomInfo, format: &PixelFormat) -> [[u8; 4]; 2] {
+    let color = |k: usize, default| {
+        let [r, g, b] = info.pixels.get(k).copied().unwrap_or(default);
+        Color::RGB(r, g, b).to_u32(format).to_ne_bytes()
+    };
+    [color(0, [0x00; 3]), color(1, [0xFF; 3])]
+}
 
+// Returns None rather than failing when there is no usable audio output
+fn open_audio(sdl_context: &sdl2::Sdl) -> Option<AudioQueue<f32>> {
     let desired_spec = AudioSpecDesired {
-        freq: Some(44_100),
+        freq: Some(SAMPLE_RATE),
         channels: Some(1),
         samples: None,
     };
+    let device = sdl_context.audio().and_then(|audio_subsystem| {
+        audio_subsystem.open_queue::<f32, _>(None, &desired_spec)
+    });
+    match device {
+        Ok(device) => {
+            device.resume();
+            Some(device)
+        },
+        Err(e) => {
+            eprintln!("No audio output available, continuing without sound: {}", e);
+            None
+        }
+    }
+}
 
-    let mut device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-        SquareWave {
-            phase_inc: 220.0 / spec.freq as f32,
-            phase: 0.0,
-            volume: 0.0,
+pub fn main() {
+    if matches!(std::env::args().nth(1).as_deref(), Some("asm" | "analyze" | "trace-diff" | "gdb")) {
+        tools::main();
+        return;
+    }
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{}\n{}", e, USAGE);
+            std::process::exit(2);
         }
-    }).unwrap();
-    device.resume();
+    };
+    let rom_path = options.rom.clone().unwrap_or_else(|| {
+        let filter : Option<(&[&str], &str)> = Some((&["*.ch8", "*.rom", "*.8o", "*.gif"], "CHIP-8 programs (.ch8, .rom, .8o, .gif)"));
+        open_file_dialog(
+            "Open rom",
+            "./",
+            filter
+        ).expect("Failed to open file!")
+    });
+    let rom_path = Path::new(&rom_path);
+    let loader = Loader::new(options.database.as_deref().map(Path::new), options.platform, options.font, options.font_address).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    // Cartridges bring their own settings, other programs are looked up in the database
+    let (program, settings) = loader.load(rom_path).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    let mut title = describe(&settings);
+    let mut symbols = program.symbols();
+    let mut reloader = Reloader::for_program(rom_path, &program, options.watch, options.keep_registers);
+    let mut machine_state : Chip8 = Chip8::with_rng(XorShift::new(rand::random()));
+    settings.apply(&mut machine_state);
+    machine_state.load_rom(&program.rom);
+    if let Some(path) = options.trace.as_ref() {
+        let mut trace = Trace::create(Path::new(path), options.trace_format, options.trace_filter.clone()).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        trace.set_symbols(&symbols);
+        machine_state.set_tracer(Some(Box::new(trace)));
+    }
+
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let device = open_audio(&sdl_context);
+    let sample_rate = device.as_ref().map_or(SAMPLE_RATE, |d| d.spec().freq) as u32;
+    let mut wav = options.wav.as_ref().map(|path| {
+        File::create(path)
+            .and_then(|file| WavWriter::new(BufWriter::new(file), sample_rate))
+            .expect("Failed to create WAV file!")
+    });
+    if device.is_some() || wav.is_some() {
+        machine_state.enable_audio(options.audio, sample_rate);
+    }
+    let mut samples : Vec<f32> = Vec::new();
  
-    let window = video_subsystem.window("CHIP-8 Interpreter", 800, 600)
+    let window = video_subsystem.window(&title, 800, 600)
         .position_centered()
         .build()
         .unwrap();
@@ -85,17 +236,48 @@ pub fn main() {
     let texture_creator = canvas.texture_creator();
     let surface = Surface::new(64, 32, PixelFormatEnum::RGB24).unwrap();
     let mut texture = Texture::from_surface(&surface, &texture_creator).unwrap();
+    let pixel_format = PixelFormat::try_from(texture.query().format).unwrap();
+    let [mut background, mut foreground] = colors(&settings.info, &pixel_format);
     let mut pixel_data : [u8; 2048 * 4] = [0; 2048 * 4];
     let mut event_pump = sdl_context.event_pump().unwrap();
     let frametime = Duration::new(0, 1_000_000_000u32 / FRAMERATE);
+    let mut halted = false;
+    let mut frame : u32 = 0;
     'running: loop {
         let instant = Instant::now();
+        frame = frame.wrapping_add(1);
+        if let Some(reloader) = reloader.as_mut().filter(|_| frame.is_multiple_of(RELOAD_CHECK_FRAMES)) {
+            match loader.reload(reloader, &mut machine_state) {
+                Some(Ok((program, settings))) => {
+                    title = describe(&settings);
+                    [background, foreground] = colors(&settings.info, &pixel_format);
+                    machine_state.set_draw(true);
+                    symbols = program.symbols();
+                    if let Some(tracer) = machine_state.tracer_mut() {
+                        tracer.set_symbols(&symbols);
+                    }
+                    halted = false;
+                    let name = reloader.path().file_name().unwrap_or_default().to_string_lossy();
+                    let reloaded = format!("{} - reloaded {} ({})", title, name, reloader.reloads());
+                    canvas.window_mut().set_title(&reloaded).unwrap();
+                    eprintln!("Reloaded {}", reloader.path().display());
+                },
+                // Keep running the last version that loaded
+                Some(Err(e)) => eprintln!("{}", e),
+                None => {}
+            }
+        }
         for event in event_pump.poll_iter() {
             match event {
                 Event::Quit {..} |
                 Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
                     break 'running
                 },
+                Event::KeyDown { keycode: Some(Keycode::M), repeat: false, .. } => {
+                    if let Some(beeper) = machine_state.beeper_mut() {
+                        beeper.toggle_mute();
+                    }
+                },
                 Event::KeyDown { keycode : Some(Keycode::X), .. } => {machine_state.set_key(0x0, true)},
                 Event::KeyDown { keycode : Some(Keycode::Num1), .. } => {machine_state.set_key(0x1, true)},
                 Event::KeyDown { keycode : Some(Keycode::Num2), .. } => {machine_state.set_key(0x2, true)},
@@ -131,33 +313,52 @@ pub fn main() {
                 _ => {}       }
         }
 
-        machine_state.emulate_frame();
+        samples.clear();
+        machine_state.emulate_frame_with_audio(&mut samples);
+        if let (Some(fault), false) = (machine_state.fault(), halted) {
+            let fault = symbols.fault(fault);
+            eprintln!("Halted: {}", fault);
+            canvas.window_mut().set_title(&format!("{} - halted: {}", title, fault)).unwrap();
+            halted = true;
+        }
+        if let Some(device) = device.as_ref() {
+            if device.size() > MAX_QUEUED_FRAMES * sample_rate / FRAMERATE * 4 {
+                device.clear();
+            }
+            device.queue(&samples);
+        }
+        if let Some(wav) = wav.as_mut() {
+            for &sample in &samples {
+                wav.push_sample(sample);
+            }
+        }
         if machine_state.get_draw() {
             for i in 0..2048 {
                 let x = i % 64;
                 let y = i / 64;
-                pixel_data[4 * i] = 0xFF * machine_state.get_gfx()[x][y];
-                pixel_data[4 * i + 1] = 0xFF * machine_state.get_gfx()[x][y];
-                pixel_data[4 * i + 2] = 0xFF * machine_state.get_gfx()[x][y];
-                pixel_data[4 * i + 3] = 0xFF;
+                let color = if machine_state.get_gfx()[x][y] == 1 { foreground } else { background };
+                pixel_data[4 * i..4 * i + 4].copy_from_slice(&color);
             }
             texture.update(None, &pixel_data, 64 * 4).unwrap();
             canvas.copy(&texture, None, None).unwrap();
             machine_state.set_draw(false);
             canvas.present();
         }
-
-        if machine_state.is_playing_sound() {
-            let mut lock = device.lock(); 
-            lock.volume = 0.10;
-        } else {
-            let mut lock = device.lock(); 
-            lock.volume = 0.0;
-        }
         
         match frametime.checked_sub(instant.elapsed()) {
             None => (),
             Some(t) => ::std::thread::sleep(t)
         };        
     }
+
+    if let Some(wav) = wav {
+        if let Err(e) = wav.finish() {
+            eprintln!("Failed to write WAV file: {}", e);
+        }
+    }
+    if let Some(mut tracer) = machine_state.set_tracer(None) {
+        if let Err(e) = tracer.finish() {
+            eprintln!("Failed to write trace: {}", e);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,54 @@
+// Source of the random bytes used by CXNN. Injected into Chip8 so the core doesn't
+// depend on an OS entropy source, and so tests and replays can be deterministic.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    // Save states capture the generator through these; sources that can't be
+    // captured keep the defaults and simply aren't restored
+    fn state(&self) -> u64 {
+        0
+    }
+
+    fn restore(&mut self, _state: u64) {}
+}
+
+const DEFAULT_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
+// xorshift64, small and fast enough for a game's random numbers
+#[derive(Clone, Copy, Debug)]
+pub struct XorShift {
+    state: u64 // never zero
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        XorShift {
+            state: if seed == 0 { DEFAULT_SEED } else { seed }
+        }
+    }
+}
+
+impl Default for XorShift {
+    fn default() -> XorShift {
+        XorShift::new(DEFAULT_SEED)
+    }
+}
+
+impl RandomSource for XorShift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 32) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn restore(&mut self, state: u64) {
+        if state != 0 {
+            self.state = state;
+        }
+    }
+}
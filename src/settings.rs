@@ -0,0 +1,100 @@
+// What the frontends run a program with. --platform and --font have the first say, then
+// a cartridge's own options or the database's entry for the ROM, then the platform the
+// ROM looks written for. Programs are loaded through a Loader, and again on each reload
+use crate::asm::{load_program, Program};
+use crate::cartridge;
+use crate::chip8::{Chip8, Platform, Quirks, CYCLES_PER_FRAME};
+use crate::database::{Database, RomInfo};
+use crate::detect::detect;
+use crate::font::{Font, FONT_SIZE};
+use crate::rng::RandomSource;
+use crate::watch::Reloader;
+use std::path::Path;
+
+#[derive(Clone, Debug)]
+pub struct Settings {
+    // What the cartridge or database says about the program, empty if neither knows it
+    pub info: RomInfo,
+    pub quirks: Quirks,
+    // Where the quirks came from, e.g. "VIP quirks from the ROM database"
+    pub origin: String,
+    pub cycles_per_frame: u32,
+    pub font: [u8; FONT_SIZE],
+    pub font_address: u16
+}
+
+impl Settings {
+    // Sets the machine's quirks, speed and font
+    pub fn apply<R: RandomSource>(&self, machine_state: &mut Chip8<R>) {
+        machine_state.set_quirks(self.quirks);
+        machine_state.set_cycles_per_frame(self.cycles_per_frame);
+        machine_state.load_font(&self.font, self.font_address);
+    }
+}
+
+// The ROM database and the command line's choices, which win over a program's own
+pub struct Loader {
+    database: Database,
+    platform: Option<Platform>,
+    font: Option<[u8; FONT_SIZE]>,
+    font_address: u16
+}
+
+impl Loader {
+    // database is a programs.json to use instead of the built-in one
+    pub fn new(database: Option<&Path>, platform: Option<Platform>, font: Option<[u8; FONT_SIZE]>, font_address: u16) -> Result<Loader, String> {
+        let database = match database {
+            Some(path) => Database::load(path)?,
+            None => Database::builtin()
+        };
+        Ok(Loader { database, platform, font, font_address })
+    }
+
+    // Reads the program at path (see load_program) and picks its settings. A cartridge is
+    // decoded once for both
+    pub fn load(&self, path: &Path) -> Result<(Program, Settings), String> {
+        let (program, info, from) = if cartridge::is_cartridge(path) {
+            let cartridge = cartridge::load(path)?;
+            (cartridge.program, cartridge.info, "cartridge")
+        } else {
+            let program = load_program(path)?;
+            let info = self.database.lookup(&program.rom).cloned().unwrap_or_default();
+            (program, info, "ROM database")
+        };
+        let settings = self.settings(info, from, &program.rom);
+        Ok((program, settings))
+    }
+
+    // Reloader::poll, with the new version's settings picked again and put into the
+    // machine, since it may be another program with settings of its own
+    pub fn reload<R: RandomSource>(&self, reloader: &mut Reloader, machine_state: &mut Chip8<R>) -> Option<Result<(Program, Settings), String>> {
+        if !reloader.changed() {
+            return None;
+        }
+        let loaded = self.load(reloader.path());
+        if let Ok((program, settings)) = &loaded {
+            reloader.replace(machine_state, &program.rom);
+            settings.apply(machine_state);
+        }
+        Some(loaded)
+    }
+
+    fn settings(&self, info: RomInfo, from: &str, rom: &[u8]) -> Settings {
+        let (quirks, origin) = match (self.platform, info.quirks) {
+            (Some(platform), _) => (platform.quirks(), format!("{} quirks", platform.name())),
+            (None, Some(quirks)) => (quirks, format!("{} quirks from the {}", info.platform.map_or("default", Platform::name), from)),
+            (None, None) => {
+                let detection = detect(rom);
+                (detection.quirks(), detection.explain())
+            }
+        };
+        Settings {
+            quirks,
+            origin,
+            cycles_per_frame: info.tickrate.unwrap_or(CYCLES_PER_FRAME),
+            font: self.font.unwrap_or(*info.font.unwrap_or(Font::Standard).data()),
+            font_address: self.font_address,
+            info
+        }
+    }
+}
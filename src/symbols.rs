@@ -0,0 +1,57 @@
+// Names for addresses, taken from the labels of an assembled program, so frontends can
+// show main+4 rather than 0x206
+use crate::chip8::Fault;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Debug, Default)]
+pub struct Symbols {
+    addresses: BTreeMap<String, u16>,
+    names: BTreeMap<u16, String> // one per address, the first alphabetically
+}
+
+impl Symbols {
+    pub fn new(labels: &BTreeMap<String, u16>) -> Symbols {
+        let mut names = BTreeMap::new();
+        for (name, &address) in labels {
+            names.entry(address).or_insert_with(|| name.clone());
+        }
+        Symbols { addresses: labels.clone(), names }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    // Every label with its address, by name
+    pub fn labels(&self) -> impl Iterator<Item = (&str, u16)> + '_ {
+        self.addresses.iter().map(|(name, &address)| (name.as_str(), address))
+    }
+
+    // The nearest label at or before address, with the distance from it: main, main+4
+    pub fn label(&self, address: u16) -> Option<String> {
+        let (&start, name) = self.names.range(..=address).next_back()?;
+        Some(match address - start {
+            0 => name.clone(),
+            offset => format!("{}+{}", name, offset)
+        })
+    }
+
+    // 0x206 (main+4), or only the address before the first label or without symbols
+    pub fn describe(&self, address: u16) -> String {
+        match self.label(address) {
+            Some(label) => format!("{:#05x} ({})", address, label),
+            None => format!("{:#05x}", address)
+        }
+    }
+
+    pub fn fault(&self, fault: Fault) -> String {
+        match self.label(fault.pc()) {
+            Some(label) => format!("{} ({})", fault, label),
+            None => fault.to_string()
+        }
+    }
+}
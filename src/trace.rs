@@ -0,0 +1,428 @@
+// Execution traces: every instruction the interpreter runs, with the machine as the
+// instruction found it, written to a file as it happens. Text traces are for reading;
+// binary ones are fixed-size records for runs too long to keep as text. Either kind can
+// be read back and compared with another, to find where two runs (two builds, two
+// quirk settings) first went different ways
+use crate::instruction::Instruction;
+use crate::symbols::Symbols;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const BINARY_MAGIC: &[u8; 4] = b"C8TR";
+const BINARY_VERSION: u8 = 1;
+// frame, pc, opcode, v, i, delay timer, sound timer
+const RECORD_SIZE: usize = 4 + 2 + 2 + 16 + 2 + 1 + 1;
+
+// One executed instruction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub frame: u32, // frames run before this one
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub delay_timer: u8,
+    pub sound_timer: u8
+}
+
+impl Step {
+    pub fn disassembly(&self) -> String {
+        match Instruction::decode(self.opcode) {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => "???".to_string()
+        }
+    }
+
+    // The fields that don't match other's, by the names the text format uses
+    pub fn differences(&self, other: &Step) -> Vec<String> {
+        let mut differences = Vec::new();
+        let mut compare = |name: String, a: u32, b: u32, width: usize| {
+            if a != b {
+                differences.push(format!("{} {:0width$x} vs {:0width$x}", name, a, b, width = width));
+            }
+        };
+        compare("frame".to_string(), self.frame, other.frame, 1);
+        compare("pc".to_string(), self.pc as u32, other.pc as u32, 3);
+        compare("opcode".to_string(), self.opcode as u32, other.opcode as u32, 4);
+        for (x, (&a, &b)) in self.v.iter().zip(&other.v).enumerate() {
+            compare(format!("v{:x}", x), a as u32, b as u32, 2);
+        }
+        compare("i".to_string(), self.i as u32, other.i as u32, 3);
+        compare("dt".to_string(), self.delay_timer as u32, other.delay_timer as u32, 2);
+        compare("st".to_string(), self.sound_timer as u32, other.sound_timer as u32, 2);
+        differences
+    }
+}
+
+// One line of a text trace:
+// frame 12  0x204  6005  LD V0, 0x05   v 05 00 .. 00  i 0x000  dt 00  st 00
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Labeled { step: self, label: None }.fmt(f)
+    }
+}
+
+impl Step {
+    // The text line with the label pc is at, when the program has one, ahead of the
+    // disassembly: frame 12  0x204  6005  <main+4>  LD V0, 0x05   v ...
+    pub fn labeled(&self, symbols: &Symbols) -> Labeled<'_> {
+        Labeled { step: self, label: symbols.label(self.pc) }
+    }
+}
+
+pub struct Labeled<'a> {
+    step: &'a Step,
+    label: Option<String>
+}
+
+impl fmt::Display for Labeled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let step = self.step;
+        write!(f, "frame {}  {:#05x}  {:04X}  ", step.frame, step.pc, step.opcode)?;
+        if let Some(label) = self.label.as_ref() {
+            write!(f, "<{}>  ", label)?;
+        }
+        write!(f, "{:<20}  v", step.disassembly())?;
+        for value in step.v.iter() {
+            write!(f, " {:02x}", value)?;
+        }
+        write!(f, "  i {:#05x}  dt {:02x}  st {:02x}", step.i, step.delay_timer, step.sound_timer)
+    }
+}
+
+// Told about each instruction before it runs, see Chip8::set_tracer
+pub trait Tracer: Send {
+    fn step(&mut self, step: &Step);
+
+    // Names for the addresses of the steps from now on, from the labels of the program
+    // being run. Called again when the program is reloaded
+    fn set_symbols(&mut self, _symbols: &Symbols) {}
+
+    // Flushes whatever is buffered, reporting the first error since the trace started
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Binary
+}
+
+#[derive(Debug)]
+pub struct ParseFormatError;
+
+impl fmt::Display for ParseFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown trace format (expected text or binary)")
+    }
+}
+
+impl FromStr for Format {
+    type Err = ParseFormatError;
+
+    fn from_str(s: &str) -> Result<Format, ParseFormatError> {
+        match s {
+            "text" => Ok(Format::Text),
+            "binary" => Ok(Format::Binary),
+            _ => Err(ParseFormatError)
+        }
+    }
+}
+
+// Which instructions get written. Ranges are inclusive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Filter {
+    pub addresses: (u16, u16),
+    pub frames: (u32, u32),
+    // Bit n set keeps the opcodes whose top nibble is n: 1 << 0xD for the draws
+    pub classes: u16
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter { addresses: (0, 0xFFF), frames: (0, u32::MAX), classes: 0xFFFF }
+    }
+}
+
+impl Filter {
+    pub fn matches(&self, step: &Step) -> bool {
+        (self.addresses.0..=self.addresses.1).contains(&step.pc)
+            && (self.frames.0..=self.frames.1).contains(&step.frame)
+            && self.classes & 1 << (step.opcode >> 12) != 0
+    }
+
+    // 0x200-0x2FF, or one address
+    pub fn parse_addresses(s: &str) -> Result<(u16, u16), String> {
+        let error = || format!("expected an address range like 0x200-0x2FF, not {}", s);
+        let (start, end) = parse_range(s, parse_number).ok_or_else(error)?;
+        if start > end || end > 0xFFF {
+            return Err(error());
+        }
+        Ok((start as u16, end as u16))
+    }
+
+    // 100-200, 100- for everything from frame 100 on, or one frame
+    pub fn parse_frames(s: &str) -> Result<(u32, u32), String> {
+        let error = || format!("expected a frame range like 100-200, not {}", s);
+        let (start, end) = match s.strip_suffix('-') {
+            Some(start) => (start.parse().map_err(|_| error())?, u32::MAX),
+            None => parse_range(s, |s| s.parse().ok()).ok_or_else(error)?
+        };
+        if start > end {
+            return Err(error());
+        }
+        Ok((start, end))
+    }
+
+    // The top nibbles of the opcodes to keep, as hex digits: 8,D or 8D
+    pub fn parse_classes(s: &str) -> Result<u16, String> {
+        let digits = s.chars().filter(|&c| c != ',');
+        let classes = digits.map(|c| c.to_digit(16).map(|nibble| 1 << nibble)).sum::<Option<u32>>();
+        match classes {
+            Some(classes) if classes != 0 => Ok(classes as u16),
+            _ => Err(format!("expected opcode classes as hex digits like 8,D, not {}", s))
+        }
+    }
+}
+
+// Decimal or 0x-prefixed hex
+fn parse_number(s: &str) -> Option<u32> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok()
+    }
+}
+
+fn parse_range(s: &str, parse: impl Fn(&str) -> Option<u32>) -> Option<(u32, u32)> {
+    match s.split_once('-') {
+        Some((start, end)) => Some((parse(start)?, parse(end)?)),
+        None => parse(s).map(|n| (n, n))
+    }
+}
+
+// Writes the steps the filter lets through
+pub struct Trace<W: Write + Send> {
+    out: W,
+    format: Format,
+    filter: Filter,
+    symbols: Symbols, // text traces show labels
+    error: Option<io::Error> // the first, after which nothing more is written
+}
+
+impl Trace<BufWriter<File>> {
+    pub fn create(path: &Path, format: Format, filter: Filter) -> Result<Trace<BufWriter<File>>, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Trace::new(BufWriter::new(file), format, filter).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl<W: Write + Send> Trace<W> {
+    pub fn new(mut out: W, format: Format, filter: Filter) -> io::Result<Trace<W>> {
+        if format == Format::Binary {
+            out.write_all(BINARY_MAGIC)?;
+            out.write_all(&[BINARY_VERSION])?;
+        }
+        Ok(Trace { out, format, filter, symbols: Symbols::default(), error: None })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write(&mut self, step: &Step) -> io::Result<()> {
+        match self.format {
+            Format::Text => writeln!(self.out, "{}", step.labeled(&self.symbols)),
+            Format::Binary => self.out.write_all(&encode(step))
+        }
+    }
+}
+
+impl<W: Write + Send> Tracer for Trace<W> {
+    fn step(&mut self, step: &Step) {
+        if self.error.is_none() && self.filter.matches(step) {
+            self.error = self.write(step).err();
+        }
+    }
+
+    fn set_symbols(&mut self, symbols: &Symbols) {
+        self.symbols = symbols.clone();
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush()
+        }
+    }
+}
+
+fn encode(step: &Step) -> [u8; RECORD_SIZE] {
+    let mut record = [0; RECORD_SIZE];
+    record[0..4].copy_from_slice(&step.frame.to_le_bytes());
+    record[4..6].copy_from_slice(&step.pc.to_le_bytes());
+    record[6..8].copy_from_slice(&step.opcode.to_le_bytes());
+    record[8..24].copy_from_slice(&step.v);
+    record[24..26].copy_from_slice(&step.i.to_le_bytes());
+    record[26] = step.delay_timer;
+    record[27] = step.sound_timer;
+    record
+}
+
+fn decode(record: &[u8; RECORD_SIZE]) -> Step {
+    let mut v = [0; 16];
+    v.copy_from_slice(&record[8..24]);
+    Step {
+        frame: u32::from_le_bytes([record[0], record[1], record[2], record[3]]),
+        pc: u16::from_le_bytes([record[4], record[5]]),
+        opcode: u16::from_le_bytes([record[6], record[7]]),
+        v,
+        i: u16::from_le_bytes([record[24], record[25]]),
+        delay_timer: record[26],
+        sound_timer: record[27]
+    }
+}
+
+// The text format read back. The label and disassembly are skipped, since they follow
+// from pc and the opcode
+fn parse_line(line: &str) -> Option<Step> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let hex = |s: &str| u32::from_str_radix(s.trim_start_matches("0x"), 16).ok();
+    // Everything after the disassembly: v, 16 registers, then i, dt and st with their values
+    let registers = fields.len().checked_sub(23)?;
+    let (["frame", frame, pc, opcode, ..], ["v", rest @ ..]) = fields.split_at(registers) else { return None };
+    let (v, ["i", i, "dt", dt, "st", st]) = rest.split_at(16) else { return None };
+    let mut registers = [0; 16];
+    for (register, value) in registers.iter_mut().zip(v) {
+        *register = hex(value)? as u8;
+    }
+    Some(Step {
+        frame: frame.parse().ok()?,
+        pc: hex(pc)? as u16,
+        opcode: hex(opcode)? as u16,
+        v: registers,
+        i: hex(i)? as u16,
+        delay_timer: hex(dt)? as u8,
+        sound_timer: hex(st)? as u8
+    })
+}
+
+// The steps in a trace of either format, told apart by the binary header
+pub struct Reader {
+    input: Box<dyn BufRead>,
+    format: Format,
+    line: usize
+}
+
+impl Reader {
+    pub fn open(path: &Path) -> Result<Reader, String> {
+        let file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Reader::new(BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn new(mut input: impl BufRead + 'static) -> Result<Reader, String> {
+        let binary = input.fill_buf().map_err(|e| e.to_string())?.starts_with(BINARY_MAGIC);
+        let format = if binary { Format::Binary } else { Format::Text };
+        if binary {
+            let mut header = [0; 5];
+            input.read_exact(&mut header).map_err(|e| e.to_string())?;
+            if header[4] != BINARY_VERSION {
+                return Err(format!("unsupported trace version {}", header[4]));
+            }
+        }
+        Ok(Reader { input: Box::new(input), format, line: 0 })
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    fn next_record(&mut self) -> Result<Option<Step>, String> {
+        let mut record = [0; RECORD_SIZE];
+        let mut filled = 0;
+        while filled < RECORD_SIZE {
+            match self.input.read(&mut record[filled..]) {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(format!("record {} is cut short", self.line + 1)),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e.to_string())
+            }
+        }
+        self.line += 1;
+        Ok(Some(decode(&record)))
+    }
+
+    fn next_line(&mut self) -> Result<Option<Step>, String> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.input.read_line(&mut line).map_err(|e| e.to_string())? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            if !line.trim().is_empty() {
+                return parse_line(&line).map(Some).ok_or_else(|| format!("line {}: not a trace step", self.line));
+            }
+        }
+    }
+}
+
+impl Iterator for Reader {
+    type Item = Result<Step, String>;
+
+    fn next(&mut self) -> Option<Result<Step, String>> {
+        match self.format {
+            Format::Text => self.next_line(),
+            Format::Binary => self.next_record()
+        }.transpose()
+    }
+}
+
+// Where two traces part: the first step that differs, or the end of the shorter one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub index: u64, // steps the traces agree on before this one
+    pub a: Option<Step>,
+    pub b: Option<Step>
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.a, &self.b) {
+            (Some(a), Some(b)) => {
+                writeln!(f, "traces diverge at step {}: {}", self.index, a.differences(b).join(", "))?;
+                writeln!(f, "< {}", a)?;
+                write!(f, "> {}", b)
+            },
+            (Some(a), None) => write!(f, "the second trace ends after {} steps; the first goes on with\n< {}", self.index, a),
+            (None, Some(b)) => write!(f, "the first trace ends after {} steps; the second goes on with\n> {}", self.index, b),
+            (None, None) => write!(f, "traces are the same for all {} steps", self.index)
+        }
+    }
+}
+
+// Reads both traces in step until they differ. None if they are the same throughout
+pub fn diff<A, B>(a: A, b: B) -> Result<Option<Divergence>, String>
+where
+    A: IntoIterator<Item = Result<Step, String>>,
+    B: IntoIterator<Item = Result<Step, String>>
+{
+    let (mut a, mut b) = (a.into_iter(), b.into_iter());
+    let mut index = 0;
+    loop {
+        let step_a = a.next().transpose().map_err(|e| format!("first trace: {}", e))?;
+        let step_b = b.next().transpose().map_err(|e| format!("second trace: {}", e))?;
+        if step_a.is_none() && step_b.is_none() {
+            return Ok(None);
+        }
+        if step_a != step_b {
+            return Ok(Some(Divergence { index, a: step_a, b: step_b }));
+        }
+        index += 1;
+    }
+}
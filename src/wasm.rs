@@ -0,0 +1,115 @@
+// Flat C ABI for embedding the interpreter in a web page, built with
+//   cargo rustc --release --lib --crate-type cdylib --target wasm32-unknown-unknown --no-default-features --features wasm
+// JS writes the ROM into the buffer at chip8_rom_buffer() and reads the screen through
+// chip8_framebuffer(), both views into the module's linear memory.
+use crate::chip8::{Chip8, MAX_ROM_SIZE};
+use crate::rng::XorShift;
+
+use std::sync::Mutex;
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+struct Instance {
+    machine_state: Chip8,
+    rom: [u8; MAX_ROM_SIZE],
+    framebuffer: [u8; WIDTH * HEIGHT], // row-major, one byte (0 or 1) per pixel
+}
+
+static INSTANCE: Mutex<Option<Instance>> = Mutex::new(None);
+
+fn with_instance<T>(f: impl FnOnce(&mut Instance) -> T) -> T {
+    let mut guard = INSTANCE.lock().unwrap();
+    let instance = guard.get_or_insert_with(|| {
+        Instance {
+            machine_state: Chip8::new(),
+            rom: [0; MAX_ROM_SIZE],
+            framebuffer: [0; WIDTH * HEIGHT],
+        }
+    });
+    f(instance)
+}
+
+// Where JS should copy the ROM before calling chip8_load_rom; MAX_ROM_SIZE bytes long
+#[no_mangle]
+pub extern "C" fn chip8_rom_buffer() -> *mut u8 {
+    with_instance(|instance| instance.rom.as_mut_ptr())
+}
+
+// Resets the machine and loads the first len bytes of the ROM buffer
+#[no_mangle]
+pub extern "C" fn chip8_load_rom(len: usize) -> bool {
+    if len > MAX_ROM_SIZE {
+        return false;
+    }
+    with_instance(|instance| {
+        // Keep the random generator so a seed set before loading still applies
+        let rng = *instance.machine_state.rng_mut();
+        instance.machine_state = Chip8::with_rng(rng);
+        instance.machine_state.load_fonts();
+        instance.machine_state.load_rom(&instance.rom[..len]);
+        instance.framebuffer = [0; WIDTH * HEIGHT];
+        true
+    })
+}
+
+// JS has no good u64, so take the seed as two halves
+#[no_mangle]
+pub extern "C" fn chip8_seed(high: u32, low: u32) {
+    with_instance(|instance| *instance.machine_state.rng_mut() = XorShift::new((high as u64) << 32 | low as u64));
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_set_key(key: u32, pressed: bool) {
+    if key < 16 {
+        with_instance(|instance| instance.machine_state.set_key(key as usize, pressed));
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_set_cycles_per_frame(cycles: u32) {
+    with_instance(|instance| instance.machine_state.set_cycles_per_frame(cycles));
+}
+
+// Runs one 60 Hz frame. Returns true if the screen changed
+#[no_mangle]
+pub extern "C" fn chip8_run_frame() -> bool {
+    with_instance(|instance| {
+        instance.machine_state.emulate_frame();
+        if !instance.machine_state.get_draw() {
+            return false;
+        }
+        let gfx = instance.machine_state.get_gfx();
+        for (i, pixel) in instance.framebuffer.iter_mut().enumerate() {
+            *pixel = gfx[i % WIDTH][i / WIDTH];
+        }
+        instance.machine_state.set_draw(false);
+        true
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_framebuffer() -> *const u8 {
+    with_instance(|instance| instance.framebuffer.as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_framebuffer_width() -> u32 {
+    WIDTH as u32
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_framebuffer_height() -> u32 {
+    HEIGHT as u32
+}
+
+// True once the ROM has stopped on a fault such as a stack overflow
+#[no_mangle]
+pub extern "C" fn chip8_halted() -> bool {
+    with_instance(|instance| instance.machine_state.fault().is_some())
+}
+
+#[no_mangle]
+pub extern "C" fn chip8_sound_playing() -> bool {
+    with_instance(|instance| instance.machine_state.is_playing_sound())
+}
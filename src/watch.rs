@@ -0,0 +1,102 @@
+// Notices a file changing on disk by polling its modification time, cheap enough to
+// check a few times a second from a frontend's main loop
+use crate::asm::{load_program, Program};
+use crate::chip8::Chip8;
+use crate::rng::RandomSource;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> FileWatcher {
+        FileWatcher { path: path.to_path_buf(), modified: modified(path) }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // True once per change. A file that is missing for a moment (editors often replace
+    // the file on save) isn't a change until it is back
+    pub fn changed(&mut self) -> bool {
+        match modified(&self.path) {
+            Some(time) if Some(time) != self.modified => {
+                self.modified = Some(time);
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Puts a program back into a running machine whenever its file changes, so a frontend
+// can keep going while the ROM or source is rebuilt next to it
+pub struct Reloader {
+    watcher: FileWatcher,
+    keep_registers: bool,
+    reloads: u32
+}
+
+impl Reloader {
+    // With keep_registers the new program continues from the old registers, stack and
+    // screen (see Chip8::replace_rom) instead of starting over at 0x200
+    pub fn new(path: &Path, keep_registers: bool) -> Reloader {
+        Reloader { watcher: FileWatcher::new(path), keep_registers, reloads: 0 }
+    }
+
+    // The reloader a frontend runs a program from path with, if any: Octo source is
+    // assembled again whenever it is saved, ROMs are only reloaded with watch
+    pub fn for_program(path: &Path, program: &Program, watch: bool, keep_registers: bool) -> Option<Reloader> {
+        if watch || !program.labels.is_empty() {
+            Some(Reloader::new(path, keep_registers))
+        } else {
+            None
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.watcher.path()
+    }
+
+    // How many times the program has been reloaded so far
+    pub fn reloads(&self) -> u32 {
+        self.reloads
+    }
+
+    // None while the file is unchanged. Otherwise the program is loaded again: Ok once it
+    // is in the machine, Err if it couldn't be read or assembled, in which case the
+    // machine carries on with the old one
+    pub fn poll<R: RandomSource>(&mut self, machine_state: &mut Chip8<R>) -> Option<Result<Program, String>> {
+        if !self.changed() {
+            return None;
+        }
+        let program = load_program(self.watcher.path());
+        if let Ok(program) = &program {
+            self.replace(machine_state, &program.rom);
+        }
+        Some(program)
+    }
+
+    // poll in two halves, for loaders that read more than the program from the file
+    pub(crate) fn changed(&mut self) -> bool {
+        self.watcher.changed()
+    }
+
+    pub(crate) fn replace<R: RandomSource>(&mut self, machine_state: &mut Chip8<R>, rom: &[u8]) {
+        if self.keep_registers {
+            machine_state.replace_rom(rom);
+        } else {
+            machine_state.reset();
+            machine_state.load_rom(rom);
+        }
+        self.reloads += 1;
+    }
+}
@@ -0,0 +1,164 @@
+// Static analysis: control flow as execute_opcode would follow it, and what the bytes
+// that aren't code are used for. Programs are written in Octo so the addresses can be
+// looked up by label
+use chip8_rs::analysis::{analyze, Analysis, ComputedJump, Edge, EdgeKind, Region, RegionKind, SelfModifyingWrite};
+use chip8_rs::asm::{assemble, Program};
+use chip8_rs::chip8::{Platform, Quirks};
+use chip8_rs::symbols::Symbols;
+
+fn analyzed(source: &str) -> (Program, Analysis) {
+    let program = assemble(source).unwrap_or_else(|e| panic!("{}", e));
+    let analysis = analyze(&program.rom, &Quirks::default());
+    (program, analysis)
+}
+
+fn edge(kind: EdgeKind, target: u16) -> Edge {
+    Edge { kind, target }
+}
+
+#[test]
+fn blocks_follow_jumps_calls_and_skips() {
+    let source = "
+        : helper
+            v1 := 2
+            return
+        : main
+            v0 := 1
+            if v0 == 1 then v2 := 3
+            helper
+            : spin
+            jump spin
+    ";
+    let (program, analysis) = analyzed(source);
+    let main = program.labels["main"];
+    let spin = program.labels["spin"];
+    let starts: Vec<u16> = analysis.blocks.keys().copied().collect();
+    assert_eq!(starts, [0x200, 0x202, main, main + 4, main + 6, spin]);
+    assert_eq!(analysis.blocks[&0x200].edges, [edge(EdgeKind::Jump, main)]);
+    assert_eq!(analysis.blocks[&0x202].opcodes, [0x6102, 0x00EE]);
+    assert!(analysis.blocks[&0x202].edges.is_empty());
+    assert_eq!(analysis.blocks[&main].edges, [edge(EdgeKind::Next, main + 4), edge(EdgeKind::Skip, main + 6)]);
+    assert_eq!(analysis.blocks[&(main + 4)].edges, [edge(EdgeKind::Next, main + 6)]);
+    assert_eq!(analysis.blocks[&(main + 6)].edges, [edge(EdgeKind::Call, 0x202), edge(EdgeKind::Return, spin)]);
+    assert_eq!(analysis.blocks[&spin].edges, [edge(EdgeKind::Jump, spin)]);
+    assert_eq!(analysis.regions, [Region { kind: RegionKind::Code, start: 0x200, end: spin + 2 }]);
+}
+
+#[test]
+fn bytes_are_classified_by_how_they_are_used() {
+    let source = "
+        : main
+            i := ball
+            sprite v0 v0 3
+            i := score
+            bcd v1
+            i := table
+            load v1
+            loop again
+        : dead
+            v0 := 1
+        : ball 0x80 0x80 0x80
+        : score 0 0 0
+        : table 1 2
+    ";
+    let (program, analysis) = analyzed(source);
+    let region = |kind, start: &str, end: u16| Region { kind, start: program.labels[start], end };
+    assert_eq!(analysis.regions[1..], [
+        region(RegionKind::Unreachable, "dead", program.labels["ball"]),
+        region(RegionKind::Sprite, "ball", program.labels["score"]),
+        region(RegionKind::Data, "score", program.labels["table"] + 2),
+    ]);
+    assert_eq!(analysis.region_at(program.labels["main"]), Some(RegionKind::Code));
+    assert_eq!(analysis.region_at(0x1FF), None);
+    assert!(analysis.self_modifying.is_empty());
+}
+
+#[test]
+fn i_is_tracked_across_blocks_until_it_is_computed() {
+    let source = "
+        : main
+            i := first
+            if v0 == 1 then i := second
+            sprite v0 v0 1
+            i := first
+            i += v1
+            sprite v0 v0 1
+            loop again
+        : first 0xFF
+        : second 0xFF
+    ";
+    let (program, analysis) = analyzed(source);
+    // I could be either at the first sprite, and is unknown at the second
+    assert_eq!(analysis.region_at(program.labels["first"]), Some(RegionKind::Unreachable));
+    assert_eq!(analysis.region_at(program.labels["second"]), Some(RegionKind::Unreachable));
+
+    let (program, analysis) = analyzed(": main i := first if v0 == 1 then v1 := 2 sprite v0 v0 1 loop again : first 0xFF");
+    assert_eq!(analysis.region_at(program.labels["first"]), Some(RegionKind::Sprite));
+}
+
+#[test]
+fn stores_into_code_are_reported() {
+    let source = "
+        : main
+            i := patch
+            v0 := 0x61
+            v1 := 0x07
+            save v1
+        : patch
+            v1 := 0
+            loop again
+    ";
+    let (program, analysis) = analyzed(source);
+    let patch = program.labels["patch"];
+    assert_eq!(analysis.self_modifying, [SelfModifyingWrite { address: patch - 2, start: patch, end: patch + 2 }]);
+    assert_eq!(analysis.region_at(patch), Some(RegionKind::Code));
+}
+
+#[test]
+fn computed_jumps_depend_on_the_quirks() {
+    let (program, analysis) = analyzed(": main jump0 0x310 : after v0 := 1");
+    let main = program.labels["main"];
+    assert_eq!(analysis.computed_jumps, [ComputedJump { address: main, base: 0x310, register: 0 }]);
+    assert!(analysis.blocks[&main].edges.is_empty());
+    assert_eq!(analysis.region_at(program.labels["after"]), Some(RegionKind::Unreachable));
+
+    let analysis = analyze(&program.rom, &Platform::Schip.quirks());
+    assert_eq!(analysis.computed_jumps[0].register, 3);
+}
+
+#[test]
+fn undefined_opcodes_and_the_end_of_the_rom_end_the_code() {
+    // 5121 isn't an instruction, so the machine would stay on it; the last byte falls
+    // through into zeroed memory
+    let analysis = analyze(&[0x30, 0x00, 0x51, 0x21, 0x60], &Quirks::default());
+    assert_eq!(analysis.undefined, [0x202]);
+    assert!(analysis.report(&Symbols::default()).ends_with("undefined opcode at 0x202, where the program would hang\n"));
+    assert_eq!(analysis.blocks[&0x204].edges, [edge(EdgeKind::Next, 0x206)]);
+    assert_eq!(analysis.regions, [Region { kind: RegionKind::Code, start: 0x200, end: 0x205 }]);
+    assert!(analyze(&[], &Quirks::default()).blocks.is_empty());
+}
+
+#[test]
+fn dot_output_shows_blocks_and_edges() {
+    let (program, analysis) = analyzed(": main sub jump0 0x300 : sub ;");
+    let dot = analysis.dot(&program.symbols());
+    assert!(dot.starts_with("digraph rom {\n"));
+    assert!(dot.contains("\"0x202\" [label=\"0x202 (main)\\l202  2206  CALL 0x206\\l\"];"), "{}", dot);
+    assert!(dot.contains("\"0x202\" -> \"0x206\" [label=\"call\" style=dashed];"));
+    assert!(dot.contains("\"0x202\" -> \"0x204\" [label=\"return\" style=dotted];"));
+    assert!(dot.contains("\"0x204\" -> \"bnnn 0x204\" [style=dashed];"));
+    assert!(dot.ends_with("}\n"));
+
+    let report = analysis.report(&program.symbols());
+    assert_eq!(report, "4 blocks, 8 bytes of code\ncode        0x200 to 0x207 (sub+1)\ncomputed jump at 0x204 (main+2) to 0x300 + V0\n");
+}
+
+#[test]
+fn ibm_logo_is_code_then_sprites() {
+    let rom = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/roms/ibm-logo.ch8")).unwrap();
+    let analysis = analyze(&rom, &Quirks::default());
+    assert_eq!(analysis.regions, [
+        Region { kind: RegionKind::Code, start: 0x200, end: 0x21A },
+        Region { kind: RegionKind::Sprite, start: 0x21A, end: 0x200 + rom.len() as u16 },
+    ]);
+}
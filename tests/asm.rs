@@ -0,0 +1,289 @@
+// The Octo assembler: encodings, directives, control structures, and errors with their
+// positions. Programs that compute something are run on Chip8 and checked by result.
+// Also what the frontends do with source files: symbols, loading, watching and reset.
+mod common;
+
+use chip8_rs::asm::{assemble, load_program, AsmError};
+use chip8_rs::chip8::Chip8;
+use chip8_rs::watch::FileWatcher;
+use common::TempDir;
+
+// The opcodes after the jump to main
+fn opcodes(source: &str) -> Vec<u16> {
+    let program = assemble(source).unwrap_or_else(|e| panic!("{}", e));
+    program.rom[2..].chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect()
+}
+
+fn error(source: &str) -> (usize, usize, String) {
+    match assemble(source) {
+        Ok(_) => panic!("assembled without error:\n{}", source),
+        Err(AsmError { line, column, message }) => (line, column, message)
+    }
+}
+
+fn run(source: &str, frames: u32) -> Chip8 {
+    let program = assemble(source).unwrap_or_else(|e| panic!("{}", e));
+    let mut machine_state = Chip8::new();
+    machine_state.load_fonts();
+    machine_state.load_rom(&program.rom);
+    for _ in 0..frames {
+        machine_state.emulate_frame();
+    }
+    machine_state
+}
+
+#[test]
+fn every_statement() {
+    let source = "
+        : main
+        clear  return  ;  native 0x123
+        jump main  jump0 0x300  main
+        v1 := 0x42  v2 += 7  v2 -= 1  v3 := v4  v3 |= v4  v3 &= v4  v3 ^= v4
+        v3 += v4  v3 -= v4  v3 >>= v4  v3 =- v4  v3 <<= v4
+        i := 0x456  i := hex v5  i += v6  v7 := random 0x0F
+        sprite v1 v2 5  v8 := key  v9 := delay  delay := va  buzzer := vb
+        bcd vc  save vd  load ve
+        if vA == 3 then  if vA != 3 then  if vA == vB then  if vA != vB then
+        if vA key then  if vA -key then
+    ";
+    assert_eq!(opcodes(source), [
+        0x00E0, 0x00EE, 0x00EE, 0x0123,
+        0x1202, 0xB300, 0x2202,
+        0x6142, 0x7207, 0x72FF, 0x8340, 0x8341, 0x8342, 0x8343,
+        0x8344, 0x8345, 0x8346, 0x8347, 0x834E,
+        0xA456, 0xF529, 0xF61E, 0xC70F,
+        0xD125, 0xF80A, 0xF907, 0xFA15, 0xFB18,
+        0xFC33, 0xFD55, 0xFE65,
+        0x4A03, 0x3A03, 0x9AB0, 0x5AB0,
+        0xEAA1, 0xEA9E,
+    ]);
+}
+
+#[test]
+fn starts_with_a_jump_to_main() {
+    let program = assemble(": helper ; : main helper").unwrap();
+    assert_eq!(program.rom, [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+    assert_eq!(program.labels["helper"], 0x202);
+    assert_eq!(program.labels["main"], 0x204);
+}
+
+#[test]
+fn labels_can_be_used_before_they_are_defined() {
+    assert_eq!(opcodes(": main jump done i := data done : done ; : data 1 2"), [0x1208, 0xA20A, 0x2208, 0x00EE, 0x0102]);
+}
+
+#[test]
+fn constants_aliases_and_bytes() {
+    let source = "
+        :const SPEED 3
+        :alias player-x v4
+        : main
+        player-x += SPEED
+        :byte 0x80  :byte -1  0b101  7
+    ";
+    assert_eq!(opcodes(source), [0x7403, 0x80FF, 0x0507]);
+}
+
+#[test]
+fn calc_is_evaluated_right_to_left() {
+    let source = "
+        :calc EIGHT { 2 * 3 + 1 }
+        :calc SEVEN { ( 2 * 3 ) + 1 }
+        :calc LOW { 0x1234 & 0xFF }
+        : main
+        v0 := EIGHT  v1 := SEVEN  v2 := LOW
+    ";
+    assert_eq!(opcodes(source), [0x6008, 0x6107, 0x6234]);
+    assert_eq!(opcodes(": main :byte { HERE - 0x200 } :byte 0"), [0x0200]);
+}
+
+#[test]
+fn org_moves_the_output() {
+    let program = assemble(": main jump main :org 0x210 : data 0xAB").unwrap();
+    assert_eq!(program.rom.len(), 0x11);
+    assert_eq!(program.rom[0x10], 0xAB);
+    assert_eq!(program.labels["data"], 0x210);
+}
+
+#[test]
+fn macros_substitute_their_arguments() {
+    let source = "
+        :macro swap A B { vf := A  A := B  B := vf }
+        :macro twice STATEMENT ARG { STATEMENT ARG STATEMENT ARG }
+        : main
+        swap v1 v2
+        twice bcd v3
+    ";
+    assert_eq!(opcodes(source), [0x8F10, 0x8120, 0x82F0, 0xF333, 0xF333]);
+}
+
+#[test]
+fn unpack_and_next() {
+    let source = "
+        : main
+        :unpack 0xA target
+        :next operand
+        v5 := 0
+        : target
+    ";
+    assert_eq!(opcodes(source), [0x60A2, 0x6108, 0x6500]);
+    assert_eq!(assemble(source).unwrap().labels["operand"], 0x207);
+}
+
+#[test]
+fn if_begin_else_end() {
+    let source = "
+        : main
+        v0 := 5
+        if v0 == 5 begin v1 := 1 else v1 := 2 end
+        if v0 != 5 begin v2 := 1 else v2 := 2 end
+        if v0 > 4 begin v3 := 1 end
+        if v0 < 5 begin v4 := 1 end
+        if v0 <= 5 then v5 := 1
+        if v0 >= 6 then v6 := 1
+        loop again
+    ";
+    let machine_state = run(source, 10);
+    let results: Vec<u8> = (1..=6).map(|x| machine_state.v(x)).collect();
+    assert_eq!(results, [1, 2, 1, 0, 1, 0]);
+}
+
+#[test]
+fn loop_while_again() {
+    // Sums 1 to 10, and counts down nested loops
+    let source = "
+        : main
+        v0 := 0  v1 := 0
+        loop
+            v0 += 1
+            v1 += v0
+            while v0 != 10
+        again
+        v2 := 3
+        loop
+            while v2 != 0
+            v3 := 4
+            loop
+                v4 += 1
+                v3 -= 1
+                if v3 == 0 then jump inner-done
+            again
+            : inner-done
+            v2 -= 1
+        again
+        loop again
+    ";
+    let machine_state = run(source, 20);
+    assert_eq!((machine_state.v(0), machine_state.v(1), machine_state.v(4)), (10, 55, 12));
+}
+
+#[test]
+fn output_runs_on_chip8() {
+    // Draws a hex digit, then the 8-pixel wide sprite defined under it
+    let source = "
+        : main
+        clear
+        v0 := 0xE  v1 := 0  v2 := 0
+        i := hex v0
+        sprite v1 v2 5
+        i := bar
+        v1 := 8
+        sprite v1 v2 1
+        loop again
+        : bar 0xFF
+    ";
+    let mut machine_state = run(source, 5);
+    let gfx = machine_state.get_gfx();
+    let row: String = (0..16).map(|x| if gfx[x][0] == 1 { '#' } else { '.' }).collect();
+    assert_eq!(row, "####....########");
+}
+
+#[test]
+fn errors_point_at_the_offending_word() {
+    assert_eq!(error(": main\n  v1 := nowhere"), (2, 9, "undefined name nowhere".to_string()));
+    assert_eq!(error(": main\n  jump nowhere"), (2, 8, "undefined name nowhere".to_string()));
+    assert_eq!(error(": main v1 := 0x100"), (1, 14, "256 doesn't fit in a byte".to_string()));
+    assert_eq!(error(": main sprite v1 vg 1"), (1, 18, "expected a register, found vg".to_string()));
+    assert_eq!(error(": main\n\n  loop v0 += 1").0, 3);
+    assert_eq!(error(": main\n  loop v0 += 1").1, 3);
+    assert_eq!(error(": main else").2, "else without if ... begin");
+    assert_eq!(error(": main : main").2, "main is already defined");
+    assert_eq!(error("v0 := 1").2, "the program has no main label");
+    assert_eq!(error(": main hires"), (1, 8, "hires is a SUPER-CHIP/XO-CHIP statement, which this interpreter doesn't run".to_string()));
+    assert_eq!(error(": main :calc X { 1 + }").2, "expression ends too early");
+    assert_eq!(error(":macro loop-forever { loop-forever } : main loop-forever").2, "too many macro expansions, does loop-forever expand into itself?");
+    assert_eq!(error(": main v1 := 1 # comment\n  v1 ?? 2"), (2, 6, "unknown operator ??".to_string()));
+}
+
+#[test]
+fn labels_name_addresses() {
+    let program = assemble(": draw-player ; : main draw-player : data 1 2 3").unwrap();
+    let symbols = program.symbols();
+    assert_eq!(symbols.address("data"), Some(0x206));
+    assert_eq!(symbols.label(0x204).as_deref(), Some("main"));
+    assert_eq!(symbols.label(0x208).as_deref(), Some("data+2"));
+    assert_eq!(symbols.label(0x200), None);
+    assert_eq!(symbols.describe(0x203), "0x203 (draw-player+1)");
+    assert_eq!(symbols.describe(0x100), "0x100");
+}
+
+#[test]
+fn faults_are_reported_with_labels() {
+    let program = assemble(": main jump oops : oops ;").unwrap();
+    let mut machine_state = Chip8::new();
+    machine_state.load_rom(&program.rom);
+    machine_state.emulate_frame();
+    let fault = machine_state.fault().unwrap();
+    assert_eq!(program.symbols().fault(fault), "return with empty stack at 0x204 (oops)");
+}
+
+#[test]
+fn source_files_are_assembled_when_loaded() {
+    let dir = TempDir::new("asm-load");
+    let source = dir.join("game.8o");
+    std::fs::write(&source, ": main\n  v0 := 1\n").unwrap();
+    let program = load_program(&source).unwrap();
+    assert_eq!(program.rom, [0x12, 0x02, 0x60, 0x01]);
+    assert_eq!(program.labels["main"], 0x202);
+
+    std::fs::write(&source, ": main\n  v0 := nothing\n").unwrap();
+    assert_eq!(load_program(&source).err().unwrap(), format!("{}:2:9: undefined name nothing", source.display()));
+
+    let rom = dir.join("game.ch8");
+    std::fs::write(&rom, [0x60, 0x01]).unwrap();
+    let program = load_program(&rom).unwrap();
+    assert_eq!(program.rom, [0x60, 0x01]);
+    assert!(program.symbols().is_empty());
+}
+
+#[test]
+fn watcher_sees_each_change_once() {
+    let dir = TempDir::new("asm-watch");
+    let path = dir.join("game.8o");
+    std::fs::write(&path, ": main").unwrap();
+    let mut watcher = FileWatcher::new(&path);
+    assert!(!watcher.changed());
+    let later = std::time::SystemTime::now() + std::time::Duration::from_secs(5);
+    std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+    assert!(watcher.changed());
+    assert!(!watcher.changed());
+    // Gone for a moment while an editor replaces it
+    std::fs::remove_file(&path).unwrap();
+    assert!(!watcher.changed());
+}
+
+#[test]
+fn reset_keeps_the_font_and_settings() {
+    let mut machine_state = run(": main v0 := 5 i := hex v0 sprite v0 v0 5 loop again", 2);
+    machine_state.set_cycles_per_frame(20);
+    machine_state.reset();
+    assert_eq!((machine_state.v(0), machine_state.pc(), machine_state.i()), (0, 0x200, 0));
+    assert!(machine_state.get_gfx().iter().flatten().all(|&pixel| pixel == 0));
+    assert_eq!(machine_state.memory()[0x202], 0);
+    assert_eq!(machine_state.memory()[..5], [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+
+    let program = assemble(": main v1 := 1 loop v1 += 1 again").unwrap();
+    machine_state.load_rom(&program.rom);
+    machine_state.emulate_frame();
+    assert_eq!(machine_state.v(1), 10);
+}
@@ -0,0 +1,144 @@
+// The beeper's output as the machine drives it, and WAV files written from it
+use chip8_rs::audio::{AudioConfig, Beeper, WavWriter, Waveform};
+use chip8_rs::chip8::Chip8;
+use std::io::Cursor;
+
+const SAMPLE_RATE: u32 = 44_100;
+
+// Full volume with no fades, so every sample is the bare waveform. The pitch makes a
+// period 64 samples, which the phase steps through exactly
+fn bare(waveform: Waveform) -> AudioConfig {
+    AudioConfig { waveform, pitch: SAMPLE_RATE as f32 / 64.0, volume: 1.0, muted: false, attack: 0.0, release: 0.0 }
+}
+
+fn machine(rom: &[u8], config: AudioConfig, sample_rate: u32) -> Chip8 {
+    let mut machine_state = Chip8::new();
+    machine_state.load_rom(rom);
+    machine_state.enable_audio(config, sample_rate);
+    machine_state
+}
+
+fn frame(machine_state: &mut Chip8) -> Vec<f32> {
+    let mut samples = Vec::new();
+    machine_state.emulate_frame_with_audio(&mut samples);
+    samples
+}
+
+#[test]
+fn silent_until_the_sound_timer_starts() {
+    // Two cycles of v0 := 5, then FX18 starts the sound timer and the ROM spins
+    let mut machine_state = machine(&[0x60, 0x05, 0x60, 0x05, 0xF0, 0x18, 0x12, 0x06], bare(Waveform::Square), SAMPLE_RATE);
+    let samples = frame(&mut machine_state);
+    // 735 samples a frame, 81 or 82 a cycle
+    let first_sound = samples.iter().position(|&sample| sample != 0.0).unwrap();
+    assert_eq!(first_sound, SAMPLE_RATE as usize * 2 / (60 * 9));
+    assert!(samples[first_sound..].iter().all(|&sample| sample.abs() == 1.0));
+
+    // The timer runs out after five frames, and with it the sound
+    for _ in 0..4 {
+        assert!(frame(&mut machine_state).iter().all(|&sample| sample != 0.0));
+    }
+    assert!(frame(&mut machine_state).iter().all(|&sample| sample == 0.0));
+}
+
+#[test]
+fn every_frame_gets_a_sixtieth_of_a_second() {
+    for (sample_rate, cycles_per_frame) in [(44_100, 9), (48_000, 9), (48_000, 15), (44_100, 1000), (96_000, 7)] {
+        let mut machine_state = machine(&[0x12, 0x00], AudioConfig::default(), sample_rate);
+        machine_state.set_cycles_per_frame(cycles_per_frame);
+        for _ in 0..120 {
+            assert_eq!(frame(&mut machine_state).len(), sample_rate as usize / 60, "{} Hz, {} cycles a frame", sample_rate, cycles_per_frame);
+        }
+    }
+    // Rates that don't divide by 60 alternate, and come out right over a second
+    let mut machine_state = machine(&[0x12, 0x00], AudioConfig::default(), 22_050);
+    let lengths: Vec<usize> = (0..60).map(|_| frame(&mut machine_state).len()).collect();
+    assert!(lengths.iter().all(|&length| length == 367 || length == 368));
+    assert_eq!(lengths.iter().sum::<usize>(), 22_050);
+}
+
+#[test]
+fn the_envelope_fades_in_and_out() {
+    // 10 ms each way is 441 samples
+    let config = AudioConfig { attack: 0.01, release: 0.01, ..bare(Waveform::Square) };
+    let mut beeper = Beeper::new(config, SAMPLE_RATE);
+    let rising: Vec<f32> = (0..441).map(|_| beeper.next_sample(true).abs()).collect();
+    assert!(rising.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!((rising[0] - 1.0 / 441.0).abs() < 1e-6);
+    assert!((rising[440] - 1.0).abs() < 1e-3);
+    assert_eq!(beeper.next_sample(true).abs(), 1.0);
+
+    let falling: Vec<f32> = (0..441).map(|_| beeper.next_sample(false).abs()).collect();
+    assert!(falling.windows(2).all(|pair| pair[0] > pair[1]));
+    assert!(falling[440] < 1e-3);
+    assert_eq!(beeper.next_sample(false), 0.0);
+
+    // Muting fades out the same way
+    let mut beeper = Beeper::new(config, SAMPLE_RATE);
+    for _ in 0..441 {
+        beeper.next_sample(true);
+    }
+    assert!(beeper.toggle_mute());
+    let muted: Vec<f32> = (0..442).map(|_| beeper.next_sample(true).abs()).collect();
+    assert!(muted[0] > 0.99 && muted[441] == 0.0);
+}
+
+#[test]
+fn waveforms_have_their_shapes() {
+    let period = |waveform| {
+        let mut beeper = Beeper::new(bare(waveform), SAMPLE_RATE);
+        (0..64).map(|_| beeper.next_sample(true)).collect::<Vec<f32>>()
+    };
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-3;
+
+    let square = period(Waveform::Square);
+    assert!(square[..32].iter().all(|&sample| sample == 1.0));
+    assert!(square[32..].iter().all(|&sample| sample == -1.0));
+
+    let sine = period(Waveform::Sine);
+    assert!(close(sine[0], 0.0) && close(sine[16], 1.0) && close(sine[32], 0.0) && close(sine[48], -1.0));
+
+    let triangle = period(Waveform::Triangle);
+    assert!(close(triangle[0], -1.0) && close(triangle[16], 0.0) && close(triangle[32], 1.0) && close(triangle[48], 0.0));
+
+    // Noise holds a random level of full amplitude for each period
+    let mut beeper = Beeper::new(bare(Waveform::Noise), SAMPLE_RATE);
+    let levels: Vec<f32> = (0..64 * 64).map(|_| beeper.next_sample(true)).collect();
+    assert!(levels.chunks(64).all(|period| period.iter().all(|&sample| sample == period[0] && sample.abs() == 1.0)));
+    let highs = levels.chunks(64).filter(|period| period[0] > 0.0).count();
+    assert!((16..48).contains(&highs), "{} of 64 periods high", highs);
+
+    // and volume scales all of them
+    let mut beeper = Beeper::new(AudioConfig { volume: 0.25, ..bare(Waveform::Square) }, SAMPLE_RATE);
+    assert_eq!(beeper.next_sample(true), 0.25);
+}
+
+#[test]
+fn wav_files_have_a_riff_header_with_the_data_length() {
+    let mut wav = WavWriter::new(Cursor::new(Vec::new()), SAMPLE_RATE).unwrap();
+    let mut machine_state = machine(&[0x60, 0x3C, 0xF0, 0x18, 0x12, 0x04], bare(Waveform::Square), SAMPLE_RATE);
+    for _ in 0..3 {
+        machine_state.emulate_frame_with_audio(&mut wav);
+    }
+    let bytes = wav.finish().unwrap().into_inner();
+    let data_length = 3 * 735 * 2;
+    assert_eq!(bytes.len(), 44 + data_length);
+
+    let word = |at: usize| u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]]);
+    let half = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(word(4) as usize, 36 + data_length);
+    assert_eq!(&bytes[8..16], b"WAVEfmt ");
+    assert_eq!(word(16), 16);
+    assert_eq!((half(20), half(22)), (1, 1)); // PCM, mono
+    assert_eq!(word(24), SAMPLE_RATE);
+    assert_eq!(word(28), SAMPLE_RATE * 2);
+    assert_eq!((half(32), half(34)), (2, 16));
+    assert_eq!(&bytes[36..40], b"data");
+    assert_eq!(word(40) as usize, data_length);
+
+    // Silence for the first cycle, then the square wave at full scale
+    let samples: Vec<i16> = bytes[44..].chunks(2).map(|pcm| i16::from_le_bytes([pcm[0], pcm[1]])).collect();
+    assert!(samples[..81].iter().all(|&sample| sample == 0));
+    assert!(samples[200..].iter().all(|&sample| sample == i16::MAX || sample == -i16::MAX));
+}
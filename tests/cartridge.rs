@@ -0,0 +1,150 @@
+// Octo cartridges, built here the way Octo does it: the payload in the low two bits of
+// the palette indices, each visible colour repeated four times in the palette
+#![cfg(feature = "cartridge")]
+mod common;
+
+use chip8_rs::asm::load_program;
+use chip8_rs::cartridge::{load, read};
+use chip8_rs::chip8::Quirks;
+use chip8_rs::font::Font;
+use common::TempDir;
+use std::path::Path;
+
+// Two bits per pixel, high bits first, under a picture (a stripe of the second colour)
+// kept in the bits above
+fn hide(bytes: &[u8]) -> Vec<u8> {
+    bytes.iter()
+        .flat_map(|&byte| [byte >> 6, byte >> 4 & 3, byte >> 2 & 3, byte & 3])
+        .enumerate().map(|(k, bits)| if k % 7 == 0 { 4 | bits } else { bits })
+        .collect()
+}
+
+fn gif(mut pixels: Vec<u8>, width: u16, height: u16) -> Vec<u8> {
+    pixels.resize(width as usize * height as usize, 4);
+    let palette: Vec<u8> = [[0x00, 0x00, 0x00], [0xFF, 0xCC, 0x00]].iter()
+        .flat_map(|color| std::iter::repeat_n(*color, 4)).flatten().collect();
+    let mut gif = Vec::new();
+    {
+        let mut encoder = gif::Encoder::new(&mut gif, width, height, &palette).unwrap();
+        let mut frame = gif::Frame::from_indexed_pixels(width, height, pixels, None);
+        frame.delay = 10;
+        encoder.write_frame(&frame).unwrap();
+        // Octo's cartridges are animated; only the first frame holds the program
+        encoder.write_frame(&gif::Frame::from_indexed_pixels(width, height, vec![5; width as usize * height as usize], None)).unwrap();
+    }
+    gif
+}
+
+fn cartridge(json: &str, width: u16, height: u16) -> Vec<u8> {
+    let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+    payload.extend_from_slice(json.as_bytes());
+    gif(hide(&payload), width, height)
+}
+
+const GAME: &str = r##"{
+    "program": ": main\n  v0 := 7\n  loop again\n",
+    "options": {
+        "tickrate": 30,
+        "fillColor": "#FFCC00",
+        "fillColor2": "#FF6600",
+        "blendColor": "#662200",
+        "backgroundColor": "#996600",
+        "buzzColor": "#FFAA00",
+        "quietColor": "#000000",
+        "shiftQuirks": true,
+        "loadStoreQuirks": true,
+        "vfOrderQuirks": false,
+        "clipQuirks": true,
+        "jumpQuirks": false,
+        "vBlankQuirks": true,
+        "logicQuirks": false,
+        "screenRotation": 0,
+        "fontStyle": "octo"
+    }
+}"##;
+
+#[test]
+fn program_and_options_come_out_of_the_picture() {
+    let cartridge = read(&cartridge(GAME, 160, 100)).unwrap();
+    assert_eq!(cartridge.source, ": main\n  v0 := 7\n  loop again\n");
+    assert_eq!(cartridge.program.rom, [0x12, 0x02, 0x60, 0x07, 0x12, 0x04]);
+    assert_eq!(cartridge.program.labels["main"], 0x202);
+    let info = cartridge.info;
+    assert_eq!(info.tickrate, Some(30));
+    assert_eq!(info.pixels, [[0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00], [0xFF, 0x66, 0x00], [0x66, 0x22, 0x00]]);
+    assert_eq!((info.buzzer, info.silence), (Some([0xFF, 0xAA, 0x00]), Some([0, 0, 0])));
+    assert_eq!(info.quirks, Some(Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        clip_sprites: true,
+        jump_uses_vx: false,
+        display_wait: true,
+        vf_reset: false,
+        ..Quirks::default()
+    }));
+    assert_eq!(info.font, Some(Font::Standard));
+}
+
+#[test]
+fn font_styles_pick_the_matching_font() {
+    let style = |name: &str| {
+        let json = format!(r#"{{"program": ": main", "options": {{"fontStyle": "{}"}}}}"#, name);
+        read(&cartridge(&json, 64, 64)).unwrap().info.font
+    };
+    assert_eq!(style("vip"), Some(Font::Vip));
+    assert_eq!(style("dream6800"), Some(Font::Dream6800));
+    assert_eq!(style("eti660"), Some(Font::Eti660));
+    assert_eq!(style("fish"), Some(Font::FishNChips));
+    assert_eq!(style("schip"), Some(Font::Standard));
+    assert_eq!(style("comic"), None);
+    assert_eq!(read(&cartridge(r#"{"program": ": main"}"#, 16, 16)).unwrap().info.font, None);
+}
+
+#[test]
+fn missing_options_are_octos_defaults() {
+    let cartridge = read(&cartridge(r#"{"program": ": main"}"#, 16, 16)).unwrap();
+    let quirks = cartridge.info.quirks.unwrap();
+    assert!(quirks.shift_uses_vy && quirks.load_store_increments_i && !quirks.clip_sprites && !quirks.jump_uses_vx);
+    assert_eq!((cartridge.info.tickrate, cartridge.info.pixels.len()), (None, 0));
+}
+
+#[test]
+fn damaged_cartridges_are_reported() {
+    let error = |gif: &[u8]| read(gif).err().unwrap();
+    assert!(error(b"GIF89a nothing").starts_with("not a GIF"));
+    let mut truncated = cartridge(r#"{"program": ": main"}"#, 16, 16);
+    truncated.truncate(truncated.len() / 2);
+    assert!(error(&truncated).starts_with("not a GIF"));
+    assert_eq!(error(&gif(vec![], 3, 2)), "not an Octo cartridge: the image is too small");
+    // 100 bytes promised in a picture with room for 12
+    assert_eq!(error(&gif(hide(&[0, 0, 0, 100]), 8, 8)), "not an Octo cartridge: the payload is cut short");
+    assert!(error(&cartridge("[1, 2", 16, 16)).starts_with("not an Octo cartridge: "));
+    assert_eq!(error(&cartridge(r#"{"options": {}}"#, 16, 16)), "the cartridge has no program");
+    assert_eq!(error(&cartridge(r#"{"program": ": main v0 := nothing"}"#, 32, 16)), "program 1:14: undefined name nothing");
+}
+
+#[test]
+fn cartridges_load_like_any_other_program() {
+    let dir = TempDir::new("cartridge-load");
+    let path = dir.join("Space Game.GIF");
+    std::fs::write(&path, cartridge(GAME, 160, 100)).unwrap();
+    let cartridge = load(&path).unwrap();
+    assert_eq!(cartridge.info.title, "Space Game");
+    assert_eq!(load_program(&path).unwrap().rom, cartridge.program.rom);
+
+    std::fs::write(&path, b"GIF").unwrap();
+    assert!(load_program(&path).err().unwrap().starts_with(&format!("{}: not a GIF", path.display())));
+}
+
+#[test]
+fn the_fixture_cartridge_runs_the_ibm_logo() {
+    let cartridge = load(Path::new("tests/cartridges/ibm-logo.gif")).unwrap();
+    assert_eq!(cartridge.source, std::fs::read_to_string("tests/roms/ibm-logo.8o").unwrap());
+    assert_eq!(cartridge.program.rom, std::fs::read("tests/roms/ibm-logo.ch8").unwrap());
+    let info = cartridge.info;
+    assert_eq!(info.title, "ibm-logo");
+    assert_eq!((info.tickrate, info.font), (Some(20), Some(Font::Vip)));
+    assert_eq!(info.quirks, Some(Quirks { vf_reset: true, clip_sprites: false, ..Quirks::default() }));
+    // Octo's options don't describe the keys
+    assert!(info.keys.is_empty());
+}
@@ -0,0 +1,51 @@
+// Helpers shared by the integration tests, each of which uses only some of them
+#![allow(dead_code)]
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// A directory for one test's files, removed with everything in it when the test ends,
+// passed or failed. Names have to differ between the tests of a file, which run at once
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("chip8-rs-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// Builds the library as crate_type for another target with the given features, returning
+// the target's output directory. Panics if the target isn't installed, so the tests that
+// call it are #[ignore]d and run with `cargo test -- --ignored` once it is.
+pub fn build_for_target(target: &str, crate_type: &str, features: &str) -> PathBuf {
+    let sysroot = Command::new("rustc").args(["--print", "sysroot"]).output().expect("failed to run rustc");
+    let sysroot = String::from_utf8(sysroot.stdout).unwrap();
+    assert!(Path::new(sysroot.trim()).join("lib/rustlib").join(target).exists(),
+        "the {} target is not installed (rustup target add {})", target, target);
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = manifest_dir.join("target").join(format!("{}-test", target));
+    let status = Command::new(env!("CARGO"))
+        .current_dir(manifest_dir)
+        .args(["rustc", "--lib", "--crate-type", crate_type, "--target", target, "--no-default-features", "--features", features, "--target-dir"])
+        .arg(&target_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "build for {} failed", target);
+    target_dir.join(target).join("debug")
+}
@@ -0,0 +1,167 @@
+// Runs the ROMs in tests/roms headlessly under each quirks preset and compares the final
+// screen with the golden images in tests/golden, one per ROM and preset. The goldens are
+// the reference model's screens (tests/reference), never Chip8's: set CHIP8_BLESS=1 to
+// write them again from the model after changing a ROM.
+// Each ROM is assembled from its .8o source, which has to give the .ch8 next to it, and
+// the checks it draws have to come out as a tick or a cross as the source says.
+// With the jit feature, each ROM is run again through the JIT and has to match too.
+use chip8_rs::asm;
+use chip8_rs::chip8::{Chip8, Platform, Quirks, CYCLES_PER_FRAME};
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+mod reference;
+use reference::Reference;
+
+const PRESETS: [&str; 4] = ["default", "vip", "schip", "xochip"];
+
+// (frame, key, pressed)
+type KeyScript<'a> = &'a [(u32, usize, bool)];
+
+fn quirks(preset: &str) -> Quirks {
+    match preset {
+        "default" => Quirks::default(),
+        name => name.parse::<Platform>().unwrap().quirks()
+    }
+}
+
+fn test_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests")
+}
+
+// One line per row, '#' for a lit pixel
+fn screen(gfx: &[[u8; 32]; 64]) -> String {
+    let mut out = String::new();
+    for y in 0..32 {
+        for column in gfx.iter() {
+            out.push(if column[y] == 1 { '#' } else { '.' });
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// The marks the check ROMs draw in 6x6 cells, ten to a row from the top left: 'v' for a
+// tick, 'x' for a cross and '#' for anything else, up to the first empty cell
+fn marks(screen: &str) -> String {
+    const TICK: [&str; 5] = ["...#", "...#", "#.#.", "#.#.", ".#.."];
+    const CROSS: [&str; 5] = ["#..#", ".##.", ".##.", "#..#", "...."];
+    let rows: Vec<&str> = screen.lines().collect();
+    let mut out = String::new();
+    for cell in 0..50 {
+        let (x, y) = (cell % 10 * 6, cell / 10 * 6);
+        let glyph: Vec<&str> = (0..5).map(|row| &rows[y + row][x..x + 4]).collect();
+        out.push(match glyph.as_slice() {
+            g if g == TICK => 'v',
+            g if g == CROSS => 'x',
+            g if g.iter().all(|row| !row.contains('#')) => break,
+            _ => '#'
+        });
+    }
+    out
+}
+
+fn run(rom: &[u8], quirks: Quirks, frames: u32, keys: KeyScript, jit: bool) -> String {
+    let mut machine_state = Chip8::new();
+    if jit {
+        #[cfg(feature = "jit")]
+        assert!(machine_state.set_jit(true));
+    }
+    machine_state.set_quirks(quirks);
+    machine_state.load_fonts();
+    machine_state.load_rom(rom);
+    for frame in 0..frames {
+        for &(_, key, pressed) in keys.iter().filter(|&&(at, _, _)| at == frame) {
+            machine_state.set_key(key, pressed);
+        }
+        machine_state.emulate_frame();
+    }
+    assert_eq!(machine_state.fault(), None);
+    screen(machine_state.get_gfx())
+}
+
+fn run_reference(rom: &[u8], quirks: Quirks, frames: u32, keys: KeyScript) -> String {
+    let mut model = Reference::new(quirks, rom, 0);
+    for frame in 0..frames {
+        for &(_, key, pressed) in keys.iter().filter(|&&(at, _, _)| at == frame) {
+            model.set_key(key, pressed);
+        }
+        model.frame(CYCLES_PER_FRAME);
+    }
+    screen(&model.screen)
+}
+
+fn assemble(name: &str) -> Vec<u8> {
+    let roms = test_dir().join("roms");
+    let source = std::fs::read_to_string(roms.join(format!("{}.8o", name))).unwrap();
+    let rom = asm::assemble(&source).unwrap_or_else(|e| panic!("{}.8o:{}", name, e)).rom;
+    let built = std::fs::read(roms.join(format!("{}.ch8", name))).unwrap();
+    assert!(rom == built, "{}.ch8 isn't what {}.8o assembles to", name, name);
+    rom
+}
+
+// expected has the marks for each preset in PRESETS order, or None for a ROM without any
+fn check(name: &str, frames: u32, keys: KeyScript, expected: Option<[&str; 4]>) {
+    let rom = assemble(name);
+    let bless = std::env::var_os("CHIP8_BLESS").is_some();
+    let mut failures = String::new();
+    for (k, preset) in PRESETS.iter().enumerate() {
+        let path = test_dir().join("golden").join(format!("{}.{}.txt", name, preset));
+        let reference = run_reference(&rom, quirks(preset), frames, keys);
+        if bless {
+            std::fs::write(&path, &reference).unwrap();
+        }
+        let golden = std::fs::read_to_string(&path)
+            .unwrap_or_else(|e| panic!("{}: {} (run with CHIP8_BLESS=1 to create it)", path.display(), e));
+        if reference != golden {
+            writeln!(failures, "{} with {} quirks: the reference model no longer gives the golden screen", name, preset).unwrap();
+        }
+        if let Some(expected) = expected {
+            if marks(&golden) != expected[k] {
+                writeln!(failures, "{} with {} quirks shows {}, the source expects {}", name, preset, marks(&golden), expected[k]).unwrap();
+            }
+        }
+        let actual = run(&rom, quirks(preset), frames, keys, false);
+        if actual != golden {
+            writeln!(failures, "{} with {} quirks, expected:\n{}got:\n{}", name, preset, golden, actual).unwrap();
+        }
+        if cfg!(feature = "jit") {
+            let compiled = run(&rom, quirks(preset), frames, keys, true);
+            if compiled != actual {
+                writeln!(failures, "{} with {} quirks differs under the JIT, interpreted:\n{}compiled:\n{}", name, preset, actual, compiled).unwrap();
+            }
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures);
+}
+
+#[test]
+fn ibm_logo() {
+    check("ibm-logo", 10, &[], None);
+}
+
+#[test]
+fn opcode_checks() {
+    let all = "vvvvvvvvvvvvvvvvvvvvvvv";
+    check("opcode-checks", 120, &[], Some([all; 4]));
+}
+
+#[test]
+fn flag_checks() {
+    let all = "vvvvvvvvvvvvvvv";
+    check("flag-checks", 120, &[], Some([all; 4]));
+}
+
+// VF reset, FX55 increments I, display wait, clipping, shifts use VY, BNNN uses V0
+#[test]
+fn quirk_checks() {
+    check("quirk-checks", 120, &[], Some(["xvxvvv", "vvvvvv", "xxxvxx", "xvxxvv"]));
+}
+
+// Types 5, then holds and releases 1 for the EX9E and EXA1 checks
+#[test]
+fn key_checks() {
+    let keys = [(5, 5, true), (10, 5, false), (20, 1, true), (30, 1, false)];
+    check("key-checks", 60, &keys, Some(["v#vv"; 4]));
+}
@@ -0,0 +1,91 @@
+// The ROM database: programs.json in the community database's format, looked up by SHA-1
+#![cfg(feature = "database")]
+use chip8_rs::chip8::{Platform, Quirks};
+use chip8_rs::database::{sha1, Database};
+
+const PROGRAMS: &str = r##"[
+  {
+    "title": "Space Race",
+    "authors": ["Someone", "Someone Else"],
+    "release": "1991",
+    "roms": {
+      "0123456789ABCDEF0123456789ABCDEF01234567": {
+        "file": "race.ch8",
+        "platforms": ["chip8x", "superchip", "xochip"],
+        "quirkyPlatforms": {
+          "superchip": { "vblank": true, "shift": false },
+          "xochip": { "logic": true }
+        },
+        "tickrate": 30,
+        "colors": { "pixels": ["#102030", "#FFcc00"], "buzzer": "#ff0000", "silence": "not a colour" },
+        "keys": { "up": 5, "down": 8, "fire": 10, "nonsense": 99 }
+      },
+      "fedcba9876543210fedcba9876543210fedcba98": {
+        "file": "race-vip.ch8",
+        "platforms": ["modernChip8"]
+      }
+    }
+  },
+  { "title": "Untested", "roms": {} }
+]"##;
+
+#[test]
+fn sha1_is_lowercase_hex() {
+    assert_eq!(sha1(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    assert_eq!(sha1(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+}
+
+#[test]
+fn entries_carry_the_settings_of_their_rom() {
+    let database = Database::parse(PROGRAMS).unwrap();
+    assert_eq!(database.len(), 2);
+    let info = database.get("0123456789abcdef0123456789abcdef01234567").unwrap();
+    assert_eq!(info.title, "Space Race");
+    assert_eq!(info.authors, ["Someone", "Someone Else"]);
+    assert_eq!(info.release.as_deref(), Some("1991"));
+    assert_eq!(info.platforms, ["chip8x", "superchip", "xochip"]);
+    // chip8x has no profile here, so the next platform is used, with its exceptions
+    assert_eq!(info.platform, Some(Platform::Schip));
+    let quirks = info.quirks.unwrap();
+    assert_eq!(quirks, Quirks { display_wait: true, shift_uses_vy: true, ..Platform::Schip.quirks() });
+    assert_eq!(info.tickrate, Some(30));
+    assert_eq!(info.pixels, [[0x10, 0x20, 0x30], [0xFF, 0xCC, 0x00]]);
+    assert_eq!((info.buzzer, info.silence), (Some([0xFF, 0, 0]), None));
+    assert_eq!(info.key_hints().as_deref(), Some("down 8, fire A, up 5"));
+
+    let plain = database.get("FEDCBA9876543210FEDCBA9876543210FEDCBA98").unwrap();
+    assert_eq!(plain.title, "Space Race");
+    assert_eq!(plain.platform, None);
+    assert_eq!(plain.quirks, Some(Quirks::default()));
+    assert_eq!((plain.tickrate, plain.key_hints()), (None, None));
+    assert!(database.get("0000000000000000000000000000000000000000").is_none());
+}
+
+#[test]
+fn database_platforms_map_to_quirks() {
+    let database = Database::parse(r#"[{ "title": "VIP", "roms": { "aa": { "platforms": ["originalChip8"] } } }]"#).unwrap();
+    assert_eq!(database.get("aa").unwrap().quirks, Some(Platform::Vip.quirks()));
+    let database = Database::parse(r#"[{ "title": "XO", "roms": { "aa": { "platforms": ["xochip"] } } }]"#).unwrap();
+    assert_eq!(database.get("aa").unwrap().quirks, Some(Platform::XoChip.quirks()));
+    let database = Database::parse(r#"[{ "title": "Mega", "roms": { "aa": { "platforms": ["megachip8"] } } }]"#).unwrap();
+    assert_eq!(database.get("aa").unwrap().quirks, None);
+}
+
+#[test]
+fn malformed_files_are_errors() {
+    assert!(Database::parse("[{").is_err());
+    assert_eq!(Database::parse("{}").err().unwrap(), "expected a list of programs");
+    let path = std::path::Path::new("/nonexistent/programs.json");
+    assert!(Database::load(path).err().unwrap().starts_with("/nonexistent/programs.json: "));
+}
+
+#[test]
+fn the_test_roms_are_built_in() {
+    let database = Database::builtin();
+    let rom = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/roms/quirk-checks.ch8")).unwrap();
+    let info = database.lookup(&rom).unwrap();
+    assert_eq!(info.title, "Quirks test");
+    assert_eq!(info.quirks, Some(Platform::Vip.quirks()));
+    assert_eq!(info.tickrate, Some(15));
+    assert!(database.lookup(&[0x12, 0x00]).is_none());
+}
@@ -0,0 +1,62 @@
+// Platform detection from the instructions a ROM can reach
+use chip8_rs::chip8::{Platform, Quirks};
+use chip8_rs::detect::{detect, DisplayMode};
+
+// The opcodes from 0x200, then a jump to itself so nothing runs past them
+fn rom(opcodes: &[u16]) -> Vec<u8> {
+    let end = 0x200 + 2 * opcodes.len() as u16;
+    opcodes.iter().chain(&[0x1000 | end]).flat_map(|opcode| opcode.to_be_bytes()).collect()
+}
+
+#[test]
+fn superchip_instructions_pick_schip() {
+    let detection = detect(&rom(&[0x00E0, 0x00FF, 0x6000, 0xD010]));
+    assert_eq!(detection.platform, Some(Platform::Schip));
+    assert_eq!(detection.display, DisplayMode::High);
+    assert_eq!(detection.quirks(), Platform::Schip.quirks());
+    assert_eq!(detection.explain(), "schip quirks for 00FF at 0x202 (high resolution), D010 at 0x206 (16x16 sprite); \
+        it uses the 128x64 display, which this interpreter can't show");
+}
+
+#[test]
+fn xochip_wins_over_the_superchip_it_extends() {
+    let detection = detect(&rom(&[0x00FE, 0x5122, 0x00FB]));
+    assert_eq!(detection.platform, Some(Platform::XoChip));
+    assert_eq!(detection.display, DisplayMode::Low);
+    assert_eq!(detection.explain(), "xochip quirks for 5122 at 0x202 (save register range)");
+    // F000 takes the next two bytes as an address, so they aren't looked at as code
+    assert_eq!(detect(&[0xF0, 0x00, 0x00, 0xFF]).platform, Some(Platform::XoChip));
+}
+
+#[test]
+fn machine_code_calls_pick_the_vip() {
+    let detection = detect(&rom(&[0x0300, 0x0300, 0x0320, 0x0340, 0x00E0]));
+    assert_eq!(detection.platform, Some(Platform::Vip));
+    assert_eq!(detection.explain(), "vip quirks for 0300 at 0x200 (machine code call), 0300 at 0x202 (machine code call), \
+        0320 at 0x204 (machine code call), 1 more");
+}
+
+#[test]
+fn only_reachable_instructions_count() {
+    // Sprite data that happens to read as 00FF and F000
+    let detection = detect(&[0x12, 0x00, 0x00, 0xFF, 0xF0, 0x00]);
+    assert_eq!(detection.platform, None);
+    assert!(detection.evidence.is_empty());
+    assert_eq!(detection.quirks(), Quirks::default());
+    assert_eq!(detection.explain(), "default quirks, no platform-specific instructions found");
+}
+
+#[test]
+fn shift_operands_are_a_hint() {
+    // SHR V1 as SUPER-CHIP assemblers wrote it
+    assert_eq!(detect(&rom(&[0x8106, 0x820E])).platform, Some(Platform::Schip));
+    // V2 shifted into V1
+    let detection = detect(&rom(&[0x8126]));
+    assert_eq!(detection.platform, Some(Platform::Vip));
+    assert!(!detection.evidence[0].telltale);
+    // Shifting a register in place means the same everywhere, and a tie decides nothing
+    assert_eq!(detect(&rom(&[0x8116, 0x800E])).platform, None);
+    assert_eq!(detect(&rom(&[0x8106, 0x8126])).platform, None);
+    // Any telltale instruction outweighs the hints
+    assert_eq!(detect(&rom(&[0x8126, 0x8126, 0x00FE])).platform, Some(Platform::Schip));
+}
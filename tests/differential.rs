@@ -0,0 +1,259 @@
+// Runs random instruction streams on Chip8 and on the reference model in tests/reference,
+// comparing the whole machine after every step, for each quirks preset.
+// Each step is a one-instruction frame, so timers tick every step and display wait never
+// stalls a stream.
+// With the jit feature, the same streams also run through the JIT against the interpreter,
+// in longer frames so there are whole blocks to compile.
+use chip8_rs::chip8::{Chip8, Platform, Quirks};
+use chip8_rs::rng::XorShift;
+use proptest::prelude::*;
+
+mod reference;
+use reference::{Halt, Reference, ROM_START};
+
+const STEPS: usize = 300;
+
+fn compare(step: usize, opcode: u16, machine_state: &mut Chip8, model: &Reference) -> Result<(), TestCaseError> {
+    let at = Step(step, opcode);
+    for x in 0..16 {
+        prop_assert_eq!(machine_state.v(x), model.v[x], "V{:X} at {}", x, at);
+    }
+    prop_assert_eq!(machine_state.i(), model.i, "I at {}", at);
+    prop_assert_eq!(machine_state.pc() & 0xFFF, model.pc & 0xFFF, "pc at {}", at);
+    prop_assert_eq!(machine_state.delay_timer(), model.delay, "delay timer at {}", at);
+    prop_assert_eq!(machine_state.sound_timer(), model.sound, "sound timer at {}", at);
+    prop_assert_eq!(machine_state.stack().collect::<Vec<_>>(), model.stack_contents(), "stack at {}", at);
+    prop_assert_eq!(machine_state.is_waiting_for_key(), model.waiting_for.is_some(), "key wait at {}", at);
+    let halt = match machine_state.fault() {
+        None => Halt::None,
+        Some(chip8_rs::chip8::Fault::StackOverflow { pc }) => Halt::Overflow(pc),
+        Some(chip8_rs::chip8::Fault::StackUnderflow { pc }) => Halt::Underflow(pc),
+    };
+    prop_assert_eq!(halt, model.halt, "fault at {}", at);
+    prop_assert!(machine_state.memory()[..] == model.memory[..], "memory differs at {}", at);
+    prop_assert!(machine_state.get_gfx() == &model.screen, "screen differs at {}", at);
+    Ok(())
+}
+
+// Where a comparison failed, formatted only when it's reported
+struct Step(usize, u16);
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "step {} after {:04X}", self.0, self.1)
+    }
+}
+
+fn presets() -> [Quirks; 4] {
+    [Quirks::default(), Platform::Vip.quirks(), Platform::Schip.quirks(), Platform::XoChip.quirks()]
+}
+
+// Mostly defined instructions, with jumps and calls kept inside the program so streams
+// don't spend all their time sliding through empty memory, and I often pointing into it
+fn instruction(len: u16) -> impl Strategy<Value = u16> {
+    let target = ROM_START..ROM_START + 2 * len;
+    let reg = 0u16..16;
+    prop_oneof![
+        1 => any::<u16>(),
+        1 => Just(0x00E0u16),
+        1 => Just(0x00EEu16),
+        2 => target.clone().prop_map(|a| 0x1000 | a),
+        4 => target.clone().prop_map(|a| 0x2000 | a),
+        4 => 0x3000u16..0x5000,
+        2 => (reg.clone(), reg.clone(), prop::sample::select(vec![0x5000u16, 0x9000])).prop_map(|(x, y, op)| op | x << 8 | y << 4),
+        6 => 0x6000u16..0x8000,
+        8 => (reg.clone(), reg.clone(), prop::sample::select(vec![0, 1, 2, 3, 4, 5, 6, 7, 0xE])).prop_map(|(x, y, n)| 0x8000 | x << 8 | y << 4 | n),
+        2 => 0xA000u16..0xB000,
+        2 => target.clone().prop_map(|a| 0xA000 | a), // so FX33/FX55 rewrite the program
+        1 => (0u16..16).prop_map(|a| 0xB200 | a),
+        2 => 0xC000u16..0xD000,
+        3 => 0xD000u16..0xE000,
+        1 => (reg.clone(), prop::sample::select(vec![0x9Eu16, 0xA1])).prop_map(|(x, op)| 0xE000 | x << 8 | op),
+        6 => (reg, prop::sample::select(vec![0x07u16, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65])).prop_map(|(x, op)| 0xF000 | x << 8 | op),
+    ]
+}
+
+fn program() -> impl Strategy<Value = Vec<u16>> {
+    (1u16..256).prop_flat_map(|len| prop::collection::vec(instruction(len), len as usize))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn matches_reference_model(
+        program in program(),
+        events in prop::collection::vec(prop::option::weighted(0.2, (0usize..16, any::<bool>())), STEPS),
+        seed: u64,
+    ) {
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        // Once as written, once with the decode cache, which self-modifying streams have to invalidate
+        for (quirks, cached) in presets().iter().flat_map(|&quirks| [(quirks, false), (quirks, true)]) {
+            let mut machine_state = Chip8::with_rng(XorShift::new(seed));
+            machine_state.set_quirks(quirks);
+            machine_state.set_decode_cache(cached);
+            machine_state.set_cycles_per_frame(1);
+            machine_state.load_fonts();
+            machine_state.load_rom(&rom);
+            let mut model = Reference::new(quirks, &rom, seed);
+            for (step, event) in events.iter().enumerate() {
+                if let Some((key, pressed)) = *event {
+                    machine_state.set_key(key, pressed);
+                    model.set_key(key, pressed);
+                }
+                let pc = model.pc & 0xFFF;
+                let opcode = (model.read(pc) as u16) << 8 | model.read(pc + 1) as u16;
+                machine_state.emulate_frame();
+                model.step();
+                compare(step, opcode, &mut machine_state, &model)?;
+            }
+        }
+    }
+}
+
+// Everything a ROM can observe, to show where the two differ
+#[cfg(feature = "jit")]
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    v: Vec<u8>,
+    i: u16,
+    pc: u16,
+    timers: (u8, u8),
+    stack: Vec<u16>,
+    waiting_for_key: bool,
+    fault: Option<chip8_rs::chip8::Fault>,
+    memory: Vec<u8>,
+    screen: [[u8; 32]; 64],
+}
+
+#[cfg(feature = "jit")]
+fn snapshot(machine_state: &mut Chip8) -> Snapshot {
+    Snapshot {
+        v: (0..16).map(|x| machine_state.v(x)).collect(),
+        i: machine_state.i(),
+        pc: machine_state.pc() & 0xFFF,
+        timers: (machine_state.delay_timer(), machine_state.sound_timer()),
+        stack: machine_state.stack().collect(),
+        waiting_for_key: machine_state.is_waiting_for_key(),
+        fault: machine_state.fault(),
+        memory: machine_state.memory().to_vec(),
+        screen: *machine_state.get_gfx(),
+    }
+}
+
+// The rest, such as the opcode register, only shows in save states
+#[cfg(feature = "jit")]
+fn save_state(machine_state: &Chip8) -> Vec<u8> {
+    let mut state = vec![0; chip8_rs::chip8::STATE_SIZE];
+    machine_state.save_state(&mut state).unwrap();
+    state
+}
+
+#[cfg(feature = "jit")]
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(256))]
+
+    #[test]
+    fn jit_matches_interpreter(
+        program in program(),
+        events in prop::collection::vec(prop::option::weighted(0.2, (0usize..16, any::<bool>())), STEPS / 10),
+        seed: u64,
+        cycles_per_frame in 1u32..40,
+    ) {
+        let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+        for quirks in presets() {
+            let [mut interpreted, mut compiled] = [false, true].map(|jit| {
+                let mut machine_state = Chip8::with_rng(XorShift::new(seed));
+                assert!(machine_state.set_jit(jit));
+                machine_state.set_quirks(quirks);
+                machine_state.set_cycles_per_frame(cycles_per_frame);
+                machine_state.load_fonts();
+                machine_state.load_rom(&rom);
+                machine_state
+            });
+            for (frame, event) in events.iter().enumerate() {
+                for machine_state in [&mut interpreted, &mut compiled] {
+                    if let Some((key, pressed)) = *event {
+                        machine_state.set_key(key, pressed);
+                    }
+                    machine_state.emulate_frame();
+                }
+                prop_assert_eq!(snapshot(&mut compiled), snapshot(&mut interpreted), "frame {}", frame);
+                prop_assert!(save_state(&compiled) == save_state(&interpreted), "save states differ at frame {}", frame);
+            }
+        }
+    }
+}
+
+// Runs a loop whose first instruction, 7201 (V2 += 1), writes bytes over itself with FX55
+// on the first pass. The second pass only sees the change if it's decoded again
+fn self_modifying_loop(write_at: u16, bytes: &[u8], setup: fn(&mut Chip8)) -> Chip8 {
+    let mut program = vec![0x7201];
+    program.extend(bytes.iter().enumerate().map(|(x, &byte)| 0x6000 | (x as u16) << 8 | byte as u16));
+    program.push(0xA000 | write_at);
+    program.push(0xF055 | (bytes.len() as u16 - 1) << 8);
+    let end = 0x200 + 2 * program.len() as u16 + 6;
+    program.extend([0x7301, 0x3302, 0x1200, 0x1000 | end]); // loop twice, then stop
+    let rom: Vec<u8> = program.iter().flat_map(|op| op.to_be_bytes()).collect();
+
+    let mut machine_state = Chip8::new();
+    setup(&mut machine_state);
+    machine_state.load_rom(&rom);
+    machine_state.emulate_frame();
+    machine_state.emulate_frame();
+    machine_state
+}
+
+fn sees_self_modifying_code(setup: fn(&mut Chip8)) {
+    // The whole instruction, 7201 to 7205
+    assert_eq!(self_modifying_loop(0x200, &[0x72, 0x05], setup).v(2), 6);
+    // Its second byte only, so the write lands inside the cached instruction
+    assert_eq!(self_modifying_loop(0x201, &[0x05], setup).v(2), 6);
+    // Its first byte only, 7201 to 7401
+    let machine_state = self_modifying_loop(0x200, &[0x74], setup);
+    assert_eq!((machine_state.v(2), machine_state.v(4)), (1, 1));
+}
+
+#[test]
+fn decode_cache_sees_self_modifying_code() {
+    sees_self_modifying_code(|machine_state| machine_state.set_decode_cache(true));
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn jit_sees_self_modifying_code() {
+    // In frames long enough for the loop to run as whole blocks
+    sees_self_modifying_code(|machine_state| {
+        assert!(machine_state.set_jit(true));
+        machine_state.set_cycles_per_frame(100);
+    });
+}
+
+// A loop that rewrites its own first instruction every pass: after a few recompiles the
+// JIT leaves it to the interpreter, which has to keep seeing each new value
+#[cfg(feature = "jit")]
+#[test]
+fn jit_gives_up_on_hot_self_modifying_code() {
+    let program = [
+        0x7301, // V3 += NN, NN set to the pass number below
+        0x7001, // V0 += 1
+        0xA201,
+        0xF055, // store V0 over NN
+        0x3020, // 32 passes
+        0x1200,
+        0x120C,
+    ];
+    let rom: Vec<u8> = program.iter().flat_map(|op: &u16| op.to_be_bytes()).collect();
+    let mut results = [false, true].map(|jit| {
+        let mut machine_state = Chip8::new();
+        assert!(machine_state.set_jit(jit));
+        machine_state.set_cycles_per_frame(1000);
+        machine_state.load_rom(&rom);
+        machine_state.emulate_frame();
+        machine_state
+    });
+    let [interpreted, compiled] = &mut results;
+    assert_eq!(compiled.pc(), 0x20C);
+    assert_eq!(snapshot(compiled), snapshot(interpreted));
+    assert!(save_state(compiled) == save_state(interpreted));
+}
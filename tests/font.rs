@@ -0,0 +1,29 @@
+// The font arguments the frontends share: --font names or files, and --font-address
+mod common;
+
+use chip8_rs::font::{parse_address, resolve_font, Font, MAX_FONT_ADDRESS};
+use common::TempDir;
+
+#[test]
+fn font_addresses_leave_room_for_the_font() {
+    assert_eq!(parse_address("0x050"), Ok(0x50));
+    assert_eq!(parse_address("0XFB0"), Ok(MAX_FONT_ADDRESS));
+    assert_eq!(parse_address("4016"), Ok(0xFB0));
+    assert!(parse_address("0xFB1").is_err());
+    assert!(parse_address("0x10000").is_err());
+    assert!(parse_address("-1").is_err());
+    assert_eq!(parse_address("0xFB1").unwrap_err().to_string(), "expected an address at most 0xFB0");
+}
+
+#[test]
+fn fonts_are_names_or_files() {
+    assert_eq!(&resolve_font("VIP").unwrap(), Font::Vip.data());
+    let dir = TempDir::new("font-files");
+    let (font, short) = (dir.join("font.bin"), dir.join("short.bin"));
+    std::fs::write(&font, [0xA5; 80]).unwrap();
+    std::fs::write(&short, [0xA5; 79]).unwrap();
+    assert_eq!(resolve_font(font.to_str().unwrap()).unwrap(), [0xA5; 80]);
+    let error = resolve_font(short.to_str().unwrap()).unwrap_err();
+    assert!(error.ends_with("short.bin: font files must be 80 bytes, got 79"), "{}", error);
+    assert_eq!(resolve_font("comic").unwrap_err(), "comic: unknown font (expected standard, vip, eti660, dream6800 or fishnchips), or a font file");
+}
@@ -0,0 +1,243 @@
+// The GDB stub, driven over localhost by a minimal client speaking the remote protocol
+use chip8_rs::asm;
+use chip8_rs::chip8::Chip8;
+use chip8_rs::gdb::serve;
+use chip8_rs::symbols::Symbols;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread::JoinHandle;
+
+struct Client {
+    stream: TcpStream,
+    acks: bool
+}
+
+impl Client {
+    fn send_raw(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    fn byte(&mut self) -> u8 {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn reply(&mut self) -> String {
+        assert_eq!(self.byte(), b'$');
+        let mut data = Vec::new();
+        loop {
+            match self.byte() {
+                b'#' => break,
+                byte => data.push(byte)
+            }
+        }
+        let checksum = [self.byte(), self.byte()];
+        let sum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        assert_eq!(u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16).unwrap(), sum);
+        if self.acks {
+            self.send_raw(b"+");
+        }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn request(&mut self, packet: &str) -> String {
+        let sum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        self.send_raw(format!("${}#{:02x}", packet, sum).as_bytes());
+        if self.acks {
+            assert_eq!(self.byte(), b'+');
+        }
+        self.reply()
+    }
+}
+
+fn debug(rom: &[u8]) -> (Client, JoinHandle<Chip8>) {
+    debug_with_symbols(rom, Symbols::default())
+}
+
+// Starts the stub on a free port with the ROM loaded, returning the machine when the
+// debugger is done with it
+fn debug_with_symbols(rom: &[u8], symbols: Symbols) -> (Client, JoinHandle<Chip8>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let mut machine_state = Chip8::new();
+    machine_state.set_cycles_per_frame(10);
+    machine_state.load_fonts();
+    machine_state.load_rom(rom);
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, &mut machine_state, &symbols).unwrap();
+        machine_state
+    });
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_nodelay(true).unwrap();
+    (Client { stream, acks: true }, server)
+}
+
+// v0 := 5, i := 0x300, call sub, loop: jump loop, sub: v1 := 7, return
+const PROGRAM: [u8; 12] = [0x60, 0x05, 0xA3, 0x00, 0x22, 0x08, 0x12, 0x06, 0x61, 0x07, 0x00, 0xEE];
+
+#[test]
+fn registers_and_memory_can_be_read_and_written() {
+    let (mut client, server) = debug(&PROGRAM);
+    assert!(client.request("qSupported:multiprocess+;xmlRegisters=i386").contains("qXfer:features:read+"));
+    assert_eq!(client.request("?"), "S05");
+    // v0-vf, i, pc, dt, st, the stack depth and the 16 stack slots
+    let registers = client.request("g");
+    assert_eq!(registers.len(), 2 * (16 + 2 + 2 + 1 + 1 + 1 + 32));
+    assert_eq!(&registers[32..40], "00000002");
+    assert_eq!(client.request("p11"), "0002");
+    assert_eq!(client.request("m200,4"), "6005a300");
+    assert_eq!(client.request("mffe,10"), "0000");
+    assert_eq!(client.request("m1000,1"), "E01");
+
+    assert_eq!(client.request("P3=2a"), "OK");
+    assert_eq!(client.request("P10=3412"), "OK");
+    assert_eq!(client.request("P15=0000"), "E01");
+    assert_eq!(client.request("M300,3:010203"), "OK");
+    assert_eq!(client.request("M300,3:01"), "E01");
+    let mut registers = client.request("g");
+    registers.replace_range(2..4, "11");
+    registers.replace_range(40..42, "09");
+    assert_eq!(client.request(&format!("G{}", registers)), "OK");
+    assert_eq!(client.request("D"), "OK");
+
+    let machine_state = server.join().unwrap();
+    assert_eq!((machine_state.v(1), machine_state.v(3), machine_state.i()), (0x11, 0x2A, 0x1234));
+    assert_eq!(machine_state.delay_timer(), 9);
+    assert_eq!(machine_state.memory()[0x300..0x303], [1, 2, 3]);
+}
+
+#[test]
+fn the_target_description_names_the_registers() {
+    let (mut client, server) = debug(&PROGRAM);
+    let mut xml = String::new();
+    loop {
+        let chunk = client.request(&format!("qXfer:features:read:target.xml:{:x},100", xml.len()));
+        xml.push_str(&chunk[1..]);
+        if chunk.starts_with('l') {
+            break;
+        }
+        assert!(chunk.starts_with('m'));
+    }
+    assert!(xml.contains("<reg name=\"v0\" bitsize=\"8\""));
+    assert!(xml.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+    assert!(xml.contains("<reg name=\"s15\""));
+    assert_eq!(client.request("qXfer:features:read:other.xml:0,100"), "E00");
+    assert_eq!(client.request("vMustReplyEmpty"), "");
+    client.send_raw(b"$k#6b");
+    server.join().unwrap();
+}
+
+#[test]
+fn breakpoints_stop_continue_and_step_goes_one_instruction() {
+    let (mut client, server) = debug(&PROGRAM);
+    assert_eq!(client.request("Z0,208,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p11"), "0802");
+    // Inside the subroutine, with the call's address on the stack
+    assert_eq!(client.request("p14"), "01");
+    assert_eq!(client.request("p15"), "0402");
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!((client.request("p1"), client.request("p11")), ("07".to_string(), "0a02".to_string()));
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!((client.request("p11"), client.request("p14")), ("0602".to_string(), "00".to_string()));
+
+    // Running from a breakpoint doesn't stop on it straight away
+    assert_eq!(client.request("Z1,206,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p11"), "0602");
+    assert_eq!(client.request("z1,206,2"), "OK");
+    assert_eq!(client.request("c200"), "S05");
+    assert_eq!(client.request("p11"), "0802");
+    assert_eq!(client.request("Z2,300,1"), "");
+    assert_eq!(client.request("D"), "OK");
+    assert_eq!(server.join().unwrap().v(0), 5);
+}
+
+#[test]
+fn time_passes_by_cycles() {
+    // delay := v0 (5), then loop
+    let (mut client, server) = debug(&[0x60, 0x05, 0xF0, 0x15, 0x12, 0x04]);
+    assert_eq!(client.request("Z0,204,2"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p12"), "05");
+    // Ten cycles a frame: the first tick comes with the tenth instruction
+    for _ in 0..7 {
+        client.request("s");
+    }
+    assert_eq!(client.request("p12"), "05");
+    client.request("s");
+    assert_eq!(client.request("p12"), "04");
+    assert_eq!(client.request("D"), "OK");
+    assert_eq!(server.join().unwrap().frame(), 1);
+}
+
+#[test]
+fn an_interrupt_stops_a_running_program() {
+    let (mut client, server) = debug(&[0x70, 0x01, 0x12, 0x00]);
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.acks = false;
+    client.send_raw(b"$c#63");
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    client.send_raw(&[0x03]);
+    assert_eq!(client.reply(), "S02");
+    assert_eq!(client.request("?"), "S02");
+    let pc = client.request("p11");
+    assert!(pc == "0002" || pc == "0202", "{}", pc);
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn stack_faults_stop_the_program() {
+    // return with nothing on the stack
+    let (mut client, server) = debug(&[0x60, 0x01, 0x00, 0xEE]);
+    assert_eq!(client.request("c"), "S0b");
+    assert_eq!(client.request("p11"), "0202");
+    // A halted machine can't go any further
+    assert_eq!(client.request("s"), "S0b");
+    // Damaged packets are asked for again
+    client.send_raw(b"$g#00");
+    assert_eq!(client.byte(), b'-');
+    assert_eq!(client.request("D"), "OK");
+    assert!(server.join().unwrap().fault().is_some());
+}
+
+#[test]
+fn lengths_past_the_end_of_the_address_space_are_cut_short() {
+    let (mut client, server) = debug(&PROGRAM);
+    assert_eq!(client.request("m1,ffffffffffffffff").len(), 2 * 4095);
+    assert_eq!(client.request("mfff,ffffffffffffffff"), "00");
+    assert_eq!(client.request("M1,ffffffffffffffff:00"), "E01");
+    assert!(client.request("qXfer:features:read:target.xml:10,ffffffffffffffff").starts_with('l'));
+    assert_eq!(client.request("m1,1ffffffffffffffff"), "E01");
+    assert_eq!(client.request("D"), "OK");
+    server.join().unwrap();
+}
+
+// Monitor commands go as hex, and so does their output
+fn monitor(client: &mut Client, command: &str) -> String {
+    let hex: String = command.bytes().map(|byte| format!("{:02x}", byte)).collect();
+    let reply = client.request(&format!("qRcmd,{}", hex));
+    let bytes: Vec<u8> = (0..reply.len()).step_by(2).map(|k| u8::from_str_radix(&reply[k..k + 2], 16).unwrap()).collect();
+    String::from_utf8(bytes).unwrap()
+}
+
+#[test]
+fn monitor_commands_use_the_labels() {
+    let program = asm::assemble(": main\n  v0 := 5\n  sub\n: spin\n  jump spin\n: sub\n  v1 := 7\n  return\n").unwrap();
+    let (mut client, server) = debug_with_symbols(&program.rom, program.symbols());
+    let labels = monitor(&mut client, "labels");
+    assert!(labels.contains("0x202 main\n") && labels.contains("0x208 sub\n"), "{}", labels);
+    // The assembler's jump to main comes before any label
+    assert_eq!(monitor(&mut client, "where"), "0x200\n");
+    assert_eq!(monitor(&mut client, "break sub"), "breakpoint at 0x208 (sub)\n");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(monitor(&mut client, "where"), "0x208 (sub)\n");
+    assert_eq!(monitor(&mut client, "break nowhere"), "no label nowhere\n");
+    assert!(monitor(&mut client, "help").starts_with("monitor commands"));
+    assert_eq!(client.request("qRcmd,zz"), "E01");
+    client.request("D");
+    assert_eq!(server.join().unwrap().v(0), 5);
+}
@@ -0,0 +1,55 @@
+use chip8_rs::instruction::{DecodeError, Instruction};
+
+#[test]
+fn every_opcode_decodes_and_encodes_back() {
+    let mut defined = 0;
+    for opcode in 0..=0xFFFF {
+        match Instruction::decode(opcode) {
+            Ok(instruction) => {
+                assert_eq!(instruction.encode(), opcode, "{:?}", instruction);
+                defined += 1;
+            },
+            Err(e) => assert_eq!(e, DecodeError(opcode))
+        }
+    }
+    // 16 * 4096 opcodes, less the undefined 5XYN, 8XYN, 9XYN, EXNN and FXNN forms
+    let undefined = 256 * 15 + 256 * 7 + 256 * 15 + 16 * 254 + 16 * 247;
+    assert_eq!(defined, 0x10000 - undefined);
+}
+
+#[test]
+fn undefined_opcodes() {
+    for opcode in [0x5121, 0x8008, 0x800F, 0x912A, 0xE09F, 0xF000, 0xF0FF] {
+        assert_eq!(Instruction::decode(opcode), Err(DecodeError(opcode)));
+    }
+}
+
+#[test]
+fn decode_fields() {
+    assert_eq!(Instruction::decode(0x00E0), Ok(Instruction::Cls));
+    assert_eq!(Instruction::decode(0x0123), Ok(Instruction::Sys { addr: 0x123 }));
+    assert_eq!(Instruction::decode(0x3A42), Ok(Instruction::Ske { x: 0xA, nn: 0x42 }));
+    assert_eq!(Instruction::decode(0x8F1E), Ok(Instruction::Shl { x: 0xF, y: 0x1 }));
+    assert_eq!(Instruction::decode(0xD125), Ok(Instruction::Draw { x: 1, y: 2, n: 5 }));
+    assert_eq!(Instruction::decode(0xF765), Ok(Instruction::Read { x: 7 }));
+}
+
+#[test]
+fn encode_masks_operands() {
+    assert_eq!(Instruction::Jump { addr: 0x1234 }.encode(), 0x1234);
+    assert_eq!(Instruction::Draw { x: 0x11, y: 2, n: 0x13 }.encode(), 0xD123);
+    // Sys can't reach the addresses of the opcodes 00E0 and 00EE decode to
+    assert_eq!(Instruction::decode(Instruction::Sys { addr: 0x0E0 }.encode()), Ok(Instruction::Cls));
+    assert_eq!(Instruction::decode(Instruction::Sys { addr: 0x0EE }.encode()), Ok(Instruction::Rts));
+}
+
+#[test]
+fn disassembly() {
+    let text = |opcode| Instruction::decode(opcode).unwrap().to_string();
+    assert_eq!(text(0x00EE), "RET");
+    assert_eq!(text(0x2345), "CALL 0x345");
+    assert_eq!(text(0x6A05), "LD VA, 0x05");
+    assert_eq!(text(0x8124), "ADD V1, V2");
+    assert_eq!(text(0xD125), "DRW V1, V2, 5");
+    assert_eq!(text(0xF355), "LD [I], V3");
+}
@@ -0,0 +1,94 @@
+// FX0A: the machine stops until a key goes down and, with the COSMAC VIP's behaviour, comes
+// back up, then carries on with the key in VX
+use chip8_rs::chip8::{Chip8, Quirks};
+
+// F10A waits for a key into V1, then F129 and D005 draw its digit in the top left corner
+const ROM: [u8; 8] = [0xF1, 0x0A, 0xF1, 0x29, 0xD0, 0x05, 0x12, 0x06];
+
+fn machine(quirks: Quirks) -> Chip8 {
+    let mut machine_state = Chip8::new();
+    machine_state.set_quirks(quirks);
+    machine_state.load_fonts();
+    machine_state.load_rom(&ROM);
+    machine_state
+}
+
+fn cycles(machine_state: &mut Chip8, count: usize) {
+    for _ in 0..count {
+        machine_state.emulate_cycle();
+    }
+}
+
+// The rows of the 4x5 digit drawn in the corner, as font bytes
+fn shown(machine_state: &mut Chip8) -> [u8; 5] {
+    let gfx = machine_state.get_gfx();
+    let mut rows = [0; 5];
+    for (y, row) in rows.iter_mut().enumerate() {
+        *row = (0..8).fold(0, |byte, x| byte << 1 | gfx[x][y]);
+    }
+    rows
+}
+
+const TWO: [u8; 5] = [0xF0, 0x10, 0xF0, 0x80, 0xF0];
+const THREE: [u8; 5] = [0xF0, 0x10, 0xF0, 0x10, 0xF0];
+const FOUR: [u8; 5] = [0x90, 0x90, 0xF0, 0x10, 0x10];
+const SEVEN: [u8; 5] = [0xF0, 0x10, 0x20, 0x40, 0x40];
+
+#[test]
+fn waits_for_the_key_to_be_released() {
+    let mut machine_state = machine(Quirks::default());
+    cycles(&mut machine_state, 5);
+    assert!(machine_state.is_waiting_for_key());
+    assert_eq!(shown(&mut machine_state), [0; 5]);
+
+    machine_state.set_key(7, true);
+    cycles(&mut machine_state, 5);
+    assert!(machine_state.is_waiting_for_key());
+    assert_eq!(shown(&mut machine_state), [0; 5]);
+
+    machine_state.set_key(7, false);
+    assert!(!machine_state.is_waiting_for_key());
+    cycles(&mut machine_state, 2);
+    assert_eq!(shown(&mut machine_state), SEVEN);
+}
+
+#[test]
+fn without_the_release_quirk_a_press_is_enough() {
+    let mut machine_state = machine(Quirks { key_wait_release: false, ..Quirks::default() });
+    cycles(&mut machine_state, 1);
+    machine_state.set_key(3, true);
+    assert!(!machine_state.is_waiting_for_key());
+    cycles(&mut machine_state, 2);
+    assert_eq!(shown(&mut machine_state), THREE);
+}
+
+#[test]
+fn keys_already_down_do_not_count() {
+    let mut machine_state = machine(Quirks::default());
+    machine_state.set_key(5, true);
+    cycles(&mut machine_state, 1);
+    assert!(machine_state.is_waiting_for_key());
+    // Still held, then let go, without ever going down during the wait
+    machine_state.set_key(5, true);
+    machine_state.set_key(5, false);
+    assert!(machine_state.is_waiting_for_key());
+
+    machine_state.set_key(2, true);
+    machine_state.set_key(2, false);
+    cycles(&mut machine_state, 2);
+    assert_eq!(shown(&mut machine_state), TWO);
+}
+
+#[test]
+fn only_the_first_key_pressed_is_taken() {
+    let mut machine_state = machine(Quirks::default());
+    cycles(&mut machine_state, 1);
+    machine_state.set_key(4, true);
+    machine_state.set_key(6, true);
+    machine_state.set_key(6, false);
+    assert!(machine_state.is_waiting_for_key());
+    machine_state.set_key(4, false);
+    assert!(!machine_state.is_waiting_for_key());
+    cycles(&mut machine_state, 2);
+    assert_eq!(shown(&mut machine_state), FOUR);
+}
@@ -0,0 +1,284 @@
+// The libretro core driven through its C API by a minimal frontend holding the core
+// options and the joypad, with the machine inspected through save states
+#![cfg(feature = "libretro")]
+use chip8_rs::chip8::{Chip8, Fault, MEMORY_STACK_ADDRESS, STATE_SIZE};
+use chip8_rs::font::{Font, DEFAULT_FONT_ADDRESS, FONT_SIZE};
+use chip8_rs::libretro::*;
+
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::{c_char, c_uint};
+use std::sync::Mutex;
+
+const GET_CAN_DUPE: c_uint = 3;
+const GET_VARIABLE: c_uint = 15;
+const SET_VARIABLES: c_uint = 16;
+const GET_VARIABLE_UPDATE: c_uint = 17;
+
+// Same layouts as retro_variable and retro_game_info
+#[repr(C)]
+struct Variable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+struct GameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+struct Frontend {
+    declared: Vec<String>, // keys passed to SET_VARIABLES
+    variables: Vec<(String, CString)>,
+    updated: bool,
+    pressed: Option<c_uint>, // joypad button held down
+    can_dupe: bool,
+    refreshed: Vec<Option<usize>>, // lit pixels of each frame sent, None for a repeat
+}
+
+static FRONTEND: Mutex<Frontend> = Mutex::new(Frontend { declared: Vec::new(), variables: Vec::new(), updated: false, pressed: None, can_dupe: true, refreshed: Vec::new() });
+// The core is a global, so only one test drives it at a time
+static SERIAL: Mutex<()> = Mutex::new(());
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    let mut frontend = FRONTEND.lock().unwrap();
+    match cmd {
+        GET_VARIABLE => {
+            let var = &mut *(data as *mut Variable);
+            let key = CStr::from_ptr(var.key).to_str().unwrap();
+            match frontend.variables.iter().find(|(k, _)| k == key) {
+                Some((_, value)) => {
+                    // The CString stays in FRONTEND until the variables are replaced
+                    var.value = value.as_ptr();
+                    true
+                },
+                None => false
+            }
+        },
+        SET_VARIABLES => {
+            let mut var = data as *const Variable;
+            while !(*var).key.is_null() {
+                frontend.declared.push(CStr::from_ptr((*var).key).to_str().unwrap().to_string());
+                var = var.add(1);
+            }
+            true
+        },
+        GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = std::mem::take(&mut frontend.updated);
+            true
+        },
+        GET_CAN_DUPE => {
+            *(data as *mut bool) = frontend.can_dupe;
+            true
+        },
+        _ => true
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, _pitch: usize) {
+    let lit = (!data.is_null()).then(|| {
+        let pixels = std::slice::from_raw_parts(data as *const u32, (width * height) as usize);
+        pixels.iter().filter(|&&pixel| pixel != 0).count()
+    });
+    FRONTEND.lock().unwrap().refreshed.push(lit);
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(_port: c_uint, _device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    (FRONTEND.lock().unwrap().pressed == Some(id)) as i16
+}
+
+fn set_variables(variables: &[(&str, &str)]) {
+    let mut frontend = FRONTEND.lock().unwrap();
+    frontend.variables = variables.iter().map(|&(k, v)| (k.to_string(), CString::new(v).unwrap())).collect();
+    frontend.updated = true;
+}
+
+fn press(button: Option<c_uint>) {
+    FRONTEND.lock().unwrap().pressed = button;
+}
+
+fn load(rom: &[u8], variables: &[(&str, &str)]) -> bool {
+    set_variables(variables);
+    press(None);
+    retro_set_environment(environment);
+    retro_set_input_poll(input_poll);
+    retro_set_input_state(input_state);
+    retro_init();
+    let game = GameInfo { path: std::ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: std::ptr::null() };
+    unsafe { retro_load_game(&game as *const GameInfo as *const RetroGameInfo) }
+}
+
+fn run(frames: u32) {
+    for _ in 0..frames {
+        retro_run();
+    }
+}
+
+fn serialize() -> Vec<u8> {
+    let mut state = vec![0; retro_serialize_size()];
+    assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+    state
+}
+
+// A machine in the core's state, with the stack layout to read it back with
+fn machine(stack_in_memory: bool) -> Chip8 {
+    let mut machine_state = Chip8::new();
+    let mut quirks = machine_state.quirks();
+    quirks.stack_in_memory = stack_in_memory;
+    machine_state.set_quirks(quirks);
+    machine_state.load_state(&serialize()).unwrap();
+    machine_state
+}
+
+#[test]
+fn declares_its_options() {
+    let _serial = SERIAL.lock().unwrap();
+    FRONTEND.lock().unwrap().declared.clear();
+    retro_set_environment(environment);
+    let declared = FRONTEND.lock().unwrap().declared.clone();
+    let quirks = ["chip8_vf_reset", "chip8_load_store_increments_i", "chip8_shift_uses_vy", "chip8_jump_uses_vx", "chip8_display_wait", "chip8_clip_sprites", "chip8_key_wait", "chip8_stack_depth", "chip8_stack_in_memory"];
+    assert_eq!(declared[..2], ["chip8_ipf", "chip8_platform"]);
+    assert_eq!(declared[2..11], quirks);
+    assert_eq!(declared[11..], ["chip8_font"]);
+}
+
+#[test]
+fn options_apply_on_load() {
+    let _serial = SERIAL.lock().unwrap();
+    // 0x200: V0 += 1, jump back
+    assert!(load(&[0x70, 0x01, 0x12, 0x00], &[("chip8_ipf", "20"), ("chip8_font", "vip")]));
+    run(1);
+    let machine_state = machine(false);
+    assert_eq!(machine_state.v(0), 10);
+    let font = &machine_state.memory()[DEFAULT_FONT_ADDRESS as usize..][..FONT_SIZE];
+    assert_eq!(font, &Font::Vip.data()[..]);
+
+    // Options the core doesn't know fall back to the defaults
+    assert!(load(&[0x70, 0x01, 0x12, 0x00], &[("chip8_ipf", "fast"), ("chip8_font", "comic")]));
+    run(1);
+    let machine_state = machine(false);
+    assert_eq!(machine_state.v(0), 5);
+    let font = &machine_state.memory()[DEFAULT_FONT_ADDRESS as usize..][..FONT_SIZE];
+    assert_eq!(font, &Font::Standard.data()[..]);
+    retro_unload_game();
+}
+
+#[test]
+fn stack_options_apply_on_load() {
+    let _serial = SERIAL.lock().unwrap();
+    // 0x200: call 0x200, forever
+    assert!(load(&[0x22, 0x00], &[("chip8_ipf", "100"), ("chip8_stack_depth", "12"), ("chip8_stack_in_memory", "enabled")]));
+    run(1);
+    let machine_state = machine(true);
+    assert_eq!(machine_state.fault(), Some(Fault::StackOverflow { pc: 0x200 }));
+    assert_eq!(machine_state.stack().count(), 12);
+    assert_eq!(machine_state.memory()[MEMORY_STACK_ADDRESS..MEMORY_STACK_ADDRESS + 4], [0x02, 0x00, 0x02, 0x00]);
+    retro_unload_game();
+}
+
+#[test]
+fn quirks_follow_the_platform_unless_overridden() {
+    let _serial = SERIAL.lock().unwrap();
+    // VF := 5, V0 |= V1, then the VIP clears VF
+    let rom = [0x6F, 0x05, 0x80, 0x11, 0x12, 0x00];
+    assert!(load(&rom, &[]));
+    run(1);
+    assert_eq!(machine(false).v(0xF), 5);
+
+    assert!(load(&rom, &[("chip8_platform", "vip")]));
+    run(1);
+    assert_eq!(machine(true).v(0xF), 0);
+
+    // Quirks other than the stack's change straight away
+    set_variables(&[("chip8_platform", "vip"), ("chip8_vf_reset", "disabled")]);
+    run(1);
+    assert_eq!(machine(true).v(0xF), 5);
+    set_variables(&[("chip8_platform", "schip"), ("chip8_vf_reset", "enabled")]);
+    run(1);
+    assert_eq!(machine(true).v(0xF), 0);
+
+    // The VIP's stack is 12 deep at 0xEA0
+    assert!(load(&[0x22, 0x00], &[("chip8_platform", "vip"), ("chip8_ipf", "100")]));
+    run(1);
+    let machine_state = machine(true);
+    assert_eq!(machine_state.stack().count(), 12);
+    assert_eq!(machine_state.memory()[MEMORY_STACK_ADDRESS..MEMORY_STACK_ADDRESS + 2], [0x02, 0x00]);
+    retro_unload_game();
+}
+
+#[test]
+fn stack_layout_changes_wait_for_reset() {
+    let _serial = SERIAL.lock().unwrap();
+    // 0x200: call 0x206, then V0 += 1 and spin. 0x206: wait for key 0 and return
+    let rom = [0x22, 0x06, 0x70, 0x01, 0x12, 0x04, 0xE1, 0x9E, 0x12, 0x06, 0x00, 0xEE];
+    assert!(load(&rom, &[]));
+    run(1);
+    assert_eq!(machine(false).stack().collect::<Vec<_>>(), [0x200]);
+
+    // Switching layouts inside the subroutine still returns to where it was called from
+    set_variables(&[("chip8_stack_in_memory", "enabled")]);
+    press(Some(0)); // B is key 0
+    run(1);
+    let machine_state = machine(false);
+    assert_eq!(machine_state.v(0), 1);
+    assert_eq!(machine_state.pc(), 0x204);
+    assert_eq!(machine_state.fault(), None);
+
+    // and the new layout is used from the next reset
+    press(None);
+    retro_reset();
+    run(1);
+    let machine_state = machine(true);
+    assert_eq!(machine_state.stack().collect::<Vec<_>>(), [0x200]);
+    assert_eq!(machine_state.memory()[MEMORY_STACK_ADDRESS..MEMORY_STACK_ADDRESS + 2], [0x02, 0x00]);
+    retro_unload_game();
+}
+
+#[test]
+fn save_states_round_trip() {
+    let _serial = SERIAL.lock().unwrap();
+    // V0 += 1, V1 := random, draw the V0 digit at V1, V1
+    let rom = [0x70, 0x01, 0xC1, 0x1F, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x00];
+    assert!(load(&rom, &[]));
+    assert_eq!(retro_serialize_size(), STATE_SIZE);
+    run(3);
+    let saved = serialize();
+    run(5);
+    let later = serialize();
+    assert_ne!(saved, later);
+
+    assert!(unsafe { retro_unserialize(saved.as_ptr() as *const c_void, saved.len()) });
+    assert_eq!(serialize(), saved);
+    run(5);
+    assert_eq!(serialize(), later);
+
+    // Buffers too small for a state are refused either way
+    let mut short = vec![0; STATE_SIZE - 1];
+    assert!(!unsafe { retro_serialize(short.as_mut_ptr() as *mut c_void, short.len()) });
+    assert!(!unsafe { retro_unserialize(short.as_ptr() as *const c_void, short.len()) });
+    let mut corrupt = saved.clone();
+    corrupt[0] ^= 0xFF;
+    assert!(!unsafe { retro_unserialize(corrupt.as_ptr() as *const c_void, corrupt.len()) });
+    assert_eq!(serialize(), later);
+    retro_unload_game();
+}
+
+#[test]
+fn unchanged_frames_are_repeated_only_by_frontends_that_can() {
+    let _serial = SERIAL.lock().unwrap();
+    retro_set_video_refresh(video_refresh);
+    // 0x200: I := the 0 glyph (14 pixels), draw it, then spin
+    let rom = [0xF0, 0x29, 0xD0, 0x05, 0x12, 0x04];
+    for (can_dupe, expected) in [(true, [Some(14), None, None]), (false, [Some(14); 3])] {
+        FRONTEND.lock().unwrap().can_dupe = can_dupe;
+        assert!(load(&rom, &[]));
+        FRONTEND.lock().unwrap().refreshed.clear();
+        run(3);
+        assert_eq!(FRONTEND.lock().unwrap().refreshed, expected);
+    }
+    FRONTEND.lock().unwrap().can_dupe = true;
+}
@@ -0,0 +1,9 @@
+// The core has to build without std for microcontroller targets
+mod common;
+
+#[test]
+#[ignore = "needs the thumbv7em-none-eabihf target"]
+fn core_builds_for_thumbv7em() {
+    let dir = common::build_for_target("thumbv7em-none-eabihf", "rlib", "");
+    assert!(dir.join("libchip8_rs.rlib").exists());
+}
@@ -0,0 +1,363 @@
+// One instruction at a time: set up the machine with Chip8Builder, run a single opcode
+// and check the registers, memory and screen it leaves behind
+use chip8_rs::builder::Chip8Builder;
+use chip8_rs::chip8::{Chip8, Fault, Platform, Quirks, MEMORY_STACK_ADDRESS};
+
+const START: u16 = 0x200;
+
+fn run(builder: Chip8Builder, opcode: u16) -> Chip8 {
+    let mut machine_state = builder.build();
+    machine_state.run_opcode(opcode);
+    machine_state
+}
+
+fn lit(machine_state: &mut Chip8) -> usize {
+    machine_state.get_gfx().iter().flatten().filter(|&&pixel| pixel == 1).count()
+}
+
+#[test]
+fn cls_clears_the_screen() {
+    let mut machine_state = run(Chip8Builder::new().pixel(0, 0).pixel(63, 31), 0x00E0);
+    assert_eq!(lit(&mut machine_state), 0);
+    assert_eq!(machine_state.pc(), START + 2);
+}
+
+#[test]
+fn machine_code_call_is_skipped() {
+    let machine_state = run(Chip8Builder::new(), 0x0123);
+    assert_eq!(machine_state.pc(), START + 2);
+}
+
+#[test]
+fn jump() {
+    assert_eq!(run(Chip8Builder::new(), 0x1ABC).pc(), 0xABC);
+}
+
+#[test]
+fn call_and_return() {
+    let mut machine_state = run(Chip8Builder::new(), 0x2400);
+    assert_eq!(machine_state.pc(), 0x400);
+    assert_eq!(machine_state.stack().collect::<Vec<_>>(), [START]);
+    machine_state.run_opcode(0x00EE);
+    assert_eq!(machine_state.pc(), START + 2);
+    assert_eq!(machine_state.stack().count(), 0);
+}
+
+#[test]
+fn call_with_stack_in_memory() {
+    let quirks = Platform::Vip.quirks();
+    let machine_state = run(Chip8Builder::new().quirks(quirks).pc(0x234), 0x2400);
+    assert_eq!(machine_state.stack().collect::<Vec<_>>(), [0x234]);
+    assert_eq!(machine_state.memory()[0xEA0..0xEA2], [0x02, 0x34]);
+}
+
+// Calls from 0x200, 0x300, 0x302, ... each one a level deeper
+fn call_down(machine_state: &mut Chip8, levels: u16) {
+    for level in 0..levels {
+        machine_state.run_opcode(0x2300 + 2 * level);
+    }
+}
+
+#[test]
+fn stack_overflow_at_the_configured_depth() {
+    let quirks = Quirks { stack_depth: 4, ..Quirks::default() };
+    let mut machine_state = Chip8Builder::new().quirks(quirks).build();
+    call_down(&mut machine_state, 4);
+    assert_eq!(machine_state.fault(), None);
+    assert_eq!(machine_state.stack().collect::<Vec<_>>(), [START, 0x300, 0x302, 0x304]);
+    machine_state.run_opcode(0x2400);
+    assert_eq!(machine_state.fault(), Some(Fault::StackOverflow { pc: 0x306 }));
+    assert_eq!(machine_state.pc(), 0x306);
+    assert_eq!(machine_state.stack().count(), 4);
+    // and nothing runs after that
+    machine_state.emulate_cycle();
+    assert_eq!(machine_state.pc(), 0x306);
+}
+
+#[test]
+fn stack_underflow_on_return_with_an_empty_stack() {
+    let machine_state = run(Chip8Builder::new(), 0x00EE);
+    assert_eq!(machine_state.fault(), Some(Fault::StackUnderflow { pc: START }));
+    assert_eq!(machine_state.pc(), START);
+}
+
+#[test]
+fn stack_faults_can_be_ignored() {
+    let quirks = Quirks { stack_depth: 2, halt_on_stack_fault: false, ..Quirks::default() };
+    let mut machine_state = Chip8Builder::new().quirks(quirks).build();
+    call_down(&mut machine_state, 3);
+    // The third call jumps but loses its return address
+    assert_eq!(machine_state.fault(), None);
+    assert_eq!(machine_state.pc(), 0x304);
+    assert_eq!(machine_state.stack().collect::<Vec<_>>(), [START, 0x300]);
+    machine_state.run_opcode(0x00EE);
+    machine_state.run_opcode(0x00EE);
+    assert_eq!(machine_state.pc(), START + 2);
+    // and a return with nothing to return to moves on
+    machine_state.run_opcode(0x00EE);
+    assert_eq!(machine_state.fault(), None);
+    assert_eq!(machine_state.pc(), START + 4);
+}
+
+#[test]
+fn vip_stack_is_twelve_deep_in_memory() {
+    let quirks = Quirks { stack_depth: 12, stack_in_memory: true, ..Quirks::default() };
+    let mut machine_state = Chip8Builder::new().quirks(quirks).build();
+    call_down(&mut machine_state, 12);
+    assert_eq!(machine_state.fault(), None);
+    let slots = &machine_state.memory()[MEMORY_STACK_ADDRESS..MEMORY_STACK_ADDRESS + 26];
+    assert_eq!(slots[..4], [0x02, 0x00, 0x03, 0x00]);
+    assert_eq!(slots[22..24], [0x03, 0x14]);
+    assert_eq!(slots[24..], [0, 0]);
+    machine_state.run_opcode(0x2400);
+    assert_eq!(machine_state.fault(), Some(Fault::StackOverflow { pc: 0x316 }));
+    assert_eq!(machine_state.memory()[MEMORY_STACK_ADDRESS + 24..MEMORY_STACK_ADDRESS + 26], [0, 0]);
+    // Returns read the slots back
+    let mut machine_state = Chip8Builder::new().quirks(quirks).build();
+    call_down(&mut machine_state, 12);
+    machine_state.run_opcode(0x00EE);
+    assert_eq!(machine_state.pc(), 0x314 + 2);
+    assert_eq!(machine_state.stack().count(), 11);
+}
+
+#[test]
+fn skip_if_equal_immediate() {
+    assert_eq!(run(Chip8Builder::new().v(3, 0x42), 0x3342).pc(), START + 4);
+    assert_eq!(run(Chip8Builder::new().v(3, 0x41), 0x3342).pc(), START + 2);
+}
+
+#[test]
+fn skip_if_not_equal_immediate() {
+    assert_eq!(run(Chip8Builder::new().v(3, 0x41), 0x4342).pc(), START + 4);
+    assert_eq!(run(Chip8Builder::new().v(3, 0x42), 0x4342).pc(), START + 2);
+}
+
+#[test]
+fn skip_if_registers_equal() {
+    assert_eq!(run(Chip8Builder::new().v(1, 7).v(2, 7), 0x5120).pc(), START + 4);
+    assert_eq!(run(Chip8Builder::new().v(1, 7).v(2, 8), 0x5120).pc(), START + 2);
+}
+
+#[test]
+fn skip_if_registers_not_equal() {
+    assert_eq!(run(Chip8Builder::new().v(1, 7).v(2, 8), 0x9120).pc(), START + 4);
+    assert_eq!(run(Chip8Builder::new().v(1, 7).v(2, 7), 0x9120).pc(), START + 2);
+}
+
+#[test]
+fn load_immediate() {
+    let machine_state = run(Chip8Builder::new(), 0x6A5C);
+    assert_eq!(machine_state.v(0xA), 0x5C);
+    assert_eq!(machine_state.pc(), START + 2);
+}
+
+#[test]
+fn add_immediate_wraps_without_touching_vf() {
+    let machine_state = run(Chip8Builder::new().v(2, 0xFF).v(0xF, 7), 0x7202);
+    assert_eq!(machine_state.v(2), 1);
+    assert_eq!(machine_state.v(0xF), 7);
+}
+
+#[test]
+fn move_register() {
+    let machine_state = run(Chip8Builder::new().v(4, 9), 0x8340);
+    assert_eq!(machine_state.v(3), 9);
+    assert_eq!(machine_state.v(4), 9);
+}
+
+#[test]
+fn logic_ops() {
+    let builder = || Chip8Builder::new().v(0, 0b1100).v(1, 0b1010).v(0xF, 0x55);
+    let or = run(builder(), 0x8011);
+    let and = run(builder(), 0x8012);
+    let xor = run(builder(), 0x8013);
+    assert_eq!((or.v(0), and.v(0), xor.v(0)), (0b1110, 0b1000, 0b0110));
+    assert_eq!((or.v(0xF), and.v(0xF), xor.v(0xF)), (0x55, 0x55, 0x55));
+}
+
+#[test]
+fn logic_ops_reset_vf_on_the_vip() {
+    let builder = || Chip8Builder::new().quirks(Platform::Vip.quirks()).v(0, 0b1100).v(1, 0b1010).v(0xF, 0x55);
+    for opcode in [0x8011, 0x8012, 0x8013] {
+        assert_eq!(run(builder(), opcode).v(0xF), 0, "{:04X}", opcode);
+    }
+}
+
+#[test]
+fn add_registers_sets_carry() {
+    let machine_state = run(Chip8Builder::new().v(0, 0xFF).v(1, 2), 0x8014);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (1, 1));
+    let machine_state = run(Chip8Builder::new().v(0, 0xFE).v(1, 1).v(0xF, 1), 0x8014);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0xFF, 0));
+}
+
+#[test]
+fn add_registers_with_vf_as_operand() {
+    // The flag is written last, so it replaces the sum when VF is the destination
+    assert_eq!(run(Chip8Builder::new().v(0xF, 0xFF).v(1, 2), 0x8F14).v(0xF), 1);
+    assert_eq!(run(Chip8Builder::new().v(0xF, 1).v(1, 2), 0x8F14).v(0xF), 0);
+    let machine_state = run(Chip8Builder::new().v(0, 0x10).v(0xF, 0x20), 0x80F4);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0x30, 0));
+}
+
+#[test]
+fn subtract_sets_not_borrow() {
+    let machine_state = run(Chip8Builder::new().v(0, 5).v(1, 3), 0x8015);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (2, 1));
+    let machine_state = run(Chip8Builder::new().v(0, 3).v(1, 5), 0x8015);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0xFE, 0));
+    let machine_state = run(Chip8Builder::new().v(0, 3).v(1, 3), 0x8015);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0, 1));
+}
+
+#[test]
+fn subtract_with_vf_as_operand() {
+    assert_eq!(run(Chip8Builder::new().v(0xF, 3).v(1, 5), 0x8F15).v(0xF), 0);
+    assert_eq!(run(Chip8Builder::new().v(0xF, 5).v(1, 3), 0x8F15).v(0xF), 1);
+    let machine_state = run(Chip8Builder::new().v(0, 0x30).v(0xF, 0x10), 0x80F5);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0x20, 1));
+}
+
+#[test]
+fn reverse_subtract() {
+    let machine_state = run(Chip8Builder::new().v(0, 3).v(1, 5), 0x8017);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (2, 1));
+    let machine_state = run(Chip8Builder::new().v(0, 5).v(1, 3), 0x8017);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0xFE, 0));
+    assert_eq!(run(Chip8Builder::new().v(0xF, 5).v(1, 3), 0x8F17).v(0xF), 0);
+    let machine_state = run(Chip8Builder::new().v(0, 0x10).v(0xF, 0x30), 0x80F7);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0x20, 1));
+}
+
+#[test]
+fn shift_right() {
+    let machine_state = run(Chip8Builder::new().v(0, 0xFF).v(1, 0b101), 0x8016);
+    assert_eq!((machine_state.v(0), machine_state.v(1), machine_state.v(0xF)), (0b10, 0b101, 1));
+    let machine_state = run(Chip8Builder::new().quirks(Platform::Schip.quirks()).v(0, 0b100).v(1, 0xFF), 0x8016);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0b10, 0));
+    assert_eq!(run(Chip8Builder::new().v(0xF, 0b10), 0x8FF6).v(0xF), 0);
+}
+
+#[test]
+fn shift_left() {
+    let machine_state = run(Chip8Builder::new().v(1, 0x81), 0x801E);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0x02, 1));
+    let machine_state = run(Chip8Builder::new().quirks(Platform::Schip.quirks()).v(0, 0x40).v(1, 0xFF), 0x801E);
+    assert_eq!((machine_state.v(0), machine_state.v(0xF)), (0x80, 0));
+    assert_eq!(run(Chip8Builder::new().v(0xF, 0x40), 0x8FFE).v(0xF), 0);
+}
+
+#[test]
+fn load_index() {
+    assert_eq!(run(Chip8Builder::new(), 0xA123).i(), 0x123);
+}
+
+#[test]
+fn jump_with_offset() {
+    assert_eq!(run(Chip8Builder::new().v(0, 4).v(3, 8), 0xB300).pc(), 0x304);
+    let quirks = Platform::Schip.quirks();
+    assert_eq!(run(Chip8Builder::new().quirks(quirks).v(0, 4).v(3, 8), 0xB300).pc(), 0x308);
+}
+
+#[test]
+fn random_is_masked() {
+    for seed in 1..50 {
+        let machine_state = run(Chip8Builder::new().seed(seed), 0xC50F);
+        assert_eq!(machine_state.v(5) & 0xF0, 0);
+    }
+    assert_eq!(run(Chip8Builder::new(), 0xC500).v(5), 0);
+}
+
+#[test]
+fn draw_sprite_and_collision() {
+    let builder = Chip8Builder::new().v(0, 2).v(1, 3).i(0x300).memory(0x300, &[0xF0, 0x90]);
+    let mut machine_state = run(builder, 0xD012);
+    assert_eq!(lit(&mut machine_state), 6);
+    assert_eq!(machine_state.get_gfx()[2][3], 1);
+    assert_eq!(machine_state.get_gfx()[3][4], 0);
+    assert_eq!(machine_state.v(0xF), 0);
+    assert!(machine_state.get_draw());
+    machine_state.run_opcode(0xD012);
+    assert_eq!(lit(&mut machine_state), 0);
+    assert_eq!(machine_state.v(0xF), 1);
+}
+
+#[test]
+fn draw_wraps_start_and_clips_or_wraps_the_rest() {
+    // The start position wraps, the rest of the sprite is cut off at the edge...
+    let builder = || Chip8Builder::new().v(0, 64 + 60).v(1, 31).i(0x300).memory(0x300, &[0xFF, 0xFF]);
+    let mut machine_state = run(builder(), 0xD012);
+    assert_eq!(lit(&mut machine_state), 4);
+    // ...unless sprites wrap, as on the XO-CHIP
+    let mut machine_state = run(builder().quirks(Platform::XoChip.quirks()), 0xD012);
+    assert_eq!(lit(&mut machine_state), 16);
+    assert_eq!(machine_state.get_gfx()[0][0], 1);
+}
+
+#[test]
+fn skip_if_key() {
+    assert_eq!(run(Chip8Builder::new().v(2, 0xA).key(0xA), 0xE29E).pc(), START + 4);
+    assert_eq!(run(Chip8Builder::new().v(2, 0xA).key(0xB), 0xE29E).pc(), START + 2);
+    assert_eq!(run(Chip8Builder::new().v(2, 0xA).key(0xB), 0xE2A1).pc(), START + 4);
+    assert_eq!(run(Chip8Builder::new().v(2, 0xA).key(0xA), 0xE2A1).pc(), START + 2);
+}
+
+#[test]
+fn wait_for_key() {
+    let mut machine_state = run(Chip8Builder::new(), 0xF30A);
+    assert!(machine_state.is_waiting_for_key());
+    assert_eq!(machine_state.pc(), START);
+    machine_state.set_key(7, true);
+    assert!(machine_state.is_waiting_for_key());
+    machine_state.set_key(7, false);
+    assert!(!machine_state.is_waiting_for_key());
+    assert_eq!((machine_state.v(3), machine_state.pc()), (7, START + 2));
+
+    let quirks = Quirks { key_wait_release: false, ..Quirks::default() };
+    let mut machine_state = run(Chip8Builder::new().quirks(quirks), 0xF30A);
+    machine_state.set_key(9, true);
+    assert_eq!((machine_state.v(3), machine_state.pc()), (9, START + 2));
+}
+
+#[test]
+fn timers() {
+    assert_eq!(run(Chip8Builder::new().delay_timer(42), 0xF407).v(4), 42);
+    assert_eq!(run(Chip8Builder::new().v(4, 17), 0xF415).delay_timer(), 17);
+    assert_eq!(run(Chip8Builder::new().v(4, 17), 0xF418).sound_timer(), 17);
+}
+
+#[test]
+fn add_to_index() {
+    assert_eq!(run(Chip8Builder::new().i(0x100).v(6, 0x20), 0xF61E).i(), 0x120);
+}
+
+#[test]
+fn font_character() {
+    let machine_state = run(Chip8Builder::new().v(1, 0xA), 0xF129);
+    assert_eq!(machine_state.i(), 50);
+    assert_eq!(machine_state.memory()[50], 0xF0);
+    // Only the low nibble picks the digit
+    assert_eq!(run(Chip8Builder::new().v(1, 0x1A), 0xF129).i(), 50);
+}
+
+#[test]
+fn binary_coded_decimal() {
+    let machine_state = run(Chip8Builder::new().v(2, 254).i(0x300), 0xF233);
+    assert_eq!(machine_state.memory()[0x300..0x303], [2, 5, 4]);
+    assert_eq!(machine_state.i(), 0x300);
+}
+
+#[test]
+fn store_and_load_registers() {
+    let machine_state = run(Chip8Builder::new().registers(&[1, 2, 3, 4]).i(0x300), 0xF255);
+    assert_eq!(machine_state.memory()[0x300..0x304], [1, 2, 3, 0]);
+    assert_eq!(machine_state.i(), 0x303);
+
+    let machine_state = run(Chip8Builder::new().i(0x300).memory(0x300, &[9, 8, 7, 6]), 0xF265);
+    assert_eq!((machine_state.v(0), machine_state.v(1), machine_state.v(2), machine_state.v(3)), (9, 8, 7, 0));
+    assert_eq!(machine_state.i(), 0x303);
+
+    let quirks = Platform::Schip.quirks();
+    assert_eq!(run(Chip8Builder::new().quirks(quirks).i(0x300), 0xF255).i(), 0x300);
+    assert_eq!(run(Chip8Builder::new().quirks(quirks).i(0x300), 0xF265).i(), 0x300);
+}
@@ -0,0 +1,109 @@
+// Arithmetic invariants, and that no instruction panics whatever state it runs in
+use chip8_rs::builder::Chip8Builder;
+use chip8_rs::chip8::{Chip8, Platform, Quirks, StateError, STATE_SIZE};
+use proptest::prelude::*;
+
+fn run(builder: Chip8Builder, opcode: u16) -> Chip8 {
+    let mut machine_state = builder.build();
+    machine_state.run_opcode(opcode);
+    machine_state
+}
+
+fn any_quirks() -> impl Strategy<Value = Quirks> {
+    prop_oneof![
+        Just(Quirks::default()),
+        Just(Platform::Vip.quirks()),
+        Just(Platform::Schip.quirks()),
+        Just(Platform::XoChip.quirks()),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn add_registers(a: u8, b: u8) {
+        let machine_state = run(Chip8Builder::new().v(0, a).v(1, b), 0x8014);
+        prop_assert_eq!(machine_state.v(0), a.wrapping_add(b));
+        prop_assert_eq!(machine_state.v(0xF), (a as u16 + b as u16 > 0xFF) as u8);
+    }
+
+    #[test]
+    fn subtract(a: u8, b: u8) {
+        let machine_state = run(Chip8Builder::new().v(0, a).v(1, b), 0x8015);
+        prop_assert_eq!(machine_state.v(0), a.wrapping_sub(b));
+        prop_assert_eq!(machine_state.v(0xF), (a >= b) as u8);
+    }
+
+    #[test]
+    fn reverse_subtract(a: u8, b: u8) {
+        let machine_state = run(Chip8Builder::new().v(0, a).v(1, b), 0x8017);
+        prop_assert_eq!(machine_state.v(0), b.wrapping_sub(a));
+        prop_assert_eq!(machine_state.v(0xF), (b >= a) as u8);
+    }
+
+    #[test]
+    fn add_then_subtract_round_trips(a: u8, b: u8) {
+        let mut machine_state = run(Chip8Builder::new().v(0, a).v(1, b), 0x8014);
+        machine_state.run_opcode(0x8015);
+        prop_assert_eq!(machine_state.v(0), a);
+    }
+
+    #[test]
+    fn shifts_keep_the_lost_bit(a: u8) {
+        let right = run(Chip8Builder::new().v(1, a), 0x8016);
+        prop_assert_eq!(right.v(0) << 1 | right.v(0xF), a);
+        let left = run(Chip8Builder::new().v(1, a), 0x801E);
+        prop_assert_eq!(left.v(0) >> 1 | left.v(0xF) << 7, a);
+    }
+
+    #[test]
+    fn bcd_digits_recompose(a: u8, i in 0u16..0x1000) {
+        let machine_state = run(Chip8Builder::new().v(0, a).i(i), 0xF033);
+        let digit = |n: u16| machine_state.memory()[((i + n) & 0xFFF) as usize] as u16;
+        prop_assert!(digit(0) < 10 && digit(1) < 10 && digit(2) < 10);
+        prop_assert_eq!(digit(0) * 100 + digit(1) * 10 + digit(2), a as u16);
+    }
+
+    #[test]
+    fn store_then_load_round_trips(values: [u8; 16], x in 0usize..16, i in 0x200u16..0x1000) {
+        let mut machine_state = run(Chip8Builder::new().registers(&values).i(i), 0xF055 | (x as u16) << 8);
+        for register in 0..16 {
+            machine_state.set_v(register, 0);
+        }
+        machine_state.set_i(i);
+        machine_state.run_opcode(0xF065 | (x as u16) << 8);
+        for (register, &value) in values.iter().enumerate() {
+            prop_assert_eq!(machine_state.v(register), if register <= x { value } else { 0 });
+        }
+    }
+
+    #[test]
+    fn any_instruction_in_any_state_is_safe(
+        opcode: u16,
+        registers: [u8; 16],
+        i: u16,
+        pc in 0u16..0x1000,
+        quirks in any_quirks(),
+        key in 0usize..16,
+    ) {
+        let builder = Chip8Builder::new().quirks(quirks).registers(&registers).i(i).pc(pc).key(key);
+        let mut machine_state = run(builder, opcode);
+        machine_state.emulate_frame();
+        prop_assert!(machine_state.pc() <= 0x1003);
+    }
+}
+
+// A saved pc past the end of memory used to load, then overflow when a key wait finished
+#[test]
+fn load_state_rejects_pc_out_of_range() {
+    let mut machine_state = Chip8Builder::new().build();
+    machine_state.run_opcode(0xF00A);
+    let mut state = vec![0; STATE_SIZE];
+    machine_state.save_state(&mut state).unwrap();
+    // Magic, version and V0-VF come before pc
+    state[21..23].copy_from_slice(&0xFFFFu16.to_le_bytes());
+    let mut loaded = Chip8::new();
+    assert_eq!(loaded.load_state(&state), Err(StateError::Corrupt));
+    loaded.set_key(0, true);
+    loaded.set_key(0, false);
+    assert_eq!(loaded.pc(), 0x200);
+}
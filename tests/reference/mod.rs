@@ -0,0 +1,316 @@
+// A small CHIP-8 model written for clarity rather than speed, to check Chip8 against: if
+// the two disagree, the model is the one to believe. Shared by the differential tests,
+// which run random instruction streams on both, and the conformance tests, whose golden
+// screens are the model's output.
+#![allow(dead_code)]
+
+use chip8_rs::chip8::{Quirks, MEMORY_STACK_ADDRESS};
+use chip8_rs::font::Font;
+use chip8_rs::rng::{RandomSource, XorShift};
+
+pub const ROM_START: u16 = 0x200;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Halt {
+    None,
+    Overflow(u16),
+    Underflow(u16),
+}
+
+pub struct Reference {
+    pub quirks: Quirks,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub memory: [u8; 4096],
+    pub screen: [[u8; 32]; 64], // 1 for a lit pixel
+    pub stack: Vec<u16>,
+    pub delay: u8,
+    pub sound: u8,
+    pub keys: [bool; 16],
+    pub waiting_for: Option<usize>, // FX0A destination register
+    pub held: Option<u8>, // key pressed during FX0A, waiting for its release
+    pub halt: Halt,
+    pub rng: XorShift,
+}
+
+impl Reference {
+    pub fn new(quirks: Quirks, rom: &[u8], seed: u64) -> Reference {
+        let mut memory = [0; 4096];
+        memory[..80].copy_from_slice(Font::Standard.data());
+        memory[ROM_START as usize..ROM_START as usize + rom.len()].copy_from_slice(rom);
+        Reference {
+            quirks,
+            v: [0; 16],
+            i: 0,
+            pc: ROM_START,
+            memory,
+            screen: [[0; 32]; 64],
+            stack: Vec::new(),
+            delay: 0,
+            sound: 0,
+            keys: [false; 16],
+            waiting_for: None,
+            held: None,
+            halt: Halt::None,
+            rng: XorShift::new(seed),
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        self.memory[(address & 0xFFF) as usize]
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[(address & 0xFFF) as usize] = value;
+    }
+
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        let was_pressed = self.keys[key];
+        self.keys[key] = pressed;
+        if let Some(x) = self.waiting_for {
+            match self.held {
+                None if pressed && !was_pressed => {
+                    if self.quirks.key_wait_release {
+                        self.held = Some(key as u8);
+                    } else {
+                        self.finish_wait(x, key as u8);
+                    }
+                },
+                Some(held) if !pressed && was_pressed && held as usize == key => self.finish_wait(x, held),
+                _ => ()
+            }
+        }
+    }
+
+    fn finish_wait(&mut self, x: usize, key: u8) {
+        self.v[x] = key;
+        self.waiting_for = None;
+        self.held = None;
+        self.pc += 2;
+    }
+
+    // A one-instruction frame
+    pub fn step(&mut self) {
+        self.frame(1);
+    }
+
+    // Runs up to `cycles` instructions, then the timers tick. A key wait or a halt stops
+    // execution, and so does a sprite when Quirks::display_wait is set
+    pub fn frame(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            if self.waiting_for.is_some() || self.halt != Halt::None {
+                break;
+            }
+            self.pc &= 0xFFF;
+            let opcode = (self.read(self.pc) as u16) << 8 | self.read(self.pc + 1) as u16;
+            self.execute(opcode);
+            if opcode >> 12 == 0xD && self.quirks.display_wait {
+                break;
+            }
+        }
+        self.delay = self.delay.saturating_sub(1);
+        self.sound = self.sound.saturating_sub(1);
+    }
+
+    fn execute(&mut self, opcode: u16) {
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = (opcode & 0xF) as usize;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+        let (vx, vy) = (self.v[x], self.v[y]);
+        let next = self.pc + 2;
+        let skip = self.pc + 4;
+
+        self.pc = match (opcode >> 12, x, y, n) {
+            (0x0, 0x0, 0xE, 0x0) => {
+                self.screen = [[0; 32]; 64];
+                next
+            },
+            (0x0, 0x0, 0xE, 0xE) => self.ret(),
+            (0x0, _, _, _) => next,
+            (0x1, _, _, _) => nnn,
+            (0x2, _, _, _) => self.call(nnn),
+            (0x3, _, _, _) => if vx == nn { skip } else { next },
+            (0x4, _, _, _) => if vx != nn { skip } else { next },
+            (0x5, _, _, 0x0) => if vx == vy { skip } else { next },
+            (0x6, _, _, _) => {
+                self.v[x] = nn;
+                next
+            },
+            (0x7, _, _, _) => {
+                self.v[x] = vx.wrapping_add(nn);
+                next
+            },
+            (0x8, _, _, 0x0) => {
+                self.v[x] = vy;
+                next
+            },
+            (0x8, _, _, 0x1..=0x3) => {
+                self.v[x] = match n {
+                    1 => vx | vy,
+                    2 => vx & vy,
+                    _ => vx ^ vy
+                };
+                if self.quirks.vf_reset {
+                    self.v[0xF] = 0;
+                }
+                next
+            },
+            (0x8, _, _, 0x4) => self.arithmetic(x, vx.wrapping_add(vy), vx as u16 + vy as u16 > 0xFF),
+            (0x8, _, _, 0x5) => self.arithmetic(x, vx.wrapping_sub(vy), vx >= vy),
+            (0x8, _, _, 0x7) => self.arithmetic(x, vy.wrapping_sub(vx), vy >= vx),
+            (0x8, _, _, 0x6) => {
+                let source = if self.quirks.shift_uses_vy { vy } else { vx };
+                self.arithmetic(x, source >> 1, source & 1 == 1)
+            },
+            (0x8, _, _, 0xE) => {
+                let source = if self.quirks.shift_uses_vy { vy } else { vx };
+                self.arithmetic(x, source << 1, source & 0x80 != 0)
+            },
+            (0x9, _, _, 0x0) => if vx != vy { skip } else { next },
+            (0xA, _, _, _) => {
+                self.i = nnn;
+                next
+            },
+            (0xB, _, _, _) => {
+                let offset = if self.quirks.jump_uses_vx { vx } else { self.v[0] };
+                (nnn + offset as u16) & 0xFFF
+            },
+            (0xC, _, _, _) => {
+                self.v[x] = self.rng.next_byte() & nn;
+                next
+            },
+            (0xD, _, _, _) => {
+                self.sprite(vx as usize, vy as usize, n);
+                next
+            },
+            (0xE, _, 0x9, 0xE) => if self.keys[(vx & 0xF) as usize] { skip } else { next },
+            (0xE, _, 0xA, 0x1) => if !self.keys[(vx & 0xF) as usize] { skip } else { next },
+            (0xF, _, 0x0, 0x7) => {
+                self.v[x] = self.delay;
+                next
+            },
+            (0xF, _, 0x0, 0xA) => {
+                self.waiting_for = Some(x);
+                self.pc
+            },
+            (0xF, _, 0x1, 0x5) => {
+                self.delay = vx;
+                next
+            },
+            (0xF, _, 0x1, 0x8) => {
+                self.sound = vx;
+                next
+            },
+            (0xF, _, 0x1, 0xE) => {
+                self.i = self.i.wrapping_add(vx as u16);
+                next
+            },
+            (0xF, _, 0x2, 0x9) => {
+                self.i = (vx & 0xF) as u16 * 5;
+                next
+            },
+            (0xF, _, 0x3, 0x3) => {
+                self.write(self.i, vx / 100);
+                self.write(self.i + 1, vx / 10 % 10);
+                self.write(self.i + 2, vx % 10);
+                next
+            },
+            (0xF, _, 0x5, 0x5) => {
+                for r in 0..=x {
+                    self.write(self.i.wrapping_add(r as u16), self.v[r]);
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+                next
+            },
+            (0xF, _, 0x6, 0x5) => {
+                for r in 0..=x {
+                    self.v[r] = self.read(self.i.wrapping_add(r as u16));
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
+                next
+            },
+            _ => self.pc // undefined instructions stop execution where they are
+        };
+    }
+
+    // The flag is written after the result, so it wins when X is F
+    fn arithmetic(&mut self, x: usize, result: u8, flag: bool) -> u16 {
+        self.v[x] = result;
+        self.v[0xF] = flag as u8;
+        self.pc + 2
+    }
+
+    fn call(&mut self, nnn: u16) -> u16 {
+        if self.stack.len() >= self.quirks.stack_depth.min(16) {
+            if self.quirks.halt_on_stack_fault {
+                self.halt = Halt::Overflow(self.pc);
+                return self.pc;
+            }
+            return nnn;
+        }
+        if self.quirks.stack_in_memory {
+            let slot = MEMORY_STACK_ADDRESS as u16 + 2 * self.stack.len() as u16;
+            self.write(slot, (self.pc >> 8) as u8);
+            self.write(slot + 1, self.pc as u8);
+        }
+        self.stack.push(self.pc);
+        nnn
+    }
+
+    fn ret(&mut self) -> u16 {
+        match self.stack.pop() {
+            Some(_) if self.quirks.stack_in_memory => {
+                // The copy in memory is the real one, a ROM may have changed it
+                let slot = MEMORY_STACK_ADDRESS as u16 + 2 * self.stack.len() as u16;
+                let address = (self.read(slot) as u16) << 8 | self.read(slot + 1) as u16;
+                (address & 0xFFF) + 2
+            },
+            Some(address) => (address & 0xFFF) + 2,
+            None if self.quirks.halt_on_stack_fault => {
+                self.halt = Halt::Underflow(self.pc);
+                self.pc
+            },
+            None => self.pc + 2
+        }
+    }
+
+    fn sprite(&mut self, x: usize, y: usize, rows: usize) {
+        let mut collision = false;
+        for row in 0..rows {
+            let bits = self.read(self.i.wrapping_add(row as u16));
+            for column in 0..8 {
+                let (mut px, mut py) = (x % 64 + column, y % 32 + row);
+                if self.quirks.clip_sprites && (px >= 64 || py >= 32) {
+                    continue;
+                }
+                px %= 64;
+                py %= 32;
+                if bits & 0x80 >> column != 0 {
+                    collision |= self.screen[px][py] == 1;
+                    self.screen[px][py] ^= 1;
+                }
+            }
+        }
+        self.v[0xF] = collision as u8;
+    }
+
+    pub fn stack_contents(&self) -> Vec<u16> {
+        if self.quirks.stack_in_memory {
+            (0..self.stack.len())
+                .map(|level| {
+                    let slot = MEMORY_STACK_ADDRESS as u16 + 2 * level as u16;
+                    (self.read(slot) as u16) << 8 | self.read(slot + 1) as u16
+                })
+                .collect()
+        } else {
+            self.stack.clone()
+        }
+    }
+}
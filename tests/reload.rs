@@ -0,0 +1,111 @@
+// Watch mode: a program whose file changes is put back into the running machine, either
+// from scratch or on top of the registers the old version left behind
+mod common;
+
+use chip8_rs::chip8::{Chip8, Platform};
+use chip8_rs::watch::Reloader;
+use common::TempDir;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+// Writes the file with a modification time that can't collide with the previous one,
+// however coarse the filesystem's timestamps are
+fn save(path: &Path, data: &[u8], generation: u64) {
+    std::fs::write(path, data).unwrap();
+    let time = SystemTime::now() + Duration::from_secs(10 * generation);
+    std::fs::File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+}
+
+// Ten cycles a frame: the setup and four times round the counters' loop
+fn started(rom: &[u8]) -> Chip8 {
+    let mut machine_state = Chip8::new();
+    machine_state.set_cycles_per_frame(10);
+    machine_state.load_fonts();
+    machine_state.load_rom(rom);
+    machine_state.emulate_frame();
+    machine_state
+}
+
+// v0 := 7, v1 := 1, loop: v1 += 1, jump loop
+const COUNTER: [u8; 8] = [0x60, 0x07, 0x61, 0x01, 0x71, 0x01, 0x12, 0x04];
+// The same loop adding 2, then a 0xAB nobody reads
+const FASTER_COUNTER: [u8; 9] = [0x60, 0x07, 0x61, 0x01, 0x71, 0x02, 0x12, 0x04, 0xAB];
+
+#[test]
+fn reload_starts_over_by_default() {
+    let dir = TempDir::new("reload-reset");
+    let path = dir.join("game.ch8");
+    save(&path, &COUNTER, 0);
+    let mut reloader = Reloader::new(&path, false);
+    let mut machine_state = started(&COUNTER);
+    assert!(reloader.poll(&mut machine_state).is_none());
+
+    save(&path, &[0x60, 0x03, 0x12, 0x02], 1);
+    let program = reloader.poll(&mut machine_state).unwrap().unwrap();
+    assert_eq!(program.rom, [0x60, 0x03, 0x12, 0x02]);
+    assert_eq!((machine_state.pc(), machine_state.v(0), machine_state.v(1)), (0x200, 0, 0));
+    assert_eq!(machine_state.memory()[0x204..0x208], [0; 4]);
+    machine_state.emulate_frame();
+    assert_eq!(machine_state.v(0), 3);
+    assert_eq!(reloader.reloads(), 1);
+    assert!(reloader.poll(&mut machine_state).is_none());
+}
+
+#[test]
+fn reload_can_keep_registers() {
+    let dir = TempDir::new("reload-keep");
+    let path = dir.join("game.ch8");
+    save(&path, &COUNTER, 0);
+    let mut reloader = Reloader::new(&path, true);
+    let mut machine_state = started(&COUNTER);
+    let (pc, v1) = (machine_state.pc(), machine_state.v(1));
+
+    save(&path, &FASTER_COUNTER, 1);
+    reloader.poll(&mut machine_state).unwrap().unwrap();
+    assert_eq!((machine_state.pc(), machine_state.v(0), machine_state.v(1)), (pc, 7, v1));
+    assert_eq!(machine_state.memory()[0x205..0x209], [0x02, 0x12, 0x04, 0xAB]);
+    machine_state.emulate_frame();
+    assert_eq!(machine_state.v(1), v1.wrapping_add(10));
+
+    // A shorter version doesn't leave the tail of the longer one behind
+    save(&path, &COUNTER, 2);
+    reloader.poll(&mut machine_state).unwrap().unwrap();
+    assert_eq!(machine_state.memory()[0x208], 0);
+    assert_eq!(reloader.reloads(), 2);
+}
+
+#[test]
+fn failed_reload_keeps_the_old_program() {
+    let dir = TempDir::new("reload-fail");
+    let path = dir.join("game.8o");
+    save(&path, b": main v0 := 1 loop again", 0);
+    let mut reloader = Reloader::new(&path, false);
+    let mut machine_state = started(&[0x60, 0x01, 0x12, 0x02]);
+
+    save(&path, b": main v0 := nothing", 1);
+    let error = reloader.poll(&mut machine_state).unwrap().err().unwrap();
+    assert!(error.ends_with("1:14: undefined name nothing"), "{}", error);
+    assert_eq!((machine_state.pc(), machine_state.v(0)), (0x202, 1));
+    assert_eq!(reloader.reloads(), 0);
+}
+
+#[test]
+fn replace_rom_keeps_the_font_and_the_stack_in_memory() {
+    // Calls a subroutine that spins, so one return address is on the VIP's stack
+    let mut machine_state = Chip8::new();
+    machine_state.set_quirks(Platform::Vip.quirks());
+    machine_state.load_fonts();
+    machine_state.load_rom(&[0x22, 0x04, 0x00, 0x00, 0x12, 0x04]);
+    machine_state.emulate_frame();
+    let stack: Vec<u16> = machine_state.stack().collect();
+
+    // Returns to where the call left off, which now holds v0 := 9
+    machine_state.replace_rom(&[0x22, 0x04, 0x60, 0x09, 0x00, 0xEE, 0x12, 0x06]);
+    assert_eq!(machine_state.stack().collect::<Vec<u16>>(), stack);
+    assert_eq!(machine_state.memory()[..5], [0xF0, 0x90, 0x90, 0x90, 0xF0]);
+    machine_state.set_cycles_per_frame(1);
+    machine_state.emulate_frame();
+    machine_state.emulate_frame();
+    assert_eq!((machine_state.pc(), machine_state.v(0)), (0x204, 9));
+    assert!(machine_state.fault().is_none());
+}
@@ -0,0 +1,91 @@
+// How the frontends pick a program's settings: the command line, then a cartridge or the
+// ROM database, then detection, and again when a watched program is reloaded
+#![cfg(feature = "cartridge")]
+mod common;
+
+use chip8_rs::chip8::{Chip8, Platform, Quirks, CYCLES_PER_FRAME};
+use chip8_rs::detect::detect;
+use chip8_rs::font::{Font, DEFAULT_FONT_ADDRESS};
+use chip8_rs::settings::Loader;
+use chip8_rs::watch::Reloader;
+use common::TempDir;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+fn loader(platform: Option<Platform>, font: Option<Font>) -> Loader {
+    Loader::new(None, platform, font.map(|font| *font.data()), DEFAULT_FONT_ADDRESS).unwrap()
+}
+
+#[test]
+fn known_roms_get_the_database_settings() {
+    let (program, settings) = loader(None, None).load(Path::new("tests/roms/quirk-checks.ch8")).unwrap();
+    assert_eq!(program.rom, std::fs::read("tests/roms/quirk-checks.ch8").unwrap());
+    assert_eq!(settings.info.title, "Quirks test");
+    assert_eq!((settings.quirks, settings.origin.as_str()), (Platform::Vip.quirks(), "vip quirks from the ROM database"));
+    assert_eq!(settings.cycles_per_frame, 15);
+    assert_eq!(&settings.font, Font::Standard.data());
+}
+
+#[test]
+fn the_command_line_wins() {
+    let loader = loader(Some(Platform::Schip), Some(Font::Eti660));
+    let (_, settings) = loader.load(Path::new("tests/roms/quirk-checks.ch8")).unwrap();
+    assert_eq!((settings.quirks, settings.origin.as_str()), (Platform::Schip.quirks(), "schip quirks"));
+    assert_eq!(&settings.font, Font::Eti660.data());
+    // The rest still comes from the database
+    assert_eq!(settings.cycles_per_frame, 15);
+}
+
+#[test]
+fn unknown_roms_get_the_detected_platform() {
+    let dir = TempDir::new("settings-unknown");
+    let path = dir.join("game.ch8");
+    let rom = [0x00, 0xFF, 0x12, 0x02];
+    std::fs::write(&path, rom).unwrap();
+    let (_, settings) = loader(None, None).load(&path).unwrap();
+    assert!(settings.info.title.is_empty());
+    assert_eq!((settings.quirks, settings.origin), (detect(&rom).quirks(), detect(&rom).explain()));
+    assert_eq!(settings.cycles_per_frame, CYCLES_PER_FRAME);
+}
+
+#[test]
+fn cartridges_bring_their_own_settings() {
+    let (program, settings) = loader(None, None).load(Path::new("tests/cartridges/ibm-logo.gif")).unwrap();
+    assert_eq!(program.rom, std::fs::read("tests/roms/ibm-logo.ch8").unwrap());
+    assert_eq!(settings.info.title, "ibm-logo");
+    assert_eq!(settings.quirks, Quirks { vf_reset: true, clip_sprites: false, ..Quirks::default() });
+    assert_eq!(settings.origin, "default quirks from the cartridge");
+    assert_eq!((settings.cycles_per_frame, &settings.font), (20, Font::Vip.data()));
+}
+
+#[test]
+fn reloads_pick_the_settings_of_the_new_program() {
+    let dir = TempDir::new("settings-reload");
+    let path = dir.join("game.ch8");
+    std::fs::write(&path, [0x12, 0x00]).unwrap();
+    let loader = loader(None, None);
+    let (program, settings) = loader.load(&path).unwrap();
+    let mut machine_state = Chip8::new();
+    settings.apply(&mut machine_state);
+    machine_state.load_rom(&program.rom);
+    let mut reloader = Reloader::for_program(&path, &program, true, false).unwrap();
+    assert!(loader.reload(&mut reloader, &mut machine_state).is_none());
+
+    // Replaced by a ROM the database knows, with a later modification time
+    std::fs::write(&path, std::fs::read("tests/roms/quirk-checks.ch8").unwrap()).unwrap();
+    let later = SystemTime::now() + Duration::from_secs(10);
+    std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+    let (_, settings) = loader.reload(&mut reloader, &mut machine_state).unwrap().unwrap();
+    assert_eq!(settings.info.title, "Quirks test");
+    assert_eq!(machine_state.quirks(), Platform::Vip.quirks());
+    assert_eq!(machine_state.cycles_per_frame(), 15);
+}
+
+#[test]
+fn only_source_files_are_reloaded_without_watch() {
+    let loader = loader(None, None);
+    let (rom, _) = loader.load(Path::new("tests/roms/ibm-logo.ch8")).unwrap();
+    assert!(Reloader::for_program(Path::new("tests/roms/ibm-logo.ch8"), &rom, false, false).is_none());
+    let (source, _) = loader.load(Path::new("tests/roms/ibm-logo.8o")).unwrap();
+    assert!(Reloader::for_program(Path::new("tests/roms/ibm-logo.8o"), &source, false, false).is_some());
+}
@@ -0,0 +1,154 @@
+// Execution traces: written while the machine runs, read back in either format and
+// compared step by step
+mod common;
+
+use chip8_rs::chip8::Chip8;
+use chip8_rs::symbols::Symbols;
+use chip8_rs::trace::{diff, Filter, Format, Reader, Step, Trace};
+use common::TempDir;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+// v0 := 5, loop: v0 += 1, i := 0x300, delay := v0, jump loop
+const COUNTER: [u8; 10] = [0x60, 0x05, 0x70, 0x01, 0xA3, 0x00, 0xF0, 0x15, 0x12, 0x02];
+
+// Runs the ROM for a number of four-cycle frames with a trace going to path
+fn traced(rom: &[u8], frames: u32, path: &Path, format: Format, filter: Filter, configure: impl Fn(&mut Chip8)) {
+    let mut machine_state = Chip8::new();
+    machine_state.set_cycles_per_frame(4);
+    machine_state.load_fonts();
+    machine_state.load_rom(rom);
+    configure(&mut machine_state);
+    let trace = Trace::create(path, format, filter).unwrap();
+    assert!(machine_state.set_tracer(Some(Box::new(trace))).is_none());
+    for _ in 0..frames {
+        machine_state.emulate_frame();
+    }
+    machine_state.set_tracer(None).unwrap().finish().unwrap();
+}
+
+fn steps(path: &Path) -> Vec<Step> {
+    Reader::open(path).unwrap().map(Result::unwrap).collect()
+}
+
+#[test]
+fn text_traces_show_the_machine_before_each_instruction() {
+    let dir = TempDir::new("trace-text");
+    let path = dir.join("counter.txt");
+    traced(&COUNTER, 2, &path, Format::Text, Filter::default(), |_| {});
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 8);
+    assert_eq!(lines[0], "frame 0  0x200  6005  LD V0, 0x05           v 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  i 0x000  dt 00  st 00");
+    // The second frame starts with the delay timer set from v0 and ticked down once
+    assert_eq!(lines[4], "frame 1  0x208  1202  JP 0x202              v 06 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  i 0x300  dt 05  st 00");
+
+    let read = steps(&path);
+    assert_eq!(read.len(), 8);
+    assert_eq!(read[5], Step { frame: 1, pc: 0x202, opcode: 0x7001, v: [6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0], i: 0x300, delay_timer: 5, sound_timer: 0 });
+}
+
+#[test]
+fn text_traces_show_labels() {
+    let dir = TempDir::new("trace-labels");
+    let path = dir.join("counter.txt");
+    let labels = BTreeMap::from([("main".to_string(), 0x200), ("loop".to_string(), 0x202)]);
+    let mut machine_state = Chip8::new();
+    machine_state.load_rom(&COUNTER);
+    machine_state.set_tracer(Some(Box::new(Trace::create(&path, Format::Text, Filter::default()).unwrap())));
+    machine_state.tracer_mut().unwrap().set_symbols(&Symbols::new(&labels));
+    for _ in 0..3 {
+        machine_state.emulate_cycle();
+    }
+    machine_state.set_tracer(None).unwrap().finish().unwrap();
+    let text = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines[0], "frame 0  0x200  6005  <main>  LD V0, 0x05           v 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00  i 0x000  dt 00  st 00");
+    assert!(lines[2].starts_with("frame 0  0x204  A300  <loop+2>  LD I, 0x300"), "{}", lines[2]);
+    // and read back like any other
+    assert_eq!(steps(&path).iter().map(|step| step.pc).collect::<Vec<_>>(), [0x200, 0x202, 0x204]);
+}
+
+#[test]
+fn binary_traces_hold_the_same_steps() {
+    let dir = TempDir::new("trace-binary");
+    let (text, binary) = (dir.join("counter.txt"), dir.join("counter.trace"));
+    traced(&COUNTER, 50, &text, Format::Text, Filter::default(), |_| {});
+    traced(&COUNTER, 50, &binary, Format::Binary, Filter::default(), |_| {});
+    assert_eq!(Reader::open(&binary).unwrap().format(), Format::Binary);
+    assert_eq!(steps(&binary), steps(&text));
+    // A 5-byte header and 28 bytes a step
+    assert_eq!(std::fs::metadata(&binary).unwrap().len(), 5 + 200 * 28);
+
+    let bytes = std::fs::read(&binary).unwrap();
+    let cut = dir.join("cut.trace");
+    std::fs::write(&cut, &bytes[..bytes.len() - 3]).unwrap();
+    let last = Reader::open(&cut).unwrap().last().unwrap();
+    assert_eq!(last.err().unwrap(), "record 200 is cut short");
+}
+
+#[test]
+fn filters_pick_addresses_classes_and_frames() {
+    let dir = TempDir::new("trace-filter");
+    let path = dir.join("filtered.txt");
+    let filter = Filter {
+        addresses: Filter::parse_addresses("0x202-0x207").unwrap(),
+        frames: Filter::parse_frames("2-3").unwrap(),
+        classes: Filter::parse_classes("7,f").unwrap()
+    };
+    traced(&COUNTER, 10, &path, Format::Text, filter, |_| {});
+    let kept: Vec<(u32, u16)> = steps(&path).iter().map(|step| (step.frame, step.pc)).collect();
+    assert_eq!(kept, [(2, 0x202), (2, 0x206), (3, 0x202), (3, 0x206)]);
+
+    assert_eq!(Filter::parse_addresses("0x300").unwrap(), (0x300, 0x300));
+    assert_eq!(Filter::parse_frames("100-").unwrap(), (100, u32::MAX));
+    assert_eq!(Filter::parse_classes("8D").unwrap(), 1 << 8 | 1 << 0xD);
+    assert!(Filter::parse_addresses("0x300-0x200").is_err());
+    assert!(Filter::parse_addresses("0x200-0x1000").is_err());
+    assert!(Filter::parse_frames("ten").is_err());
+    assert!(Filter::parse_classes("G").is_err());
+}
+
+#[test]
+fn the_decode_cache_is_bypassed_while_tracing() {
+    let dir = TempDir::new("trace-cache");
+    let (plain, cached) = (dir.join("plain.trace"), dir.join("cached.trace"));
+    traced(&COUNTER, 5, &plain, Format::Binary, Filter::default(), |_| {});
+    traced(&COUNTER, 5, &cached, Format::Binary, Filter::default(), |machine_state| machine_state.set_decode_cache(true));
+    assert_eq!(steps(&cached).len(), 20);
+    assert_eq!(diff(Reader::open(&plain).unwrap(), Reader::open(&cached).unwrap()), Ok(None));
+}
+
+#[test]
+fn diff_finds_the_first_divergence() {
+    let dir = TempDir::new("trace-diff");
+    let (a, b, short) = (dir.join("a.txt"), dir.join("b.trace"), dir.join("short.txt"));
+    traced(&COUNTER, 3, &a, Format::Text, Filter::default(), |_| {});
+    // The same program with v0 starting at 6 instead, traced in the other format
+    let mut changed = COUNTER;
+    changed[1] = 0x06;
+    traced(&changed, 3, &b, Format::Binary, Filter::default(), |_| {});
+    let divergence = diff(Reader::open(&a).unwrap(), Reader::open(&b).unwrap()).unwrap().unwrap();
+    assert_eq!(divergence.index, 0);
+    assert_eq!(divergence.a.unwrap().differences(&divergence.b.unwrap()), ["opcode 6005 vs 6006"]);
+
+    changed = COUNTER;
+    changed[3] = 0x02;
+    traced(&changed, 3, &b, Format::Binary, Filter::default(), |_| {});
+    let divergence = diff(Reader::open(&a).unwrap(), Reader::open(&b).unwrap()).unwrap().unwrap();
+    assert_eq!(divergence.index, 1);
+    let report = divergence.to_string();
+    assert!(report.starts_with("traces diverge at step 1: opcode 7001 vs 7002\n< frame 0  0x202  7001"), "{}", report);
+    // The registers only go different ways one step later
+    let divergence = diff(Reader::open(&a).unwrap().filter(|step| step.as_ref().unwrap().pc != 0x202),
+        Reader::open(&b).unwrap().filter(|step| step.as_ref().unwrap().pc != 0x202)).unwrap().unwrap();
+    assert_eq!(divergence.a.unwrap().differences(&divergence.b.unwrap()), ["v0 06 vs 07"]);
+
+    traced(&COUNTER, 2, &short, Format::Text, Filter::default(), |_| {});
+    let divergence = diff(Reader::open(&a).unwrap(), Reader::open(&short).unwrap()).unwrap().unwrap();
+    assert_eq!((divergence.index, divergence.b), (8, None));
+    assert!(divergence.to_string().starts_with("the second trace ends after 8 steps"));
+
+    std::fs::write(&short, "frame 0 nonsense\n").unwrap();
+    assert_eq!(diff(Reader::open(&a).unwrap(), Reader::open(&short).unwrap()).err().unwrap(), "second trace: line 1: not a trace step");
+}
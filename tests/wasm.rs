@@ -0,0 +1,69 @@
+// Builds the wasm module and drives its exported API through the wasmi interpreter,
+// the same way a web page would through WebAssembly.instantiate
+mod common;
+
+use std::path::Path;
+use wasmi::{Engine, Instance, Linker, Memory, Module, Store};
+
+struct Harness {
+    store: Store<()>,
+    instance: Instance,
+    memory: Memory,
+}
+
+impl Harness {
+    fn new(path: &Path) -> Harness {
+        let engine = Engine::default();
+        let module = Module::new(&engine, &std::fs::read(path).unwrap()[..]).unwrap();
+        let mut store = Store::new(&engine, ());
+        let linker = <Linker<()>>::new(&engine);
+        let instance = linker.instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+        let memory = instance.get_memory(&store, "memory").unwrap();
+        Harness { store, instance, memory }
+    }
+
+    fn call<P: wasmi::WasmParams, R: wasmi::WasmResults>(&mut self, name: &str, params: P) -> R {
+        let func = self.instance.get_typed_func::<P, R>(&self.store, name).unwrap();
+        func.call(&mut self.store, params).unwrap()
+    }
+
+    fn load_rom(&mut self, rom: &[u8]) -> bool {
+        let buffer: i32 = self.call("chip8_rom_buffer", ());
+        self.memory.write(&mut self.store, buffer as usize, rom).unwrap();
+        self.call::<i32, i32>("chip8_load_rom", rom.len() as i32) != 0
+    }
+
+    fn framebuffer(&mut self) -> Vec<u8> {
+        let ptr: i32 = self.call("chip8_framebuffer", ());
+        let width: i32 = self.call("chip8_framebuffer_width", ());
+        let height: i32 = self.call("chip8_framebuffer_height", ());
+        let mut pixels = vec![0; (width * height) as usize];
+        self.memory.read(&self.store, ptr as usize, &mut pixels).unwrap();
+        pixels
+    }
+}
+
+#[test]
+#[ignore = "needs the wasm32-unknown-unknown target"]
+fn wasm_api_runs_a_rom() {
+    let path = common::build_for_target("wasm32-unknown-unknown", "cdylib", "wasm").join("chip8_rs.wasm");
+    let mut harness = Harness::new(&path);
+
+    // Draw the "0" glyph at the top left, start the sound timer, then spin
+    let rom = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x61, 0x0A, 0xF1, 0x18, 0x12, 0x0A];
+    assert!(harness.load_rom(&rom));
+    assert!(!harness.load_rom(&[0; 4096]));
+    assert!(harness.load_rom(&rom));
+
+    let changed: i32 = harness.call("chip8_run_frame", ());
+    assert_eq!(changed, 1);
+    let pixels = harness.framebuffer();
+    let glyph: Vec<&[u8]> = (0..5).map(|row| &pixels[row * 64..row * 64 + 4]).collect();
+    assert_eq!(glyph, vec![&[1, 1, 1, 1][..], &[1, 0, 0, 1], &[1, 0, 0, 1], &[1, 0, 0, 1], &[1, 1, 1, 1]]);
+    assert_eq!(pixels.iter().filter(|&&p| p != 0).count(), 14);
+
+    let sound: i32 = harness.call("chip8_sound_playing", ());
+    assert_eq!(sound, 1);
+    let changed: i32 = harness.call("chip8_run_frame", ());
+    assert_eq!(changed, 0);
+}